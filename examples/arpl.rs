@@ -32,11 +32,7 @@ fn plucked_track(song: &Song) -> Vec<Frame> {
             song,
             // Function to use to generate the audio
             &mut |note, length| {
-                Plucked(InitialBurstType::Triangle(2, 3)).generate(
-                    length,
-                    note.into(),
-                    song.sample_rate(),
-                )
+                Plucked(InitialBurstType::Triangle(2, 3)).generate_for(song, length, note.into())
             },
             Plucked::default_adsr(song.sample_rate()),
         )
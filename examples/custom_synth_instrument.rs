@@ -31,7 +31,7 @@ fn track(song: &Song) -> Vec<Frame> {
     }
     .generate(
         song,
-        &mut |note, length| Sine.generate(length, note.into(), song.sample_rate()),
+        &mut |note, length| Sine.generate_for(song, length, note.into()),
         Sine::default_adsr(song.sample_rate()),
     )
 }
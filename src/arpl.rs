@@ -61,20 +61,14 @@ impl SoundPatternGenerator {
         for (i, step) in self.0.take(LEN).enumerate() {
             pattern[i] = step % 2 == 0;
         }
-        SoundPattern {
-            sound: self.1,
-            pattern,
-        }
+        SoundPattern::new(pattern, self.1)
     }
     pub fn with<const LEN: usize>(self, f: &dyn Fn(u64) -> bool) -> SoundPattern<LEN> {
         let mut pattern = [false; LEN];
         for (i, step) in self.0.take(LEN).enumerate() {
             pattern[i] = f(step);
         }
-        SoundPattern {
-            sound: self.1,
-            pattern,
-        }
+        SoundPattern::new(pattern, self.1)
     }
 }
 
@@ -0,0 +1,200 @@
+//! Aux buses, for effects shared between tracks
+//!
+//! Tracks send part of their signal to a bus by name, the bus runs its effects once
+//! over the sum of all sends, and the result gets mixed into the master
+
+use crate::effects::{Automation, Effect, EffectBundle};
+
+pub struct Bus {
+    pub(crate) name: String,
+    pub(crate) effects: EffectBundle,
+    /// Multiplier applied to the output of the bus
+    pub(crate) gain: Automation<f64>,
+}
+
+impl Bus {
+    pub fn new(name: impl Into<String>, effects: EffectBundle) -> Self {
+        Self {
+            name: name.into(),
+            effects,
+            gain: Automation::Const(1.),
+        }
+    }
+
+    pub fn gain(mut self, gain: Automation<f64>) -> Self {
+        self.gain = gain;
+        self
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Buses can be streamed if all of their effects can
+    pub(crate) fn is_streamable(&self) -> bool {
+        self.effects.processor().is_some()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::effects::*;
+    use crate::error::Error;
+    use crate::frame::Frame;
+    use crate::song::*;
+    use crate::track::{constant, Track};
+
+    fn half_bus() -> Bus {
+        Bus::new(
+            "half",
+            EffectBundle(vec![Box::new(Volume {
+                mult: Automation::Const(0.5),
+            })]),
+        )
+    }
+
+    #[test]
+    fn sends_get_mixed_into_the_master() {
+        let config = SongConfig {
+            buses: vec![half_bus()],
+            ..Default::default()
+        };
+        let mut song = Song::new(
+            vec![Track::new(constant).send("half", Automation::Const(0.5))],
+            config,
+        );
+        song.generate(44_100);
+
+        // 0.5 from the track, and 0.5 * 0.5 * 0.5 from the bus
+        assert_eq!(Frame::mono(0.625), song.generated().unwrap()[0]);
+    }
+
+    #[test]
+    fn pre_fader_sends_ignore_track_gain() {
+        let config = SongConfig {
+            buses: vec![half_bus()],
+            ..Default::default()
+        };
+        let mut song = Song::new(
+            vec![
+                Track::new(constant)
+                    .gain(Automation::Const(0.))
+                    .pre_fader_send("half", Automation::Const(1.)),
+                Track::new(constant)
+                    .gain(Automation::Const(0.))
+                    .send("half", Automation::Const(1.)),
+            ],
+            config,
+        );
+        song.generate(44_100);
+
+        assert_eq!(Frame::mono(0.25), song.generated().unwrap()[0]);
+    }
+
+    #[test]
+    fn buses_run_their_effects_on_the_sends() {
+        let reverb = || {
+            EffectBundle(vec![Box::new(Convolution::new(vec![
+                Frame::mono(0.5),
+                Frame::mono(0.25),
+            ]))])
+        };
+        let delay = || {
+            EffectBundle(vec![Box::new(Delay {
+                delay_time: 10,
+                feedback: Automation::Const(0.5),
+            })])
+        };
+        assert!(!Bus::new("reverb", reverb()).is_streamable());
+        assert!(Bus::new("delay", delay()).is_streamable());
+
+        for effects in &[reverb as fn() -> EffectBundle, delay] {
+            let config = SongConfig {
+                buses: vec![Bus::new("bus", effects())],
+                master: EffectBundle(vec![]),
+                ..Default::default()
+            };
+            let mut song = Song::new(
+                vec![Track::new(constant)
+                    .gain(Automation::Const(0.))
+                    .pre_fader_send("bus", Automation::Const(1.))],
+                config,
+            );
+            song.generate(44_100);
+
+            // Streamed buses also keep the tail of the delay, which is checked separately
+            let expected = effects().run(constant(&song));
+            assert_eq!(expected[..], song.generated().unwrap()[..expected.len()]);
+        }
+    }
+
+    #[test]
+    fn sending_to_a_missing_bus_is_an_error() {
+        let mut song = Song::new(
+            vec![Track::new(constant)
+                .name("lead")
+                .send("missing", Automation::Const(1.))],
+            SongConfig::default(),
+        );
+
+        match song.try_generate(44_100) {
+            Err(Error::Track { name, source }) => {
+                assert_eq!("lead", name);
+                assert!(matches!(*source, Error::BusNotFound(bus) if bus == "missing"));
+            }
+            other => panic!("Expected a missing bus error, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn buses_with_the_same_name_are_an_error() {
+        let config = SongConfig {
+            buses: vec![half_bus(), half_bus()],
+            ..Default::default()
+        };
+        let mut song = Song::new(
+            vec![Track::new(constant).send("half", Automation::Const(1.))],
+            config,
+        );
+
+        assert!(matches!(
+            song.try_generate(44_100),
+            Err(Error::DuplicateBus(bus)) if bus == "half"
+        ));
+    }
+
+    #[test]
+    fn streamed_buses_keep_their_tails() {
+        let config = SongConfig {
+            buses: vec![Bus::new(
+                "delay",
+                EffectBundle(vec![Box::new(Delay {
+                    delay_time: 1_000,
+                    feedback: Automation::Const(0.5),
+                })]),
+            )],
+            master: EffectBundle(vec![]),
+            ..Default::default()
+        };
+        let mut song = Song::new(
+            vec![Track::new(constant)
+                .gain(Automation::Const(0.))
+                .pre_fader_send("delay", Automation::Const(1.))],
+            config,
+        );
+        song.generate(44_100);
+        let generated = song.generated().unwrap();
+
+        // The echoes go on after the track ends, until they fade out
+        assert!(generated.len() > 10_000);
+        let mut input = constant(&song);
+        input.resize(generated.len(), Frame::default());
+        let expected = Delay {
+            delay_time: 1_000,
+            feedback: Automation::Const(0.5),
+        }
+        .run(input);
+        assert_eq!(expected, generated);
+    }
+}
@@ -13,4 +13,10 @@ impl Effect for Balance {
             .map(|(idx, val)| val.balance(self.balance.value(idx)))
             .collect()
     }
+
+    fn processor(&self) -> Option<Box<dyn BlockProcessor + '_>> {
+        Some(Box::new(FrameProcessor::new(move |idx, val: Frame| {
+            val.balance(self.balance.value(idx))
+        })))
+    }
 }
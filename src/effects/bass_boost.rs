@@ -14,31 +14,31 @@ pub struct BassBoost {
 }
 impl Effect for BassBoost {
     fn run(&self, input: Vec<Frame>) -> Vec<Frame> {
+        run_with_processor(self, input)
+    }
+
+    fn processor(&self) -> Option<Box<dyn BlockProcessor + '_>> {
         let mut cap_left: f64 = 0.;
         let mut cap_right: f64 = 0.;
 
-        input
-            .iter()
-            .enumerate()
-            .map(|(idx, frame)| {
-                let selectivity = self.selectivity.value(idx);
-                let bass_ratio = self.bass_ratio.value(idx);
-                let input_ratio = self.input_ratio.value(idx);
-                let gain = self.gain.value(idx);
+        Some(Box::new(FrameProcessor::new(move |idx, frame: Frame| {
+            let selectivity = self.selectivity.value(idx);
+            let bass_ratio = self.bass_ratio.value(idx);
+            let input_ratio = self.input_ratio.value(idx);
+            let gain = self.gain.value(idx);
 
-                frame.map_left_right(
-                    |val: f64| {
-                        let gain2 = 1.0 / (selectivity + 1.0);
-                        cap_left = cap_left.mul_add(selectivity, val) * gain2;
-                        (val.mul_add(input_ratio, cap_left * bass_ratio) * gain).clamp(-1., 1.)
-                    },
-                    |val: f64| {
-                        let gain2 = 1.0 / (selectivity + 1.0);
-                        cap_right = cap_right.mul_add(selectivity, val) * gain2;
-                        (val.mul_add(input_ratio, cap_right * bass_ratio) * gain).clamp(-1., 1.)
-                    },
-                )
-            })
-            .collect()
+            frame.map_left_right(
+                |val: f64| {
+                    let gain2 = 1.0 / (selectivity + 1.0);
+                    cap_left = cap_left.mul_add(selectivity, val) * gain2;
+                    (val.mul_add(input_ratio, cap_left * bass_ratio) * gain).clamp(-1., 1.)
+                },
+                |val: f64| {
+                    let gain2 = 1.0 / (selectivity + 1.0);
+                    cap_right = cap_right.mul_add(selectivity, val) * gain2;
+                    (val.mul_add(input_ratio, cap_right * bass_ratio) * gain).clamp(-1., 1.)
+                },
+            )
+        })))
     }
 }
@@ -14,55 +14,31 @@ pub enum BitCrusherMode {
 
 impl Effect for BitCrusher {
     fn run(&self, input: Vec<Frame>) -> Vec<Frame> {
-        match self.mode {
-            BitCrusherMode::Round => input
-                .iter()
-                .enumerate()
-                .map(|(idx, val)| {
-                    let mult = self.multiplier.value(idx);
-
-                    let left = (((val.left + 1.) * mult).round() / mult) - 1.;
-                    let right = (((val.right + 1.) * mult).round() / mult) - 1.;
-
-                    Frame::new(left, right)
-                })
-                .collect(),
-            BitCrusherMode::Ceiling => input
-                .iter()
-                .enumerate()
-                .map(|(idx, val)| {
-                    let mult = self.multiplier.value(idx);
-
-                    let left = (((val.left + 1.) * mult).ceil() / mult) - 1.;
-                    let right = (((val.right + 1.) * mult).ceil() / mult) - 1.;
-
-                    Frame::new(left, right)
-                })
-                .collect(),
-            BitCrusherMode::Floor => input
-                .iter()
-                .enumerate()
-                .map(|(idx, val)| {
-                    let mult = self.multiplier.value(idx);
+        input
+            .iter()
+            .enumerate()
+            .map(|(idx, val)| self.crush(idx, *val))
+            .collect()
+    }
 
-                    let left = (((val.left + 1.) * mult).floor() / mult) - 1.;
-                    let right = (((val.right + 1.) * mult).floor() / mult) - 1.;
+    fn processor(&self) -> Option<Box<dyn BlockProcessor + '_>> {
+        Some(Box::new(FrameProcessor::new(move |idx, val| {
+            self.crush(idx, val)
+        })))
+    }
+}
 
-                    Frame::new(left, right)
-                })
-                .collect(),
-            BitCrusherMode::Truncate => input
-                .iter()
-                .enumerate()
-                .map(|(idx, val)| {
-                    let mult = self.multiplier.value(idx);
+impl BitCrusher {
+    fn crush(&self, idx: usize, val: Frame) -> Frame {
+        let mult = self.multiplier.value(idx);
 
-                    let left = (((val.left + 1.) * mult).trunc() / mult) - 1.;
-                    let right = (((val.right + 1.) * mult).trunc() / mult) - 1.;
+        let round: fn(f64) -> f64 = match self.mode {
+            BitCrusherMode::Round => f64::round,
+            BitCrusherMode::Ceiling => f64::ceil,
+            BitCrusherMode::Floor => f64::floor,
+            BitCrusherMode::Truncate => f64::trunc,
+        };
 
-                    Frame::new(left, right)
-                })
-                .collect(),
-        }
+        val.map(|sample| (round((sample + 1.) * mult) / mult) - 1.)
     }
 }
@@ -9,30 +9,36 @@ pub struct Compressor {
 }
 impl Effect for Compressor {
     fn run(&self, input: Vec<Frame>) -> Vec<Frame> {
-        let (left, right) = input.split_sides();
+        input
+            .iter()
+            .enumerate()
+            .map(|(idx, frame)| self.compress(idx, *frame))
+            .collect()
+    }
 
-        join_left_and_right_channels(run(self, left), run(self, right))
+    fn processor(&self) -> Option<Box<dyn BlockProcessor + '_>> {
+        Some(Box::new(FrameProcessor::new(move |idx, frame| {
+            self.compress(idx, frame)
+        })))
     }
 }
 
-fn run(comp: &Compressor, input: Vec<f64>) -> Vec<f64> {
-    input
-        .iter()
-        .enumerate()
-        .map(|(idx, val)| {
-            let threshold = comp.threshold.value(idx).abs();
-            let gain = comp.gain.value(idx).abs();
-            let ratio = comp.ratio.value(idx).abs();
+impl Compressor {
+    fn compress(&self, idx: usize, frame: Frame) -> Frame {
+        let threshold = self.threshold.value(idx).abs();
+        let gain = self.gain.value(idx).abs();
+        let ratio = self.ratio.value(idx).abs();
 
+        frame.map(|val| {
             let absval = val.abs();
 
             let res = if absval > threshold {
                 let extra = absval - threshold;
                 val.signum() * (threshold + (extra / ratio))
             } else {
-                *val
+                val
             };
             res * gain
         })
-        .collect()
+    }
 }
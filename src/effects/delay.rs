@@ -7,27 +7,46 @@ pub struct Delay {
 }
 impl Effect for Delay {
     fn run(&self, input: Vec<Frame>) -> Vec<Frame> {
+        run_with_processor(self, input)
+    }
+
+    fn processor(&self) -> Option<Box<dyn BlockProcessor + '_>> {
         let mut delay_left = DelayLine::new(self.delay_time);
         let mut delay_right = DelayLine::new(self.delay_time);
 
-        input
-            .iter()
-            .enumerate()
-            .map(|(idx, frame)| {
-                let feedback = self.feedback.value(idx);
-                frame.map_left_right(
-                    |sample| {
-                        let ret = sample + delay_left.read();
-                        delay_left.write_and_advance(ret * feedback);
-                        ret
-                    },
-                    |sample| {
-                        let ret = sample + delay_right.read();
-                        delay_right.write_and_advance(ret * feedback);
-                        ret
-                    },
-                )
-            })
-            .collect()
+        let processor = FrameProcessor::new(move |idx, frame: Frame| {
+            let feedback = self.feedback.value(idx);
+            frame.map_left_right(
+                |sample| {
+                    let ret = sample + delay_left.read();
+                    delay_left.write_and_advance(ret * feedback);
+                    ret
+                },
+                |sample| {
+                    let ret = sample + delay_right.read();
+                    delay_right.write_and_advance(ret * feedback);
+                    ret
+                },
+            )
+        });
+        Some(Box::new(DelayProcessor {
+            processor,
+            tail: self.delay_time,
+        }))
+    }
+}
+
+/// Processor of a `Delay`, which can go silent for up to `delay_time` before the next echo
+struct DelayProcessor<P> {
+    processor: P,
+    tail: usize,
+}
+impl<P: BlockProcessor> BlockProcessor for DelayProcessor<P> {
+    fn process(&mut self, block: &mut [Frame]) {
+        self.processor.process(block);
+    }
+
+    fn tail(&self) -> usize {
+        self.tail
     }
 }
@@ -32,40 +32,50 @@ pub enum FilterMode {
 
 impl Effect for Filter {
     fn run(&self, input: Vec<Frame>) -> Vec<Frame> {
-        let (left, right) = input.split_sides();
+        run_with_processor(self, input)
+    }
+
+    fn processor(&self) -> Option<Box<dyn BlockProcessor + '_>> {
+        let mut left = FilterState::default();
+        let mut right = FilterState::default();
 
-        join_left_and_right_channels(run(self, left), run(self, right))
+        Some(Box::new(FrameProcessor::new(move |idx, frame: Frame| {
+            frame.map_left_right(
+                |val| left.tick(self, idx, val),
+                |val| right.tick(self, idx, val),
+            )
+        })))
     }
 }
 
-fn run(filter: &Filter, input: Vec<f64>) -> Vec<f64> {
-    let mut ic1eq: f64 = 0.;
-    let mut ic2eq: f64 = 0.;
+/// State of the filter for a single channel
+#[derive(Default)]
+struct FilterState {
+    ic1eq: f64,
+    ic2eq: f64,
+}
 
-    input
-        .iter()
-        .enumerate()
-        .map(|(idx, val)| {
-            let g = (PI * (filter.cutoff.value(idx) / filter.sample_rate as f64)).tan();
-            let k = 2.0 - (1.9 * filter.resonance.value(idx).clamp(0., 1.));
+impl FilterState {
+    fn tick(&mut self, filter: &Filter, idx: usize, val: f64) -> f64 {
+        let g = (PI * (filter.cutoff.value(idx) / filter.sample_rate as f64)).tan();
+        let k = 2.0 - (1.9 * filter.resonance.value(idx).clamp(0., 1.));
 
-            let a1 = 1.0 / g.mul_add(g + k, 1.0);
-            let a2 = g * a1;
-            let a3 = g * a2;
+        let a1 = 1.0 / g.mul_add(g + k, 1.0);
+        let a2 = g * a1;
+        let a3 = g * a2;
 
-            let v3 = val - ic2eq;
-            let v1 = ic1eq.mul_add(a1, v3 * a2);
-            let v2 = v3.mul_add(a3, ic1eq.mul_add(a2, ic2eq));
+        let v3 = val - self.ic2eq;
+        let v1 = self.ic1eq.mul_add(a1, v3 * a2);
+        let v2 = v3.mul_add(a3, self.ic1eq.mul_add(a2, self.ic2eq));
 
-            ic1eq = (v1 * 2.0) - ic1eq;
-            ic2eq = (v2 * 2.0) - ic2eq;
+        self.ic1eq = (v1 * 2.0) - self.ic1eq;
+        self.ic2eq = (v2 * 2.0) - self.ic2eq;
 
-            match filter.mode {
-                FilterMode::LowPass => v2,
-                FilterMode::BandPass => v1,
-                FilterMode::HighPass => val - v1 * k - v2,
-                FilterMode::Notch => val - v1 * k,
-            }
-        })
-        .collect()
+        match filter.mode {
+            FilterMode::LowPass => v2,
+            FilterMode::BandPass => v1,
+            FilterMode::HighPass => val - v1 * k - v2,
+            FilterMode::Notch => val - v1 * k,
+        }
+    }
 }
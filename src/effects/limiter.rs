@@ -0,0 +1,227 @@
+use super::*;
+use std::collections::VecDeque;
+
+/// Brickwall limiter that looks ahead, so the output never goes over `ceiling`
+///
+/// Peaks are estimated between samples too (true peak), by interpolating the signal.
+/// Both channels get the same gain, so the stereo image doesn't move
+pub struct Limiter {
+    /// Highest amplitude of the output, between 0 and 1
+    pub ceiling: f64,
+    /// Seconds the limiter looks ahead. It adds one more sample of latency,
+    /// because the peaks between samples are only known after the next sample
+    pub lookahead: f64,
+    /// Seconds it takes the gain to recover most of the way after a peak
+    pub release: f64,
+    /// Sample rate used by `run` and `processor`, songs use their own
+    pub sample_rate: u32,
+}
+impl Default for Limiter {
+    /// -1 dBTP ceiling, with 1.5ms of lookahead and 50ms of release
+    fn default() -> Self {
+        Self {
+            ceiling: 10_f64.powf(-1. / 20.),
+            lookahead: 0.0015,
+            release: 0.05,
+            sample_rate: 44_100,
+        }
+    }
+}
+impl Limiter {
+    /// Converts a time in seconds to a number of samples, which is at least 1
+    fn samples(seconds: f64, sample_rate: u32) -> usize {
+        ((seconds * sample_rate as f64).round() as usize).max(1)
+    }
+}
+
+impl Effect for Limiter {
+    fn run(&self, input: Vec<Frame>) -> Vec<Frame> {
+        self.run_at(input, self.sample_rate)
+    }
+
+    fn processor(&self) -> Option<Box<dyn BlockProcessor + '_>> {
+        self.processor_at(self.sample_rate)
+    }
+
+    /// Unlike the processor, the output is aligned with the input
+    fn run_at(&self, mut input: Vec<Frame>, sample_rate: u32) -> Vec<Frame> {
+        let mut processor = self
+            .processor_at(sample_rate)
+            .expect("Limiter should have a processor");
+        let latency = processor.latency();
+        let len = input.len();
+        input.resize(len + latency, Frame::default());
+
+        processor.process(&mut input);
+        input.drain(..latency);
+        input
+    }
+
+    fn processor_at(&self, sample_rate: u32) -> Option<Box<dyn BlockProcessor + '_>> {
+        let lookahead = Self::samples(self.lookahead, sample_rate);
+        let release = Self::samples(self.release, sample_rate);
+        Some(Box::new(LimiterProcessor {
+            ceiling: self.ceiling,
+            lookahead,
+            release: (-1. / release as f64).exp(),
+
+            history: [Frame::default(); 3],
+            delay: vec![Frame::default(); lookahead + 1].into(),
+            minimums: VecDeque::new(),
+            gains: vec![1.; lookahead].into(),
+            gain: 1.,
+            idx: 0,
+        }))
+    }
+}
+
+struct LimiterProcessor {
+    ceiling: f64,
+    lookahead: usize,
+    /// Multiplier of the distance to 1 on each sample while releasing
+    release: f64,
+
+    /// Last three input frames, to estimate the peaks between samples
+    history: [Frame; 3],
+    /// Input frames waiting to be output
+    delay: VecDeque<Frame>,
+    /// Candidates for the minimum gain of the lookahead window and the sample before it,
+    /// with the index of their sample
+    minimums: VecDeque<(usize, f64)>,
+    /// Last gains after release, which get averaged so the gain changes smoothly
+    gains: VecDeque<f64>,
+    /// Gain after release
+    gain: f64,
+    idx: usize,
+}
+
+impl LimiterProcessor {
+    /// Returns the highest absolute value of the frame, and between the previous frame and this one
+    fn true_peak(&mut self, frame: Frame) -> f64 {
+        let [a, b, c] = self.history;
+        self.history = [b, c, frame];
+
+        let mut peak = frame.left.abs().max(frame.right.abs());
+        for &t in &[0.25, 0.5, 0.75] {
+            let left = catmull_rom(a.left, b.left, c.left, frame.left, t);
+            let right = catmull_rom(a.right, b.right, c.right, frame.right, t);
+            peak = peak.max(left.abs()).max(right.abs());
+        }
+        peak.max(c.left.abs()).max(c.right.abs())
+    }
+}
+
+impl BlockProcessor for LimiterProcessor {
+    fn process(&mut self, block: &mut [Frame]) {
+        for frame in block.iter_mut() {
+            let peak = self.true_peak(*frame);
+            let needed = if peak > self.ceiling {
+                self.ceiling / peak
+            } else {
+                1.
+            };
+
+            // Minimum of the gains needed from `idx - lookahead - 1` to `idx`. Peaks between samples
+            // are found one sample late, so the window goes one further back to cover both of their frames
+            while matches!(self.minimums.back(), Some(&(_, gain)) if gain >= needed) {
+                self.minimums.pop_back();
+            }
+            self.minimums.push_back((self.idx, needed));
+            while matches!(self.minimums.front(), Some(&(idx, _)) if idx + self.lookahead + 1 < self.idx)
+            {
+                self.minimums.pop_front();
+            }
+            let minimum = self.minimums.front().unwrap().1;
+
+            self.gain = minimum.min((self.gain - 1.).mul_add(self.release, 1.));
+            self.gains.pop_front();
+            self.gains.push_back(self.gain);
+            let gain = self.gains.iter().sum::<f64>() / self.lookahead as f64;
+
+            self.delay.push_back(*frame);
+            *frame = self.delay.pop_front().unwrap() * gain.min(1.);
+            self.idx += 1;
+        }
+    }
+
+    fn latency(&self) -> usize {
+        self.lookahead + 1
+    }
+}
+
+/// Interpolates between `b` and `c`, with `t` going from 0 to 1
+fn catmull_rom(a: f64, b: f64, c: f64, d: f64, t: f64) -> f64 {
+    let c1 = c - a;
+    let c2 = a.mul_add(2., c.mul_add(4., b.mul_add(-5., -d)));
+    let c3 = (b - c).mul_add(3., d - a);
+    0.5 * c3.mul_add(t, c2).mul_add(t, c1).mul_add(t, 2. * b)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn quiet_signals_are_not_changed() {
+        let input: Vec<Frame> = (0..1000)
+            .map(|i| Frame::mono((i as f64 * 0.01).sin() * 0.5))
+            .collect();
+
+        assert_eq!(input, Limiter::default().run(input.clone()));
+    }
+
+    #[test]
+    fn peaks_stay_under_the_ceiling() {
+        let limiter = Limiter::default();
+        let mut input: Vec<Frame> = (0..5000)
+            .map(|i| Frame::mono((i as f64 * 0.05).sin() * 0.5))
+            .collect();
+        input[2000] = Frame::mono(3.);
+        input[2001] = Frame::mono(-2.);
+
+        let output = limiter.run(input.clone());
+        assert_eq!(input.len(), output.len());
+        assert!(output
+            .iter()
+            .all(|frame| frame.left.abs() <= limiter.ceiling + 1e-12));
+
+        // The gain recovers after the peak
+        assert!((output[4900].left - input[4900].left).abs() < 0.01);
+    }
+
+    #[test]
+    fn peaks_between_samples_stay_under_the_ceiling() {
+        let limiter = Limiter::default();
+        let mut input = vec![Frame::default(); 1000];
+        input[500] = Frame::mono(0.88);
+        input[501] = Frame::mono(0.88);
+
+        // The peak is between the two loud samples, so both of them need the full reduction
+        let output = limiter.run(input);
+        let peak = output
+            .windows(4)
+            .flat_map(|w| {
+                [0.25, 0.5, 0.75]
+                    .iter()
+                    .map(move |&t| catmull_rom(w[0].left, w[1].left, w[2].left, w[3].left, t).abs())
+            })
+            .fold(0., f64::max);
+        assert!(peak <= limiter.ceiling + 1e-9, "{}", peak);
+    }
+
+    #[test]
+    fn times_follow_the_sample_rate() {
+        let limiter = Limiter::default();
+        assert_eq!(67, limiter.processor().unwrap().latency());
+        assert_eq!(73, limiter.processor_at(48_000).unwrap().latency());
+
+        // The gain takes the same time to recover at any sample rate
+        let recovered = |sample_rate: u32| {
+            let mut input = vec![Frame::mono(0.5); sample_rate as usize];
+            input[100] = Frame::mono(2.);
+            let output = limiter.run_at(input, sample_rate);
+            output.iter().rposition(|frame| frame.left < 0.49).unwrap() as f64 / sample_rate as f64
+        };
+        assert!((recovered(44_100) - recovered(96_000)).abs() < 0.005);
+    }
+}
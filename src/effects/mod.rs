@@ -18,15 +18,51 @@ impl EffectExtension for Vec<Frame> {
 
 pub trait Effect {
     fn run(&self, input: Vec<Frame>) -> Vec<Frame>;
+
+    /// Returns a processor that applies the effect one block at a time
+    ///
+    /// Effects that need the whole signal at once (like `Convolution`) can't be streamed,
+    /// so by default this returns None
+    fn processor(&self) -> Option<Box<dyn BlockProcessor + '_>> {
+        None
+    }
+
+    /// Same as `run`, at the sample rate of the song the effect is used in
+    ///
+    /// Effects with settings in seconds, like `Limiter`, use it to convert them to samples.
+    /// By default the sample rate is ignored
+    fn run_at(&self, input: Vec<Frame>, _sample_rate: u32) -> Vec<Frame> {
+        self.run(input)
+    }
+
+    /// Same as `processor`, at the sample rate of the song the effect is used in
+    fn processor_at(&self, _sample_rate: u32) -> Option<Box<dyn BlockProcessor + '_>> {
+        self.processor()
+    }
 }
 
-impl Effect for Box<dyn Effect> {
+impl<E: Effect + ?Sized> Effect for Box<E> {
     fn run(&self, input: Vec<Frame>) -> Vec<Frame> {
         (**self).run(input)
     }
+
+    fn processor(&self) -> Option<Box<dyn BlockProcessor + '_>> {
+        (**self).processor()
+    }
+
+    fn run_at(&self, input: Vec<Frame>, sample_rate: u32) -> Vec<Frame> {
+        (**self).run_at(input, sample_rate)
+    }
+
+    fn processor_at(&self, sample_rate: u32) -> Option<Box<dyn BlockProcessor + '_>> {
+        (**self).processor_at(sample_rate)
+    }
 }
 
-pub struct EffectBundle(pub Vec<Box<dyn Effect>>);
+/// List of effects that get applied one after the other
+///
+/// Effects need to be `Send` and `Sync` so bundles can be used as track inserts
+pub struct EffectBundle(pub Vec<Box<dyn Effect + Send + Sync>>);
 impl Effect for EffectBundle {
     fn run(&self, mut input: Vec<Frame>) -> Vec<Frame> {
         for effect in &self.0 {
@@ -34,6 +70,100 @@ impl Effect for EffectBundle {
         }
         input
     }
+
+    /// Only returns a processor if all of the effects in the bundle have one
+    fn processor(&self) -> Option<Box<dyn BlockProcessor + '_>> {
+        let processors = self
+            .0
+            .iter()
+            .map(|effect| effect.processor())
+            .collect::<Option<Vec<_>>>()?;
+        Some(Box::new(ChainProcessor(processors)))
+    }
+
+    fn run_at(&self, mut input: Vec<Frame>, sample_rate: u32) -> Vec<Frame> {
+        for effect in &self.0 {
+            input = effect.run_at(input, sample_rate);
+        }
+        input
+    }
+
+    fn processor_at(&self, sample_rate: u32) -> Option<Box<dyn BlockProcessor + '_>> {
+        let processors = self
+            .0
+            .iter()
+            .map(|effect| effect.processor_at(sample_rate))
+            .collect::<Option<Vec<_>>>()?;
+        Some(Box::new(ChainProcessor(processors)))
+    }
+}
+
+/// Applies an effect to consecutive blocks of a signal, keeping its state between blocks
+pub trait BlockProcessor {
+    fn process(&mut self, block: &mut [Frame]);
+
+    /// Number of samples the output is delayed by, like the lookahead of a `Limiter`
+    fn latency(&self) -> usize {
+        0
+    }
+
+    /// Number of samples the output can stay silent while the effect still has something to play,
+    /// like the time of a `Delay`
+    ///
+    /// Streamed buses keep running their effects after their sends stop, until the output has
+    /// been silent for this long
+    fn tail(&self) -> usize {
+        0
+    }
+}
+
+/// Processor that maps each frame individually
+/// The closure receives the index of the frame since the processor was created
+pub struct FrameProcessor<F> {
+    fun: F,
+    idx: usize,
+}
+impl<F: FnMut(usize, Frame) -> Frame> FrameProcessor<F> {
+    pub const fn new(fun: F) -> Self {
+        Self { fun, idx: 0 }
+    }
+}
+impl<F: FnMut(usize, Frame) -> Frame> BlockProcessor for FrameProcessor<F> {
+    fn process(&mut self, block: &mut [Frame]) {
+        for frame in block.iter_mut() {
+            *frame = (self.fun)(self.idx, *frame);
+            self.idx += 1;
+        }
+    }
+}
+
+/// Runs a list of processors one after the other
+struct ChainProcessor<'a>(Vec<Box<dyn BlockProcessor + 'a>>);
+impl BlockProcessor for ChainProcessor<'_> {
+    fn process(&mut self, block: &mut [Frame]) {
+        for processor in &mut self.0 {
+            processor.process(block);
+        }
+    }
+
+    fn latency(&self) -> usize {
+        self.0.iter().map(|processor| processor.latency()).sum()
+    }
+
+    fn tail(&self) -> usize {
+        self.0.iter().map(|processor| processor.tail()).sum()
+    }
+}
+
+/// Runs the effect's processor over the whole input as a single block
+///
+/// Used by stateful effects, so the state is only handled in one place
+fn run_with_processor(effect: &impl Effect, mut input: Vec<Frame>) -> Vec<Frame> {
+    effect
+        .processor()
+        .expect("Effect should have a processor")
+        .process(&mut input);
+    input
 }
 
 #[derive(Clone)]
@@ -45,7 +175,7 @@ pub enum Automation<T: Default + Clone + Debug> {
     /// Will return each value of the vec in order, looping back to the beginning once it's done
     Loop(Vec<T>),
     /// Generator method
-    Generator(Arc<dyn Fn(usize) -> T>),
+    Generator(Arc<dyn Fn(usize) -> T + Send + Sync>),
 }
 impl<T: Default + Clone + Debug> Debug for Automation<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -73,7 +203,7 @@ impl<T: Default + Clone + Debug> Automation<T> {
         }
     }
 
-    pub fn generator(fun: &'static dyn Fn(usize) -> T) -> Self {
+    pub fn generator(fun: &'static (dyn Fn(usize) -> T + Sync)) -> Self {
         Self::Generator(Arc::new(fun))
     }
 }
@@ -133,10 +263,13 @@ mod ring_modulator;
 pub use ring_modulator::RingModulator;
 mod compressor;
 pub use compressor::Compressor;
+mod limiter;
+pub use limiter::Limiter;
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::signals::waves;
 
     #[test]
     fn effect_bundle_works() {
@@ -156,6 +289,69 @@ mod test {
         assert_eq!(4, res.len());
     }
 
+    /// Runs the effect over `input` in blocks of `block_size`
+    fn run_in_blocks(effect: &impl Effect, mut input: Vec<Frame>, block_size: usize) -> Vec<Frame> {
+        let mut processor = effect.processor().unwrap();
+        for block in input.chunks_mut(block_size) {
+            processor.process(block);
+        }
+        input
+    }
+
+    #[test]
+    fn processors_match_run() {
+        let input = waves::sine(1000, Automation::Const(440.), 44_100).as_frames();
+
+        let volume = Volume {
+            mult: Automation::Loop(vec![0.1, 0.5, 1.]),
+        };
+        assert_eq!(
+            volume.run(input.clone()),
+            run_in_blocks(&volume, input.clone(), 64)
+        );
+
+        let delay = Delay {
+            delay_time: 100,
+            feedback: Automation::Const(0.5),
+        };
+        assert_eq!(
+            delay.run(input.clone()),
+            run_in_blocks(&delay, input.clone(), 33)
+        );
+
+        let filter = Filter {
+            mode: FilterMode::LowPass,
+            sample_rate: 44_100,
+            cutoff: Automation::Const(200.),
+            resonance: Automation::Const(0.3),
+        };
+        assert_eq!(
+            filter.run(input.clone()),
+            run_in_blocks(&filter, input, 100)
+        );
+    }
+
+    #[test]
+    fn bundle_has_processor_only_if_all_effects_have_one() {
+        let bundle = EffectBundle(vec![
+            Box::new(Volume {
+                mult: Automation::Const(0.5),
+            }),
+            Box::new(Balance {
+                balance: Automation::Const(0.5),
+            }),
+        ]);
+        assert!(bundle.processor().is_some());
+
+        let bundle = EffectBundle(vec![
+            Box::new(Volume {
+                mult: Automation::Const(0.5),
+            }),
+            Box::new(Convolution::new(vec![Frame::mono(1.)])),
+        ]);
+        assert!(bundle.processor().is_none());
+    }
+
     #[test]
     fn const_automation() {
         let a = Automation::Const(4.);
@@ -12,4 +12,10 @@ impl Effect for RingModulator {
             .map(|(idx, val)| val * self.carrier.value(idx))
             .collect()
     }
+
+    fn processor(&self) -> Option<Box<dyn BlockProcessor + '_>> {
+        Some(Box::new(FrameProcessor::new(move |idx, val: Frame| {
+            val * self.carrier.value(idx)
+        })))
+    }
 }
@@ -11,4 +11,10 @@ impl Effect for Volume {
             .map(|(idx, val)| val * self.mult.value(idx))
             .collect()
     }
+
+    fn processor(&self) -> Option<Box<dyn BlockProcessor + '_>> {
+        Some(Box::new(FrameProcessor::new(move |idx, val: Frame| {
+            val * self.mult.value(idx)
+        })))
+    }
 }
@@ -0,0 +1,195 @@
+//! Errors that can happen while generating or saving a song
+
+use std::fmt;
+use std::path::PathBuf;
+use vst::host::PluginLoadError;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// A wav file could not be opened, read or written
+    Wav { path: String, source: hound::Error },
+    /// A flac file could not be opened or decoded
+    Flac { path: String, source: claxon::Error },
+    /// A compressed file (like Ogg Vorbis or mp3) could not be decoded
+    Decode {
+        path: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    /// The manifest of a `SampleLibrary` could not be parsed, or it doesn't match the files
+    Manifest {
+        path: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    /// The files in the folder of a `SampleLibrary` don't make a valid library
+    SampleLibrary { path: String, message: String },
+    /// An SFZ file could not be parsed
+    Sfz {
+        path: String,
+        line: usize,
+        message: String,
+    },
+    /// A SoundFont file could not be parsed, or it doesn't have the requested preset
+    SoundFont { path: String, message: String },
+    /// The format of an audio file couldn't be recognized from its content or extension
+    UnknownFormat(String),
+    /// The channels of a file don't fit the `Downmix` it was opened with
+    Downmix { path: String, channels: usize },
+    /// The format of an exported file doesn't support this number of bits per sample
+    BitsPerSample { path: String, bits_per_sample: u16 },
+    /// Some other I/O operation on a file or folder failed
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+    /// The requested part of a sound is out of the bounds of the file
+    SoundRange {
+        path: String,
+        begin: usize,
+        end: usize,
+        len: usize,
+    },
+    /// A VST plugin could not be loaded or instantiated
+    Plugin {
+        path: PathBuf,
+        source: PluginLoadError,
+    },
+    /// There is no section with this name in the `SongConfig`
+    SectionNotFound(String),
+    /// A track sends to a bus that isn't in the `SongConfig`
+    BusNotFound(String),
+    /// There is more than one bus with this name in the `SongConfig`
+    DuplicateBus(String),
+    /// A track has `Track::cache` but no name, which is what identifies it in the cache
+    UnnamedCachedTrack,
+    /// A method that needs the sample rate was called before the song started generating
+    SampleRateNotSet,
+    /// A track failed while generating
+    Track { name: String, source: Box<Self> },
+    /// Custom error returned by a fallible track generator
+    Custom(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Wav { path, source } => write!(f, "Wav file {}: {}", path, source),
+            Self::Flac { path, source } => write!(f, "Flac file {}: {}", path, source),
+            Self::Decode { path, source } => write!(f, "Failed to decode {}: {}", path, source),
+            Self::Manifest { path, source } => write!(f, "Manifest {}: {}", path, source),
+            Self::Sfz {
+                path,
+                line,
+                message,
+            } => write!(f, "SFZ file {}, line {}: {}", path, line, message),
+            Self::SampleLibrary { path, message } => {
+                write!(f, "Sample library {}: {}", path, message)
+            }
+            Self::SoundFont { path, message } => write!(f, "SoundFont {}: {}", path, message),
+            Self::UnknownFormat(path) => write!(f, "Unknown audio format in {}", path),
+            Self::Downmix { path, channels } => write!(
+                f,
+                "File {} has {} channels, which don't fit the downmix",
+                path, channels
+            ),
+            Self::BitsPerSample {
+                path,
+                bits_per_sample,
+            } => write!(
+                f,
+                "File {} can't have {} bits per sample",
+                path, bits_per_sample
+            ),
+            Self::Io { path, source } => write!(f, "File {}: {}", path, source),
+            Self::SoundRange {
+                path,
+                begin,
+                end,
+                len,
+            } => write!(
+                f,
+                "Sound {} has {} frames, but {}..{} was requested",
+                path, len, begin, end
+            ),
+            Self::Plugin { path, source } => {
+                write!(f, "Failed to load plugin {}: {}", path.display(), source)
+            }
+            Self::SectionNotFound(name) => write!(f, "There is no section called {}", name),
+            Self::BusNotFound(name) => write!(f, "There is no bus called {}", name),
+            Self::DuplicateBus(name) => write!(f, "There is more than one bus called {}", name),
+            Self::UnnamedCachedTrack => write!(f, "Cached tracks need a name"),
+            Self::SampleRateNotSet => write!(f, "Sample rate has not been set"),
+            Self::Track { name, source } => write!(f, "Track {} failed: {}", name, source),
+            Self::Custom(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Wav { source, .. } => Some(source),
+            Self::Flac { source, .. } => Some(source),
+            Self::Decode { source, .. } => Some(source.as_ref()),
+            Self::Manifest { source, .. } => Some(source.as_ref()),
+            Self::Io { source, .. } => Some(source),
+            Self::Plugin { source, .. } => Some(source),
+            Self::Track { source, .. } => Some(source.as_ref()),
+            Self::UnknownFormat(_)
+            | Self::SampleLibrary { .. }
+            | Self::Sfz { .. }
+            | Self::SoundFont { .. }
+            | Self::Downmix { .. }
+            | Self::BitsPerSample { .. }
+            | Self::SoundRange { .. }
+            | Self::SectionNotFound(_)
+            | Self::BusNotFound(_)
+            | Self::DuplicateBus(_)
+            | Self::UnnamedCachedTrack
+            | Self::SampleRateNotSet
+            | Self::Custom(_) => None,
+        }
+    }
+}
+
+impl Error {
+    pub(crate) fn wav(path: &str) -> impl FnOnce(hound::Error) -> Self + '_ {
+        move |source| Self::Wav {
+            path: path.to_string(),
+            source,
+        }
+    }
+
+    pub(crate) fn flac(path: &str) -> impl FnOnce(claxon::Error) -> Self + '_ {
+        move |source| Self::Flac {
+            path: path.to_string(),
+            source,
+        }
+    }
+
+    pub(crate) fn decode<E: std::error::Error + Send + Sync + 'static>(
+        path: &str,
+    ) -> impl FnOnce(E) -> Self + '_ {
+        move |source| Self::Decode {
+            path: path.to_string(),
+            source: Box::new(source),
+        }
+    }
+
+    pub(crate) fn manifest<E: Into<Box<dyn std::error::Error + Send + Sync>>>(
+        path: &str,
+    ) -> impl FnOnce(E) -> Self + '_ {
+        move |source| Self::Manifest {
+            path: path.to_string(),
+            source: source.into(),
+        }
+    }
+
+    pub(crate) fn io(path: &str) -> impl FnOnce(std::io::Error) -> Self + '_ {
+        move |source| Self::Io {
+            path: path.to_string(),
+            source,
+        }
+    }
+}
@@ -0,0 +1,50 @@
+//! Hashes that are kept on disk, so they need to be the same on every run
+
+use std::hash::{Hash, Hasher};
+
+/// `Hasher` that uses blake3, as the one in the standard library can change between versions of Rust
+pub struct StableHasher(blake3::Hasher);
+impl StableHasher {
+    pub fn new() -> Self {
+        Self(blake3::Hasher::new())
+    }
+
+    /// Returns the hash as 32 hex characters, to be used in file names
+    pub fn to_hex(&self) -> String {
+        self.0.finalize().to_hex()[..32].to_string()
+    }
+}
+impl Hasher for StableHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        let hash = self.0.finalize();
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&hash.as_bytes()[..8]);
+        u64::from_le_bytes(bytes)
+    }
+}
+
+/// Returns the hash of `value` as 32 hex characters
+pub fn stable_hash(value: &impl Hash) -> String {
+    let mut hasher = StableHasher::new();
+    value.hash(&mut hasher);
+    hasher.to_hex()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hashes_dont_change() {
+        assert_eq!(stable_hash(&1_u64), stable_hash(&1_u64));
+        assert_ne!(stable_hash(&1_u64), stable_hash(&2_u64));
+        assert_eq!(
+            blake3::hash(&1_u64.to_ne_bytes()).to_hex()[..32],
+            stable_hash(&1_u64)
+        );
+    }
+}
@@ -25,7 +25,7 @@ macro_rules! note_option {
 macro_rules! vec_into {
     ( $( $x:expr ),* $(,)?) => {
         {
-            let mut temp_vec: Vec<TrackGenerator> = Vec::new();
+            let mut temp_vec: Vec<Track> = Vec::new();
             $(
                 temp_vec.push($x.into());
             )*
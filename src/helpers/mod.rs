@@ -2,6 +2,7 @@
 
 pub mod delay_line;
 mod extensions;
+pub(crate) mod hashing;
 #[macro_use]
 mod macros;
 pub mod pitch_detection;
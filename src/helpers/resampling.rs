@@ -3,6 +3,30 @@ use crate::helpers::*;
 
 use dasp::interpolate::linear::Linear;
 use dasp::{signal, Signal};
+use std::f64::consts::PI;
+
+/// Zero crossings on each side of the sinc used by `ResampleQuality::Sinc`
+const SINC_ZEROS: usize = 16;
+
+/// How samples are interpolated when resampling
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+pub enum ResampleQuality {
+    /// Linear interpolation, which is fast but lets some aliasing through
+    #[default]
+    Linear,
+    /// Windowed sinc interpolation, which is slower but keeps the high frequencies clean
+    Sinc,
+}
+
+impl ResampleQuality {
+    /// Short name used in the names of cached files
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Linear => "linear",
+            Self::Sinc => "sinc",
+        }
+    }
+}
 
 /// Passing factor = 2 will make the audio length be twice as long
 pub fn stretch(frames: Vec<f64>, factor: f64) -> Vec<f64> {
@@ -49,6 +73,50 @@ pub fn resample_frames(
     join_left_and_right_channels(left, right)
 }
 
+/// Resamples the frames with the interpolation of `quality`
+pub fn resample_frames_with(
+    frames: Vec<Frame>,
+    old_sample_rate: u32,
+    new_sample_rate: u32,
+    quality: ResampleQuality,
+) -> Vec<Frame> {
+    match quality {
+        ResampleQuality::Linear => resample_frames(frames, old_sample_rate, new_sample_rate),
+        ResampleQuality::Sinc => sinc_resample(&frames, old_sample_rate, new_sample_rate),
+    }
+}
+
+/// Hann windowed sinc, which is 0 from `-half` to `half`
+fn windowed_sinc(x: f64, half: f64) -> f64 {
+    if x == 0. {
+        1.
+    } else if x.abs() >= half {
+        0.
+    } else {
+        0.5 * (1. + (PI * x / half).cos()) * (PI * x).sin() / (PI * x)
+    }
+}
+
+/// Band limited interpolation, with the cutoff lowered when downsampling to avoid aliasing
+fn sinc_resample(frames: &[Frame], old_sample_rate: u32, new_sample_rate: u32) -> Vec<Frame> {
+    let factor = new_sample_rate as f64 / old_sample_rate as f64;
+    let cutoff = factor.min(1.);
+    let half = SINC_ZEROS as f64 / cutoff;
+    let len = (frames.len() as f64 * factor) as usize;
+
+    (0..len)
+        .map(|idx| {
+            let t = idx as f64 / factor;
+            let first = (t - half).ceil().max(0.) as usize;
+            let last = ((t + half).floor() as usize).min(frames.len() - 1);
+            (first..=last).fold(Frame::default(), |sum, k| {
+                let x = (k as f64 - t) * cutoff;
+                sum + frames[k] * (cutoff * windowed_sinc(x, SINC_ZEROS as f64))
+            })
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -61,6 +129,41 @@ mod test {
         assert_eq!(vec![0., 0.5, 1., 1., 1., 0.75, 0.5, 0.25, 0., 0.], result);
     }
 
+    #[test]
+    fn sinc_keeps_sines_and_removes_aliasing() {
+        let sine = |freq: f64, sample_rate: u32, len: usize| -> Vec<Frame> {
+            (0..len)
+                .map(|i| Frame::mono((2. * PI * freq * i as f64 / sample_rate as f64).sin()))
+                .collect()
+        };
+
+        // A 1kHz sine is the same at any sample rate, away from the edges
+        let result = resample_frames_with(
+            sine(1_000., 22_050, 2_205),
+            22_050,
+            44_100,
+            ResampleQuality::Sinc,
+        );
+        assert_eq!(4_410, result.len());
+        let expected = sine(1_000., 44_100, 4_410);
+        for (a, b) in result[100..4_300].iter().zip(&expected[100..4_300]) {
+            assert!((a.left - b.left).abs() < 0.01);
+        }
+
+        // 20kHz is above 11.025kHz, the Nyquist frequency at 22,050Hz, so it gets removed
+        let result = resample_frames_with(
+            sine(20_000., 44_100, 4_410),
+            44_100,
+            22_050,
+            ResampleQuality::Sinc,
+        );
+        let peak = result[100..2_100]
+            .iter()
+            .map(|frame| frame.left.abs())
+            .fold(0., f64::max);
+        assert!(peak < 0.05, "{}", peak);
+    }
+
     #[test]
     fn can_stretch_vector() {
         let smth = vec![0., 1., 1., 0.5, 0.];
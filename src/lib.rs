@@ -62,21 +62,33 @@ mod test_readme {
 pub mod helpers;
 
 pub mod arpl;
+pub mod bus;
 pub mod debug;
 pub mod effects;
+pub mod error;
 pub mod frame;
 pub mod fundsp;
+pub mod loudness;
 pub mod music_theory;
 pub mod player;
+pub mod random;
 pub mod record;
+pub mod region;
+pub mod render;
 pub mod select;
 pub mod signals;
 pub mod song;
 pub mod sound_files;
 pub mod synth;
+pub mod tempo;
+pub mod time_signature;
+pub mod timeline;
+pub mod track;
 pub mod trigger;
 pub mod vst;
 
+pub use error::{Error, Result};
+
 // Prelude
 
 pub mod prelude {
@@ -85,15 +97,27 @@ pub mod prelude {
     pub use std::collections::HashMap;
 
     pub use super::arpl::*;
+    pub use super::bus::*;
     pub use super::effects::*;
+    pub use super::error::Error;
     pub use super::frame::*;
     pub use super::fundsp::*;
     pub use super::helpers::*;
+    pub use super::loudness::*;
     pub use super::music_theory::{chords::*, intervals::*, n_tet::*, notes::*, scales::*};
+    pub use super::region::*;
+    pub use super::render::*;
     pub use super::signals::{adsr::Adsr, *};
     pub use super::song::*;
-    pub use super::sound_files::{enums::*, io::*, pattern::*, Sound};
+    pub use super::sound_files::{
+        cache::*, downmix::*, enums::*, export::*, io::*, library::*, onsets::*, pattern::*,
+        sf2::*, sfz::*, Sound, SoundPosition,
+    };
     pub use super::synth::*;
+    pub use super::tempo::*;
+    pub use super::time_signature::*;
+    pub use super::timeline::*;
+    pub use super::track::*;
     pub use super::trigger::*;
     pub use super::vst::*;
 }
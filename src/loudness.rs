@@ -0,0 +1,333 @@
+//! Loudness measurement following ITU-R BS.1770 and EBU R128
+//!
+//! The audio is K-weighted, and its power is measured over windows of 400ms (momentary)
+//! and 3s (short-term) that start every 100ms. Loudness is in LUFS, and peaks in dBTP
+
+use crate::frame::Frame;
+use std::f64::consts::PI;
+
+/// Length of the steps between windows, in seconds
+const HOP_SECONDS: f64 = 0.1;
+/// Number of steps in a momentary window
+const MOMENTARY_HOPS: usize = 4;
+/// Number of steps in a short-term window
+const SHORT_TERM_HOPS: usize = 30;
+
+/// Windows quieter than this (in LUFS) are ignored
+const ABSOLUTE_GATE: f64 = -70.;
+/// Windows this many LU quieter than the rest are ignored for the integrated loudness
+const RELATIVE_GATE: f64 = -10.;
+/// Windows this many LU quieter than the rest are ignored for the loudness range
+const RANGE_RELATIVE_GATE: f64 = -20.;
+
+/// Times the audio gets oversampled to find the true peak
+const OVERSAMPLING: usize = 4;
+/// Length of the interpolation filter for each oversampled position
+const TAPS: usize = 12;
+
+/// Loudness of some audio, see `Loudness::measure`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Loudness {
+    /// Loudness of the whole audio, in LUFS
+    pub integrated: f64,
+    /// Loudness of every 400ms window, starting every 100ms
+    pub momentary: Vec<f64>,
+    /// Loudness of every 3s window, starting every 100ms
+    pub short_term: Vec<f64>,
+    /// Variation of the short-term loudness, in LU
+    pub range: f64,
+    /// Highest peak, including the ones between samples, in dBTP
+    pub true_peak: f64,
+}
+
+impl Loudness {
+    /// Measures the loudness of `audio`
+    ///
+    /// Audio shorter than a window has no loudness, so it's negative infinity
+    pub fn measure(audio: &[Frame], sample_rate: u32) -> Self {
+        let hops = hop_powers(&k_weighted(audio, sample_rate), sample_rate);
+        let momentary = window_powers(&hops, MOMENTARY_HOPS);
+        let short_term = window_powers(&hops, SHORT_TERM_HOPS);
+
+        Self {
+            integrated: to_lufs(mean(&gate(&momentary, RELATIVE_GATE))),
+            momentary: momentary.iter().map(|&power| to_lufs(power)).collect(),
+            short_term: short_term.iter().map(|&power| to_lufs(power)).collect(),
+            range: loudness_range(&short_term),
+            true_peak: 20. * true_peak(audio).log10(),
+        }
+    }
+
+    /// Loudest momentary window, in LUFS
+    pub fn max_momentary(&self) -> f64 {
+        self.momentary
+            .iter()
+            .copied()
+            .fold(f64::NEG_INFINITY, f64::max)
+    }
+
+    /// Loudest short-term window, in LUFS
+    pub fn max_short_term(&self) -> f64 {
+        self.short_term
+            .iter()
+            .copied()
+            .fold(f64::NEG_INFINITY, f64::max)
+    }
+}
+
+/// Multiplies the audio so its integrated loudness is `target` LUFS
+///
+/// Silent audio is returned unchanged. Peaks aren't limited, so they can go over 1 if the audio gets louder
+pub fn normalize_loudness(audio: Vec<Frame>, sample_rate: u32, target: f64) -> Vec<Frame> {
+    let gain = loudness_gain(&audio, sample_rate, target);
+    audio.into_iter().map(|frame| frame * gain).collect()
+}
+
+/// Gain that brings the integrated loudness of the audio to `target` LUFS, or 1 if it's silent
+pub fn loudness_gain(audio: &[Frame], sample_rate: u32, target: f64) -> f64 {
+    let integrated = Loudness::measure(audio, sample_rate).integrated;
+    if integrated.is_finite() {
+        10_f64.powf((target - integrated) / 20.)
+    } else {
+        1.
+    }
+}
+
+fn to_lufs(power: f64) -> f64 {
+    10_f64.mul_add(power.log10(), -0.691)
+}
+
+/// Mean of the values, or 0 if there are none, so their loudness is negative infinity
+fn mean(values: &[f64]) -> f64 {
+    if values.is_empty() {
+        return 0.;
+    }
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+/// Filter with two poles and two zeros, with the coefficients divided by a0
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+}
+
+impl Biquad {
+    /// First stage of the K-weighting, which models the acoustic effect of the head
+    fn high_shelf(sample_rate: u32) -> Self {
+        let k = (PI * 1_681.974_450_955_533 / sample_rate as f64).tan();
+        let q = 0.707_175_236_955_419_6;
+        let vh = 10_f64.powf(3.999_843_853_973_347 / 20.);
+        let vb = vh.powf(0.499_666_774_154_541_6);
+        let a0 = 1. + k / q + k * k;
+
+        Self {
+            b: [
+                (vh + vb * k / q + k * k) / a0,
+                2. * (k * k - vh) / a0,
+                (vh - vb * k / q + k * k) / a0,
+            ],
+            a: [2. * (k * k - 1.) / a0, (1. - k / q + k * k) / a0],
+        }
+    }
+
+    /// Second stage of the K-weighting, which removes the lowest frequencies
+    fn high_pass(sample_rate: u32) -> Self {
+        let k = (PI * 38.135_470_876_024_44 / sample_rate as f64).tan();
+        let q = 0.500_327_037_323_877_3;
+        let a0 = 1. + k / q + k * k;
+
+        Self {
+            b: [1., -2., 1.],
+            a: [2. * (k * k - 1.) / a0, (1. - k / q + k * k) / a0],
+        }
+    }
+
+    fn process(&self, audio: &mut [Frame]) {
+        let mut left = [0.; 2];
+        let mut right = [0.; 2];
+        for frame in audio {
+            frame.left = self.tick(&mut left, frame.left);
+            frame.right = self.tick(&mut right, frame.right);
+        }
+    }
+
+    /// Transposed direct form II, where `state` holds the two delays
+    fn tick(&self, state: &mut [f64; 2], x: f64) -> f64 {
+        let y = self.b[0].mul_add(x, state[0]);
+        state[0] = self.b[1].mul_add(x, self.a[0].mul_add(-y, state[1]));
+        state[1] = self.b[2].mul_add(x, -self.a[1] * y);
+        y
+    }
+}
+
+fn k_weighted(audio: &[Frame], sample_rate: u32) -> Vec<Frame> {
+    let mut audio = audio.to_vec();
+    Biquad::high_shelf(sample_rate).process(&mut audio);
+    Biquad::high_pass(sample_rate).process(&mut audio);
+    audio
+}
+
+/// Sum of the squares of both channels for every step, and the length of a step
+fn hop_powers(audio: &[Frame], sample_rate: u32) -> (Vec<f64>, usize) {
+    let hop = (sample_rate as f64 * HOP_SECONDS).round().max(1.) as usize;
+    let sums = audio
+        .chunks_exact(hop)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .map(|frame| frame.left.mul_add(frame.left, frame.right * frame.right))
+                .sum()
+        })
+        .collect();
+    (sums, hop)
+}
+
+/// Mean power of every window of `length` steps
+fn window_powers((sums, hop): &(Vec<f64>, usize), length: usize) -> Vec<f64> {
+    sums.windows(length)
+        .map(|window| window.iter().sum::<f64>() / (length * hop) as f64)
+        .collect()
+}
+
+/// Keeps the windows that are louder than the absolute gate,
+/// and than `relative` LU below the loudness of those
+fn gate(powers: &[f64], relative: f64) -> Vec<f64> {
+    let audible: Vec<f64> = powers
+        .iter()
+        .copied()
+        .filter(|&power| to_lufs(power) > ABSOLUTE_GATE)
+        .collect();
+    let threshold = to_lufs(mean(&audible)) + relative;
+
+    audible
+        .into_iter()
+        .filter(|&power| to_lufs(power) > threshold)
+        .collect()
+}
+
+/// Difference between the 10th and 95th percentiles of the short-term loudness, per EBU Tech 3342
+fn loudness_range(short_term: &[f64]) -> f64 {
+    let mut loudness: Vec<f64> = gate(short_term, RANGE_RELATIVE_GATE)
+        .into_iter()
+        .map(to_lufs)
+        .collect();
+    if loudness.is_empty() {
+        return 0.;
+    }
+    loudness.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+    let percentile = |p: f64| loudness[((loudness.len() - 1) as f64 * p).round() as usize];
+    percentile(0.95) - percentile(0.10)
+}
+
+/// Windowed sinc, with the window going from `-half` to `half`
+fn windowed_sinc(x: f64, half: f64) -> f64 {
+    let window = 0.5 * (1. + (PI * x / half).cos());
+    if x == 0. {
+        1.
+    } else {
+        window * (PI * x).sin() / (PI * x)
+    }
+}
+
+/// Highest absolute value of the audio oversampled 4 times
+fn true_peak(audio: &[Frame]) -> f64 {
+    let half = TAPS as isize / 2;
+    let phases: Vec<Vec<f64>> = (1..OVERSAMPLING)
+        .map(|phase| {
+            let t = phase as f64 / OVERSAMPLING as f64;
+            let taps: Vec<f64> = (1 - half..=half)
+                .map(|j| windowed_sinc(j as f64 - t, half as f64))
+                .collect();
+            let sum: f64 = taps.iter().sum();
+            taps.iter().map(|tap| tap / sum).collect()
+        })
+        .collect();
+
+    let sample = |idx: isize| {
+        if idx < 0 {
+            Frame::default()
+        } else {
+            audio.get(idx as usize).copied().unwrap_or_default()
+        }
+    };
+    let abs_max = |frame: Frame| frame.left.abs().max(frame.right.abs());
+
+    let mut peak = 0_f64;
+    for (idx, &frame) in audio.iter().enumerate() {
+        peak = peak.max(abs_max(frame));
+        for taps in &phases {
+            let interpolated = taps
+                .iter()
+                .zip(idx as isize + 1 - half..)
+                .fold(Frame::default(), |sum, (&tap, idx)| sum + sample(idx) * tap);
+            peak = peak.max(abs_max(interpolated));
+        }
+    }
+    peak
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sine(db: f64, seconds: f64, sample_rate: u32) -> Vec<Frame> {
+        let amplitude = 10_f64.powf(db / 20.);
+        (0..(seconds * sample_rate as f64) as usize)
+            .map(|i| {
+                let t = i as f64 / sample_rate as f64;
+                Frame::mono((2. * PI * 1_000. * t).sin() * amplitude)
+            })
+            .collect()
+    }
+
+    fn assert_close(a: f64, b: f64, tolerance: f64) {
+        assert!((a - b).abs() < tolerance, "{} is not {}", a, b);
+    }
+
+    #[test]
+    fn sines_have_their_level_in_lufs() {
+        // From EBU Tech 3341, a stereo sine at -23 dBFS measures -23 LUFS
+        let loudness = Loudness::measure(&sine(-23., 20., 48_000), 48_000);
+
+        assert_close(-23., loudness.integrated, 0.1);
+        assert_close(-23., loudness.max_momentary(), 0.1);
+        assert_close(-23., loudness.max_short_term(), 0.1);
+        assert_close(0., loudness.range, 0.1);
+        assert_close(-23., loudness.true_peak, 0.1);
+    }
+
+    #[test]
+    fn loudness_range_of_two_levels() {
+        // From EBU Tech 3342, 20s at -20 dBFS and 20s at -30 dBFS have a range of 10 LU
+        let mut audio = sine(-20., 20., 48_000);
+        audio.extend(sine(-30., 20., 48_000));
+
+        assert_close(10., Loudness::measure(&audio, 48_000).range, 1.);
+    }
+
+    #[test]
+    fn true_peaks_are_found_between_samples() {
+        // A quarter of the sample rate, with every sample halfway between the peaks
+        let audio: Vec<Frame> = (0..4_800)
+            .map(|i| Frame::mono((PI / 2.).mul_add(i as f64, PI / 4.).sin()))
+            .collect();
+        let loudness = Loudness::measure(&audio, 48_000);
+
+        assert!(loudness.true_peak > -0.5);
+    }
+
+    #[test]
+    fn audio_gets_normalized_to_the_target() {
+        let audio = normalize_loudness(sine(-30., 5., 44_100), 44_100, -14.);
+        assert_close(-14., Loudness::measure(&audio, 44_100).integrated, 0.01);
+
+        let silence = vec![Frame::default(); 44_100];
+        let normalized = normalize_loudness(silence.clone(), 44_100, -14.);
+        assert_eq!(silence, normalized);
+        assert_eq!(
+            f64::NEG_INFINITY,
+            Loudness::measure(&silence, 44_100).integrated
+        );
+    }
+}
@@ -4,9 +4,8 @@ use crate::frame::Frame;
 use anyhow::Result;
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
 use cpal::{Device, SampleFormat, StreamConfig};
-use std::sync::atomic::AtomicUsize;
-use std::sync::atomic::Ordering;
 use std::sync::mpsc;
+use std::sync::mpsc::{Receiver, TryRecvError};
 use std::sync::Arc;
 
 pub(crate) struct PlayerConfig {
@@ -38,11 +37,62 @@ pub(crate) fn get_player_config() -> PlayerConfig {
     }
 }
 
+pub(crate) enum PlayerAudio {
+    /// The whole song, already generated
+    Buffer(Arc<[Frame]>),
+    /// Blocks that arrive while the song is being rendered
+    Stream(Receiver<Vec<Frame>>),
+}
+
 pub(crate) struct Player {
-    pub audio: Arc<[Frame]>,
+    pub audio: PlayerAudio,
     pub cycle: bool,
 }
 
+/// Keeps track of the next frame to play
+struct Cursor {
+    player: Player,
+    block: Vec<Frame>,
+    idx: usize,
+}
+impl Cursor {
+    /// Returns the next frame, or None once the audio has finished
+    fn next(&mut self) -> Option<Frame> {
+        match &self.player.audio {
+            PlayerAudio::Buffer(audio) => {
+                if self.idx >= audio.len() {
+                    if !self.player.cycle || audio.is_empty() {
+                        return None;
+                    }
+                    self.idx = 0;
+                }
+                let frame = audio[self.idx];
+                self.idx += 1;
+                Some(frame)
+            }
+            PlayerAudio::Stream(receiver) => {
+                while self.idx >= self.block.len() {
+                    match receiver.try_recv() {
+                        Ok(block) => {
+                            self.block = block;
+                            self.idx = 0;
+                        }
+                        // Rendering fell behind, so we play silence until the next block arrives
+                        Err(TryRecvError::Empty) => return Some(Frame::default()),
+                        Err(TryRecvError::Disconnected) => return None,
+                    }
+                }
+                let frame = self.block[self.idx];
+                self.idx += 1;
+                Some(frame)
+            }
+        }
+    }
+}
+
+/// Plays the audio, blocking until it has finished
+///
+/// `feed` gets called once the stream has started playing, and is used to keep sending blocks when streaming
 pub(crate) fn run_player(
     player: Player,
     PlayerConfig {
@@ -51,15 +101,21 @@ pub(crate) fn run_player(
         stream_config,
         ..
     }: PlayerConfig,
+    feed: impl FnOnce(),
 ) -> Result<()> {
     match sample_format {
-        cpal::SampleFormat::F32 => run::<f32>(&device, &stream_config, player),
-        cpal::SampleFormat::I16 => run::<i16>(&device, &stream_config, player),
-        cpal::SampleFormat::U16 => run::<u16>(&device, &stream_config, player),
+        cpal::SampleFormat::F32 => run::<f32>(&device, &stream_config, player, feed),
+        cpal::SampleFormat::I16 => run::<i16>(&device, &stream_config, player, feed),
+        cpal::SampleFormat::U16 => run::<u16>(&device, &stream_config, player, feed),
     }
 }
 
-fn run<T>(device: &cpal::Device, config: &cpal::StreamConfig, player: Player) -> Result<()>
+fn run<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    player: Player,
+    feed: impl FnOnce(),
+) -> Result<()>
 where
     T: cpal::Sample,
 {
@@ -68,16 +124,20 @@ where
 
     // Create and run the stream.
     let channels = config.channels as usize;
-    let index = Arc::new(AtomicUsize::new(0));
-    let total_len = player.audio.len();
+    let mut cursor = Cursor {
+        player,
+        block: Vec::new(),
+        idx: 0,
+    };
 
     let stream = device.build_output_stream(
         config,
         move |output: &mut [T], _: &cpal::OutputCallbackInfo| {
-            let mut idx = index.load(Ordering::Relaxed);
-
             for frame in output.chunks_mut(channels) {
-                let sample = player.audio[idx];
+                let sample = cursor.next().unwrap_or_else(|| {
+                    complete_tx.try_send(()).ok();
+                    Frame::default()
+                });
 
                 if frame.len() == 2 {
                     frame[0] = cpal::Sample::from::<f32>(&(sample.left as f32));
@@ -88,25 +148,15 @@ where
                         *sample = value;
                     }
                 }
-
-                idx += 1;
-                if idx >= total_len {
-                    if player.cycle {
-                        idx = 0;
-                    } else {
-                        index.store(idx, Ordering::Relaxed);
-                        complete_tx.try_send(()).ok();
-                        return;
-                    }
-                }
             }
-            index.store(idx, Ordering::Relaxed);
         },
         |err| eprintln!("an error occurred on stream: {}", err),
     )?;
     println!("Starting to play");
     stream.play()?;
 
+    feed();
+
     // Wait for playback to complete.
     complete_rx.recv().unwrap();
     stream.pause()?;
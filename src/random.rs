@@ -0,0 +1,181 @@
+//! Seeded random numbers, so a song renders the same every time
+//!
+//! Every track gets its own random number generator, seeded from `SongConfig::seed`
+//! and the index of the track. That way the numbers a track gets don't depend on the
+//! order in which the tracks are generated
+
+use crate::song::Song;
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+thread_local! {
+    /// Index of the track that is being generated in this thread
+    static CURRENT_TRACK: Cell<Option<usize>> = const { Cell::new(None) };
+    /// Generator of the track that is being generated in this thread
+    static CURRENT_RNG: RefCell<Option<TrackRng>> = const { RefCell::new(None) };
+}
+
+/// Runs `f` with `track` of `song` as the track being generated in this thread
+pub(crate) fn with_current_track<T>(song: &Song, track: usize, f: impl FnOnce() -> T) -> T {
+    let previous = CURRENT_TRACK.with(|current| current.replace(Some(track)));
+    let previous_rng = CURRENT_RNG.with(|current| current.replace(Some(song.rng())));
+    let result = f();
+    CURRENT_TRACK.with(|current| current.set(previous));
+    CURRENT_RNG.with(|current| *current.borrow_mut() = previous_rng);
+    result
+}
+
+/// Returns the generator of the track being generated in this thread, see `Song::rng`
+///
+/// It's None outside of a track, like when an instrument is used on its own
+pub(crate) fn track_rng() -> Option<TrackRng> {
+    CURRENT_RNG.with(|current| current.borrow().as_ref().map(|rng| TrackRng(rng.0.clone())))
+}
+
+/// Makes a generator for `track`, or for the song itself if it's None
+fn new_rng(seed: u64, track: Option<usize>) -> ChaCha8Rng {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    rng.set_stream(track.map_or(0, |track| track as u64 + 1));
+    rng
+}
+
+/// Generators of every track of a song, created the first time they are used
+#[derive(Default)]
+pub(crate) struct TrackRngs {
+    rngs: Mutex<HashMap<Option<usize>, Arc<Mutex<ChaCha8Rng>>>>,
+}
+impl TrackRngs {
+    /// Returns the generator of the track being generated in this thread
+    pub(crate) fn current(&self, seed: u64) -> TrackRng {
+        let track = CURRENT_TRACK.with(Cell::get);
+        let rng = self
+            .rngs
+            .lock()
+            .unwrap()
+            .entry(track)
+            .or_insert_with(|| Arc::new(Mutex::new(new_rng(seed, track))))
+            .clone();
+        TrackRng(rng)
+    }
+
+    /// Starts every generator again from its seed
+    pub(crate) fn reset(&self) {
+        self.rngs.lock().unwrap().clear();
+    }
+}
+
+/// Random number generator of a track, see `Song::rng`
+///
+/// Every handle of the same track shares its state, so getting it more than once
+/// keeps returning new numbers
+pub struct TrackRng(Arc<Mutex<ChaCha8Rng>>);
+
+impl RngCore for TrackRng {
+    fn next_u32(&mut self) -> u32 {
+        self.0.lock().unwrap().next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0.lock().unwrap().next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.0.lock().unwrap().fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.0.lock().unwrap().try_fill_bytes(dest)
+    }
+}
+
+/// Generator used by instruments when they are not given one, so they still sound the same every time
+pub(crate) fn default_rng() -> ChaCha8Rng {
+    new_rng(0, None)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::frame::{Frame, IntoFrames};
+    use crate::signals::noise::noise_for;
+    use crate::song::*;
+    use crate::synth::{DrumHiHat, Instrument};
+    use crate::track::Track;
+
+    fn hihat(song: &Song) -> Vec<Frame> {
+        let mut audio = DrumHiHat::default().generate_for(song, 100, 440.);
+        audio.extend(DrumHiHat::default().generate_for(song, 100, 440.));
+        audio
+    }
+
+    fn render(seed: u64) -> Vec<Vec<Frame>> {
+        let config = SongConfig {
+            seed,
+            ..Default::default()
+        };
+        let mut song = Song::new(
+            vec![
+                Track::new(hihat),
+                Track::new(|song: &Song| noise_for(song, 100).as_frames()),
+            ],
+            config,
+        );
+        song.generate(44_100);
+        let first = song.generated().unwrap().to_vec();
+        song.generate(48_000);
+        song.generate(44_100);
+        vec![first, song.generated().unwrap().to_vec()]
+    }
+
+    #[test]
+    fn renders_are_the_same_with_the_same_seed() {
+        let first = render(1);
+        assert_eq!(first[0], first[1]);
+        assert_eq!(first, render(1));
+        assert_ne!(first, render(2));
+    }
+
+    #[test]
+    fn tracks_get_different_numbers() {
+        let mut song = Song::new(
+            vec![Track::new(hihat), Track::new(hihat)],
+            SongConfig::default(),
+        );
+        let stems = song.stems(44_100, crate::render::StemMode::PreMaster);
+
+        assert_ne!(stems[0].audio, stems[1].audio);
+        // Each note of a track also gets different numbers
+        assert_ne!(stems[0].audio[..100], stems[0].audio[100..]);
+    }
+
+    #[test]
+    fn instruments_use_the_numbers_of_the_track() {
+        let render = |seed: u64| {
+            let hihat = |song: &Song| {
+                let mut audio = DrumHiHat::default().generate(100, 440., song.sample_rate());
+                audio.extend(DrumHiHat::default().generate(100, 440., song.sample_rate()));
+                audio
+            };
+            let config = SongConfig {
+                seed,
+                ..Default::default()
+            };
+            let mut song = Song::new(vec![Track::new(hihat)], config);
+            song.generate(44_100);
+            song.generated().unwrap().to_vec()
+        };
+
+        let audio = render(1);
+        assert_eq!(audio, render(1));
+        assert_ne!(audio, render(2));
+        assert_ne!(audio[..100], audio[100..]);
+    }
+
+    #[test]
+    fn instruments_are_seeded_outside_of_a_song() {
+        let hihat = || DrumHiHat::default().generate(100, 440., 44_100);
+        assert_eq!(hihat(), hihat());
+    }
+}
@@ -0,0 +1,50 @@
+//! Named sections of a song, and regions to render only part of it
+
+use crate::time_signature::Position;
+
+/// Named part of the song, like "chorus" or "bridge"
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Section {
+    pub name: String,
+    pub start: Position,
+    /// The section ends right before this position
+    pub end: Position,
+}
+impl Section {
+    pub fn new(name: impl Into<String>, start: Position, end: Position) -> Self {
+        Self {
+            name: name.into(),
+            start,
+            end,
+        }
+    }
+}
+
+/// Part of the song, from a start to an end
+///
+/// Since we don't know the sample rate when creating it, the region gets converted
+/// to samples with `Song::region`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Region {
+    Samples(usize, usize),
+    Seconds(f64, f64),
+    Beats(f64, f64),
+    Positions(Position, Position),
+    /// One of the sections in the `SongConfig`
+    Section(String),
+}
+impl From<&str> for Region {
+    fn from(section: &str) -> Self {
+        Self::Section(section.to_string())
+    }
+}
+impl From<String> for Region {
+    fn from(section: String) -> Self {
+        Self::Section(section)
+    }
+}
+impl From<&Section> for Region {
+    fn from(section: &Section) -> Self {
+        Self::Positions(section.start, section.end)
+    }
+}
@@ -0,0 +1,1102 @@
+//! Block based rendering
+//!
+//! Instead of joining every track into a full `Vec<Frame>`, a song can be rendered one block
+//! at a time. The blocks can be written to a file or sent to the player as soon as they're ready,
+//! so the mixed song never has to be fully in memory.
+//!
+//! Only the tracks made with `TrackGenerator::blocks` are generated as the blocks are requested.
+//! Every other track is still generated in full before the first block, and then read from a `BufferSource`
+
+use crate::bus::Bus;
+use crate::effects::{BlockProcessor, Effect, EffectBundle, FrameProcessor};
+use crate::error::{Error, Result};
+use crate::frame::Frame;
+use crate::helpers::*;
+use crate::random::with_current_track;
+use crate::song::{Song, TrackGenerator};
+use crate::sound_files::enums::Metronome;
+use crate::time_signature::Position;
+use crate::track::{AuxSend, Track};
+use rayon::prelude::*;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// Number of frames in each block, unless a different one is requested
+pub const DEFAULT_BLOCK_SIZE: usize = 512;
+
+/// Something that produces audio one block at a time
+pub trait BlockSource {
+    /// Writes the next frames into `block`, and returns how many were written
+    ///
+    /// Writing less than `block.len()` frames means that the source has finished
+    fn next_block(&mut self, song: &Song, block: &mut [Frame]) -> usize;
+
+    /// Goes back to the beginning, so the source can be rendered again
+    fn reset(&mut self) {}
+}
+
+/// Serves an already generated buffer one block at a time
+pub struct BufferSource {
+    audio: Vec<Frame>,
+    position: usize,
+}
+impl BufferSource {
+    pub const fn new(audio: Vec<Frame>) -> Self {
+        Self { audio, position: 0 }
+    }
+}
+impl BlockSource for BufferSource {
+    fn next_block(&mut self, _song: &Song, block: &mut [Frame]) -> usize {
+        let remaining = &self.audio[self.position.min(self.audio.len())..];
+        let len = block.len().min(remaining.len());
+
+        block[..len].copy_from_slice(&remaining[..len]);
+        self.position += len;
+        len
+    }
+
+    fn reset(&mut self) {
+        self.position = 0;
+    }
+}
+
+/// Block source made out of a closure
+///
+/// The closure receives the song, the position of the first frame in the block,
+/// and the block to fill. It returns the number of frames it wrote
+pub struct BlockFn<F> {
+    fun: F,
+    position: usize,
+}
+impl<F: FnMut(&Song, usize, &mut [Frame]) -> usize> BlockFn<F> {
+    pub const fn new(fun: F) -> Self {
+        Self { fun, position: 0 }
+    }
+}
+impl<F: FnMut(&Song, usize, &mut [Frame]) -> usize> BlockSource for BlockFn<F> {
+    fn next_block(&mut self, song: &Song, block: &mut [Frame]) -> usize {
+        let written = (self.fun)(song, self.position, block);
+        self.position += written;
+        written
+    }
+
+    fn reset(&mut self) {
+        self.position = 0;
+    }
+}
+
+/// Reads from the source until it finishes, and returns all of the audio
+pub fn collect_blocks(source: &mut dyn BlockSource, song: &Song, block_size: usize) -> Vec<Frame> {
+    let mut audio = Vec::new();
+    let mut block = vec![Frame::default(); block_size];
+
+    loop {
+        let written = source.next_block(song, &mut block);
+        audio.extend_from_slice(&block[..written]);
+
+        if written < block_size {
+            return audio;
+        }
+    }
+}
+
+/// Whether stems include the master volume and effects
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StemMode {
+    /// Stems as they get into the master, before the volume of the song
+    PreMaster,
+    /// Stems with the volume of the song, each run through its own copy of the master effects
+    ///
+    /// They only add up to the mix while the master effects are linear,
+    /// like a limiter that doesn't have to lower any peaks
+    PostMaster,
+}
+
+/// Audio of a single track or bus, aligned with the rest of the song
+pub struct Stem {
+    pub name: String,
+    pub audio: Vec<Frame>,
+}
+
+enum TrackStream<'a> {
+    /// Tracks that can only be generated all at once
+    Buffer(BufferSource),
+    /// Tracks that are generated as the blocks are requested
+    Source(&'a Mutex<dyn BlockSource + Send>),
+}
+
+struct RenderTrack<'a> {
+    track: &'a Track,
+    /// Position of the track in the song
+    index: usize,
+    stream: TrackStream<'a>,
+    /// Insert effects, if the track is streamed
+    inserts: Option<Box<dyn BlockProcessor + 'a>>,
+    /// Sends to streamed buses, with the index of the bus
+    sends: Vec<(usize, &'a AuxSend)>,
+    position: usize,
+    finished: bool,
+}
+impl<'a> RenderTrack<'a> {
+    /// `audio` is the already generated track, or None if the track gets streamed
+    fn new(
+        track: &'a Track,
+        index: usize,
+        audio: Option<Vec<Frame>>,
+        buses: &[RenderBus<'a>],
+        sample_rate: u32,
+    ) -> Self {
+        let mut inserts = None;
+
+        let stream = match (audio, &track.generator) {
+            (Some(audio), _) => TrackStream::Buffer(BufferSource::new(audio)),
+            (None, TrackGenerator::Blocks(source)) => {
+                source.lock().unwrap().reset();
+                inserts = track.effects.as_ref().map(|effects| {
+                    effects
+                        .processor_at(sample_rate)
+                        .expect("Streamed tracks should have streamable effects")
+                });
+                TrackStream::Source(&**source)
+            }
+            (None, _) => unreachable!("Only block tracks can be streamed"),
+        };
+
+        let sends = track
+            .sends
+            .iter()
+            .map(|send| (bus_index(buses.iter().map(|bus| bus.bus), &send.bus), send))
+            .filter(|(idx, _)| matches!(buses[*idx].stream, BusStream::Streamed(_)))
+            .collect();
+
+        Self {
+            track,
+            index,
+            stream,
+            inserts,
+            sends,
+            position: 0,
+            finished: false,
+        }
+    }
+
+    /// Reads the next frames of the track, with the insert effects applied
+    fn next_block(&mut self, song: &Song, block: &mut [Frame]) -> usize {
+        let written = match &mut self.stream {
+            TrackStream::Buffer(buffer) => buffer.next_block(song, block),
+            TrackStream::Source(source) => with_current_track(song, self.index, || {
+                source.lock().unwrap().next_block(song, block)
+            }),
+        };
+
+        if let Some(inserts) = &mut self.inserts {
+            inserts.process(&mut block[..written]);
+        }
+
+        if written < block.len() {
+            self.finished = true;
+        }
+        written
+    }
+}
+
+enum BusStream<'a> {
+    /// Buses whose effects get applied one block at a time
+    Streamed(Box<dyn BlockProcessor + 'a>),
+    /// Buses that get rendered all at once, after generating the tracks that send to them
+    Buffer(BufferSource),
+}
+
+struct RenderBus<'a> {
+    bus: &'a Bus,
+    stream: BusStream<'a>,
+    /// Sum of the sends in the current block
+    input: Vec<Frame>,
+    position: usize,
+    /// Number of silent frames at the end of the output so far
+    silence: usize,
+    finished: bool,
+}
+
+/// Frames quieter than this count as silence when waiting for the tail of a bus
+const SILENCE: f64 = 1e-6;
+
+/// Returns the index of the bus with the given name
+///
+/// The sends are checked with `check_sends` before rendering, so the bus should exist
+fn bus_index<'a>(mut buses: impl Iterator<Item = &'a Bus>, name: &str) -> usize {
+    buses
+        .position(|bus| bus.name == name)
+        .unwrap_or_else(|| panic!("There is no bus named {}", name))
+}
+
+/// Returns an error if a track sends to a bus that doesn't exist, or if two buses have the same name
+fn check_sends(song: &Song) -> Result<()> {
+    let buses = &song.config.buses;
+    for (idx, bus) in buses.iter().enumerate() {
+        if buses[..idx].iter().any(|other| other.name == bus.name) {
+            return Err(Error::DuplicateBus(bus.name.clone()));
+        }
+    }
+
+    for track in &song.tracks {
+        for send in &track.sends {
+            if !song.config.buses.iter().any(|bus| bus.name == send.bus) {
+                return Err(Error::Track {
+                    name: track_name(song, track),
+                    source: Box::new(Error::BusNotFound(send.bus.clone())),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Generates the tracks that can't be streamed, with their insert effects applied
+///
+/// Returns None if the track can be streamed. If `force` is true, the track is always generated
+fn generate_track(track: &Track, song: &Song, force: bool) -> Result<Option<Vec<Frame>>> {
+    // Tracks only get cached when rendering the whole song, as generators may skip
+    // the parts outside of the range
+    let cache = track.cache.as_ref().filter(|_| song.range().is_none());
+
+    let streamable = matches!(track.generator, TrackGenerator::Blocks(_))
+        && !matches!(&track.effects, Some(effects) if effects.processor().is_none());
+    if streamable && !force && cache.is_none() {
+        return Ok(None);
+    }
+
+    let name = track_name(song, track);
+    if cache.is_some() && track.name.is_empty() {
+        return Err(Error::Track {
+            name,
+            source: Box::new(Error::UnnamedCachedTrack),
+        });
+    }
+    let generate = || {
+        with_current_track(song, track_index(song, track), || {
+            track.generator.try_call(song)
+        })
+        .map_err(|err| Error::Track {
+            name: name.clone(),
+            source: Box::new(err),
+        })
+    };
+    let audio = match cache {
+        Some(version) => song.cached_track(&track.name, version, generate)?,
+        None => generate()?,
+    };
+    Ok(Some(match &track.effects {
+        Some(effects) => effects.run_at(audio, song.sample_rate()),
+        None => audio,
+    }))
+}
+
+/// Returns the position of the track in the song
+fn track_index(song: &Song, track: &Track) -> usize {
+    song.tracks
+        .iter()
+        .position(|other| std::ptr::eq(other, track))
+        .unwrap()
+}
+
+/// Returns the name of the track, or `Track {number}` if it doesn't have one
+fn track_name(song: &Song, track: &Track) -> String {
+    if track.name.is_empty() {
+        format!("Track {}", track_index(song, track) + 1)
+    } else {
+        track.name.clone()
+    }
+}
+
+/// Adds up everything that the tracks send to a bus
+fn sum_sends(bus: &Bus, tracks: &[&Track], generated: &[Option<Vec<Frame>>]) -> Vec<Frame> {
+    let mut input: Vec<Frame> = vec![];
+
+    for (track, audio) in tracks.iter().zip(generated) {
+        for send in track.sends.iter().filter(|send| send.bus == bus.name) {
+            let audio = audio
+                .as_ref()
+                .expect("Tracks sending to a buffered bus should have been generated");
+            if input.len() < audio.len() {
+                input.resize(audio.len(), Frame::default());
+            }
+
+            for (idx, (frame, value)) in input.iter_mut().zip(audio).enumerate() {
+                *frame += send.value(idx, *value, track.fader(idx, *value));
+            }
+        }
+    }
+
+    input
+}
+
+/// Number of beats the metronome plays before the song starts
+const PREROLL_BEATS: usize = 3;
+
+/// Plays a click at the start of every beat, following the tempo and time signature of the song
+///
+/// The first beat of each bar gets a louder click
+struct MetronomeClicks {
+    click: Vec<Frame>,
+    accent: Vec<Frame>,
+    /// Sample of the song where the rendering starts
+    start: usize,
+    /// Length of the beats before the song starts, which use the tempo at `start`
+    preroll_beat: usize,
+    /// Number of preroll beats that have started
+    preroll_count: usize,
+    /// Position of the next beat of the song
+    next_position: Position,
+    next_start: usize,
+    current: Option<(usize, bool)>,
+}
+impl MetronomeClicks {
+    fn new(song: &Song, start: usize) -> Result<Self> {
+        let bpm = song.bpm_at(song.beat_at(start));
+        let preroll_beat = song.seconds(60. / bpm);
+        let accent = song
+            .try_sound(Metronome)?
+            .take_samples(song.seconds(0.2 * 60. / bpm));
+        let click = accent.iter().map(|&frame| frame * 0.5).collect();
+
+        // The first click after the preroll is on the first beat at or after `start`
+        let position = song.position_at(start);
+        let next_position = if position.tick == 0 && song.position(position) == start {
+            position
+        } else {
+            song.time_signatures().next_beat(position)
+        };
+
+        Ok(Self {
+            click,
+            accent,
+            start,
+            preroll_beat,
+            preroll_count: 0,
+            next_position,
+            next_start: 0,
+            current: None,
+        })
+    }
+
+    const fn preroll(&self) -> usize {
+        self.preroll_beat * PREROLL_BEATS
+    }
+
+    /// Moves to the next beat, returning whether the current one is a downbeat
+    fn advance(&mut self, song: &Song) -> bool {
+        if self.preroll_count < PREROLL_BEATS {
+            self.preroll_count += 1;
+            self.next_start = self.preroll_count * self.preroll_beat;
+            return self.preroll_count == 1;
+        }
+
+        let downbeat = self.next_position.is_downbeat();
+        self.next_position = song.time_signatures().next_beat(self.next_position);
+        self.next_start = self.preroll() + song.position(self.next_position) - self.start;
+        downbeat
+    }
+
+    /// Returns the value of the metronome at `position`
+    ///
+    /// Positions need to be requested in order
+    fn value(&mut self, song: &Song, position: usize) -> Frame {
+        while position >= self.next_start {
+            let start = self.next_start;
+            let downbeat = self.advance(song);
+            self.current = Some((start, downbeat));
+        }
+
+        self.current
+            .and_then(|(start, downbeat)| {
+                let click = if downbeat { &self.accent } else { &self.click };
+                click.get(position - start)
+            })
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+/// Iterator over the blocks of a song
+///
+/// Created with `Song::render`
+pub struct SongRenderer<'a> {
+    song: &'a Song,
+    tracks: Vec<RenderTrack<'a>>,
+    buses: Vec<RenderBus<'a>>,
+    volume: Box<dyn BlockProcessor + 'a>,
+
+    metronome: Option<MetronomeClicks>,
+    /// Silence added in front of the tracks
+    preroll: usize,
+    /// Sample of the song where the output starts
+    start: usize,
+    duration: Option<usize>,
+    block_size: usize,
+
+    /// Position in the output of the next block
+    position: usize,
+    /// Set once all of the tracks have finished
+    mix_finished: bool,
+    /// Buffer the tracks get read into before being mixed
+    scratch: Vec<Frame>,
+    /// Audio of every track and then every bus, if stems are being recorded
+    stems: Option<(StemMode, Vec<Vec<Frame>>)>,
+
+    /// Multiplier applied to the song before the master effects
+    gain: f64,
+    master: MasterStage<'a>,
+    /// Frames that went through the master effects, waiting to be returned
+    output: VecDeque<Frame>,
+    premaster_finished: bool,
+    /// Samples that went over 1 before the master effects
+    clipped: usize,
+}
+
+impl<'a> SongRenderer<'a> {
+    pub(crate) fn try_new(song: &'a Song, block_size: usize) -> Result<Self> {
+        assert!(
+            !song.tracks.is_empty(),
+            "There should be at least one track to render"
+        );
+        assert!(block_size > 0, "Blocks should have at least one frame");
+        check_sends(song)?;
+        song.reset_rngs();
+        let sample_rate = song.try_sample_rate()?;
+
+        let any_solo = song.tracks.iter().any(|track| track.solo);
+        let audible = song
+            .tracks
+            .iter()
+            .filter(|track| track.is_audible(any_solo))
+            .collect::<Vec<_>>();
+
+        // Tracks that send to a bus that can't be streamed need to be generated fully
+        let streamable_buses = song
+            .config
+            .buses
+            .iter()
+            .map(Bus::is_streamable)
+            .collect::<Vec<_>>();
+        let sends_to_buffered_bus = |track: &Track| {
+            track
+                .sends
+                .iter()
+                .any(|send| !streamable_buses[bus_index(song.config.buses.iter(), &send.bus)])
+        };
+
+        // Tracks that can't be streamed get generated in parallel
+        // The order is kept, so the mix is the same as generating them one after another
+        let generated = audible
+            .par_iter()
+            .map(|track| generate_track(track, song, sends_to_buffered_bus(track)))
+            .collect::<Result<Vec<_>>>()?;
+
+        // Buses that can't be streamed are also run in parallel
+        let buffered_buses = song
+            .config
+            .buses
+            .par_iter()
+            .zip(streamable_buses.par_iter())
+            .map(|(bus, streamable)| {
+                (!streamable).then(|| {
+                    bus.effects
+                        .run_at(sum_sends(bus, &audible, &generated), sample_rate)
+                })
+            })
+            .collect::<Vec<_>>();
+        let buses = song
+            .config
+            .buses
+            .iter()
+            .zip(buffered_buses)
+            .map(|(bus, buffered)| RenderBus {
+                bus,
+                stream: buffered.map_or_else(
+                    || BusStream::Streamed(bus.effects.processor_at(sample_rate).unwrap()),
+                    |audio| BusStream::Buffer(BufferSource::new(audio)),
+                ),
+                input: vec![Frame::default(); block_size],
+                position: 0,
+                silence: 0,
+                finished: false,
+            })
+            .collect::<Vec<_>>();
+
+        let tracks = audible
+            .iter()
+            .zip(generated)
+            .map(|(track, audio)| {
+                RenderTrack::new(track, track_index(song, track), audio, &buses, sample_rate)
+            })
+            .collect();
+
+        let volume = &song.config.volume;
+
+        // Add some delay in the front if we enable metronome
+        // This way we get like 3 beats of the metronome before we start
+        let range = song.range();
+        let start = range.as_ref().map_or(0, |range| range.start);
+        let duration = range.map_or_else(
+            || song.duration(),
+            |range| Some(range.end.saturating_sub(range.start)),
+        );
+
+        let (metronome, preroll) = if song.config.metronome {
+            let metronome = MetronomeClicks::new(song, start)?;
+            let preroll = metronome.preroll();
+            (Some(metronome), preroll)
+        } else {
+            (None, 0)
+        };
+
+        let mut renderer = Self {
+            song,
+            tracks,
+            buses,
+            volume: Box::new(FrameProcessor::new(move |idx, frame: Frame| {
+                frame * volume.value(idx)
+            })),
+
+            metronome,
+            preroll,
+            start,
+            duration,
+            block_size,
+
+            position: 0,
+            mix_finished: false,
+            scratch: vec![Frame::default(); block_size],
+            stems: None,
+
+            gain: 1.,
+            master: MasterStage::new(&song.config.master, sample_rate),
+            output: VecDeque::new(),
+            premaster_finished: false,
+            clipped: 0,
+        };
+        renderer.skip_samples(start);
+
+        Ok(renderer)
+    }
+
+    /// Mixes the first `samples` of the song and throws them away
+    ///
+    /// The effects still run over them, so their tails get into the part that is kept
+    fn skip_samples(&mut self, mut samples: usize) {
+        let mut buffer = vec![Frame::default(); self.block_size];
+        while samples > 0 && !self.mix_finished {
+            let len = samples.min(self.block_size);
+            for frame in &mut buffer[..len] {
+                *frame = Frame::default();
+            }
+            let written = self.mix(&mut buffer[..len], 0);
+            self.master.skip(buffer[..written].to_vec());
+            samples -= len;
+        }
+    }
+
+    /// Multiplies the song by `gain` before the master effects, like to normalize its loudness
+    ///
+    /// Only the blocks that haven't been rendered yet are affected
+    pub(crate) const fn pre_master_gain(&mut self, gain: f64) {
+        self.gain = gain;
+    }
+
+    /// Makes the renderer keep the audio of each track and bus, to get them later with `into_stems`
+    pub(crate) fn record_stems(&mut self, mode: StemMode) {
+        let count = self.tracks.len() + self.buses.len();
+        self.stems = Some((mode, vec![vec![]; count]));
+    }
+
+    /// Returns the stems recorded so far, named after their track or bus
+    ///
+    /// Tracks without a name are called `Track {number}`
+    pub(crate) fn into_stems(self) -> Vec<Stem> {
+        let song = self.song;
+        let names = self
+            .tracks
+            .iter()
+            .map(|track| track_name(song, track.track))
+            .chain(self.buses.iter().map(|bus| bus.bus.name.clone()));
+
+        let (mode, stems) = self.stems.unwrap_or((StemMode::PreMaster, vec![]));
+        let sample_rate = song.sample_rate();
+        names
+            .zip(stems)
+            .map(|(name, audio)| {
+                let audio = match mode {
+                    StemMode::PreMaster => audio,
+                    StemMode::PostMaster => {
+                        let mut master = MasterStage::new(&song.config.master, sample_rate);
+                        let mut output = VecDeque::new();
+                        master.push(audio, &mut output);
+                        master.finish(&mut output);
+                        output.into()
+                    }
+                };
+                Stem { name, audio }
+            })
+            .collect()
+    }
+
+    /// Adds the next frames of every track and bus into `out`, and returns how many were written
+    ///
+    /// `position` is where `out` starts in the song, including the preroll
+    fn mix(&mut self, out: &mut [Frame], position: usize) -> usize {
+        let song = self.song;
+        let len = out.len();
+        let scratch = &mut self.scratch[..len];
+        let buses = &mut self.buses;
+        let stems = &mut self.stems;
+
+        // Adds a frame to a stem, if stems are being recorded
+        let preroll = self.preroll;
+        let start = self.start;
+        let mut add_to_stem = |stem: usize, idx: usize, frame: Frame| {
+            if let Some((mode, stems)) = stems {
+                let volume = match mode {
+                    StemMode::PreMaster => 1.,
+                    StemMode::PostMaster => song.config.volume.value(idx + start - preroll),
+                };
+                stems[stem][idx] += frame * volume;
+            }
+        };
+
+        for bus in buses.iter_mut() {
+            for frame in &mut bus.input[..len] {
+                *frame = Frame::default();
+            }
+        }
+
+        let mut written = 0;
+        for (track_idx, track) in self.tracks.iter_mut().enumerate() {
+            if track.finished {
+                continue;
+            }
+            let written_track = track.next_block(song, scratch);
+
+            for (i, frame) in scratch[..written_track].iter().enumerate() {
+                let idx = track.position + i;
+                let post_fader = track.track.fader(idx, *frame);
+                out[i] += post_fader;
+
+                for (bus, send) in &track.sends {
+                    buses[*bus].input[i] += send.value(idx, *frame, post_fader);
+                }
+                add_to_stem(track_idx, position + i, post_fader);
+            }
+
+            track.position += written_track;
+            written = written.max(written_track);
+        }
+
+        let track_count = self.tracks.len();
+        let tracks_written = written;
+        for (bus_idx, bus) in buses.iter_mut().enumerate() {
+            if bus.finished {
+                continue;
+            }
+            let written_bus = match &mut bus.stream {
+                BusStream::Streamed(processor) => {
+                    // Once the sends stop, the effects keep running on silence until their tail ends
+                    processor.process(&mut bus.input[..len]);
+                    let tail = processor.tail() + processor.latency();
+                    let mut end = len;
+                    for (i, frame) in bus.input[..len].iter().enumerate() {
+                        if i >= tracks_written && bus.silence >= tail {
+                            end = i;
+                            break;
+                        }
+                        if frame.max() < SILENCE && frame.min() > -SILENCE {
+                            bus.silence += 1;
+                        } else {
+                            bus.silence = 0;
+                        }
+                    }
+                    end
+                }
+                BusStream::Buffer(buffer) => buffer.next_block(song, &mut bus.input[..len]),
+            };
+
+            for (i, frame) in bus.input[..written_bus].iter().enumerate() {
+                let frame = frame * bus.bus.gain.value(bus.position + i);
+                out[i] += frame;
+                add_to_stem(track_count + bus_idx, position + i, frame);
+            }
+
+            bus.position += written_bus;
+            if written_bus < len {
+                bus.finished = true;
+            }
+            written = written.max(written_bus);
+        }
+
+        if written < len {
+            self.mix_finished = true;
+        }
+
+        self.volume.process(&mut out[..written]);
+        written
+    }
+}
+
+impl SongRenderer<'_> {
+    /// Renders the next block of the song before the master effects
+    fn next_premaster(&mut self) -> Option<Vec<Frame>> {
+        // If a duration was specified, we output exactly that number of samples
+        // Otherwise we stop when all of the tracks have finished
+        let len = match self.duration {
+            Some(duration) => self.block_size.min(duration.saturating_sub(self.position)),
+            None if self.mix_finished => 0,
+            None => self.block_size,
+        };
+        if len == 0 {
+            return None;
+        }
+
+        let mut block = vec![Frame::default(); len];
+
+        if let Some((_, stems)) = &mut self.stems {
+            for stem in stems {
+                stem.resize(self.position + len, Frame::default());
+            }
+        }
+
+        let mut written = self.preroll.saturating_sub(self.position).min(len);
+        if !self.mix_finished && written < len {
+            written += self.mix(&mut block[written..], self.position + written);
+        }
+
+        if let Some(metronome) = &mut self.metronome {
+            for (idx, frame) in block[..written].iter_mut().enumerate() {
+                *frame += metronome.value(self.song, self.position + idx);
+            }
+        }
+
+        if self.duration.is_none() {
+            block.truncate(written);
+            if let Some((_, stems)) = &mut self.stems {
+                for stem in stems {
+                    stem.truncate(self.position + written);
+                }
+            }
+        }
+
+        self.position += block.len();
+
+        if block.is_empty() {
+            None
+        } else {
+            Some(block)
+        }
+    }
+
+    /// Renders the rest of the song before the master effects, like to measure its loudness
+    pub(crate) fn collect_premaster(mut self) -> Vec<Frame> {
+        std::iter::from_fn(|| self.next_premaster())
+            .flatten()
+            .collect()
+    }
+
+    /// Returns how many samples have gone over 1 before the master effects so far
+    ///
+    /// These are the samples that would have clipped without a limiter
+    pub const fn clipped_samples(&self) -> usize {
+        self.clipped
+    }
+}
+
+impl Iterator for SongRenderer<'_> {
+    type Item = Vec<Frame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.output.len() < self.block_size && !self.premaster_finished {
+            match self.next_premaster() {
+                Some(mut block) => {
+                    for frame in &mut block {
+                        *frame *= self.gain;
+                    }
+                    self.clipped += block
+                        .iter()
+                        .map(|frame| {
+                            usize::from(frame.left.abs() > 1.) + usize::from(frame.right.abs() > 1.)
+                        })
+                        .sum::<usize>();
+                    self.master.push(block, &mut self.output);
+                }
+                None => {
+                    self.premaster_finished = true;
+                    self.master.finish(&mut self.output);
+                }
+            }
+        }
+
+        let len = self.block_size.min(self.output.len());
+        if len == 0 {
+            None
+        } else {
+            Some(self.output.drain(..len).collect())
+        }
+    }
+}
+
+/// Master effects, that run after the volume of the song
+enum MasterStage<'a> {
+    Streamed {
+        processor: Box<dyn BlockProcessor + 'a>,
+        /// Frames at the start of the output that are only latency, and get dropped
+        skip: usize,
+    },
+    /// The effects can't be streamed, so the whole song is kept until it finishes
+    Buffered {
+        effects: &'a EffectBundle,
+        sample_rate: u32,
+        input: Vec<Frame>,
+        /// Frames at the start of the output that were skipped, and get dropped
+        skip: usize,
+    },
+}
+impl<'a> MasterStage<'a> {
+    fn new(effects: &'a EffectBundle, sample_rate: u32) -> Self {
+        effects.processor_at(sample_rate).map_or_else(
+            || Self::Buffered {
+                effects,
+                sample_rate,
+                input: vec![],
+                skip: 0,
+            },
+            |processor| Self::Streamed {
+                skip: processor.latency(),
+                processor,
+            },
+        )
+    }
+
+    /// Runs the effects over `block` and adds the frames that are ready to `output`
+    fn push(&mut self, mut block: Vec<Frame>, output: &mut VecDeque<Frame>) {
+        match self {
+            Self::Streamed { processor, skip } => {
+                processor.process(&mut block);
+                let skipped = block.len().min(*skip);
+                *skip -= skipped;
+                output.extend(&block[skipped..]);
+            }
+            Self::Buffered { input, .. } => input.append(&mut block),
+        }
+    }
+
+    /// Runs the effects over `block` without outputting it, so it still affects what comes after
+    fn skip(&mut self, block: Vec<Frame>) {
+        match self {
+            Self::Streamed { skip, .. } | Self::Buffered { skip, .. } => *skip += block.len(),
+        }
+        self.push(block, &mut VecDeque::new());
+    }
+
+    /// Adds the frames that are left to `output`, once the song has finished
+    fn finish(&mut self, output: &mut VecDeque<Frame>) {
+        match self {
+            Self::Streamed { processor, skip } => {
+                // Push silence through, to get the frames that are still in the effects
+                let mut tail = vec![Frame::default(); processor.latency()];
+                processor.process(&mut tail);
+                let skipped = tail.len().min(*skip);
+                *skip -= skipped;
+                output.extend(&tail[skipped..]);
+            }
+            Self::Buffered {
+                effects,
+                sample_rate,
+                input,
+                skip,
+            } => {
+                let audio = effects.run_at(std::mem::take(input), *sample_rate);
+                output.extend(audio.into_iter().skip(*skip));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::effects::*;
+    use crate::frame::IntoFrames;
+    use crate::signals::noise::noise;
+    use crate::song::*;
+    use crate::track::Track;
+
+    #[test]
+    fn buffer_source_serves_blocks() {
+        let song = Song::new(vec![], SongConfig::default());
+        let mut source = BufferSource::new(vec![Frame::mono(1.); 10]);
+
+        let mut block = vec![Frame::default(); 4];
+        assert_eq!(4, source.next_block(&song, &mut block));
+        assert_eq!(4, source.next_block(&song, &mut block));
+        assert_eq!(2, source.next_block(&song, &mut block));
+        assert_eq!(0, source.next_block(&song, &mut block));
+
+        source.reset();
+        assert_eq!(10, collect_blocks(&mut source, &song, 3).len());
+    }
+
+    #[test]
+    fn blocks_have_the_requested_size() {
+        let mut song = Song::new(
+            vec![(|_song: &Song| vec![Frame::mono(0.5); 1000]).into()],
+            SongConfig::default(),
+        );
+
+        let blocks = song.render(44_100, 300).collect::<Vec<_>>();
+
+        assert_eq!(4, blocks.len());
+        assert_eq!(300, blocks[0].len());
+        assert_eq!(100, blocks[3].len());
+    }
+
+    #[test]
+    fn block_tracks_are_streamed() {
+        let track = TrackGenerator::blocks(BlockFn::new(
+            |_song: &Song, position: usize, block: &mut [Frame]| {
+                let len = block.len().min(1000 - position);
+                for (idx, frame) in block[..len].iter_mut().enumerate() {
+                    *frame = Frame::mono((position + idx) as f64 / 1000.);
+                }
+                len
+            },
+        ));
+        let mut song = Song::new(
+            vec![
+                track.into(),
+                (|_song: &Song| vec![Frame::mono(0.); 10]).into(),
+            ],
+            SongConfig::default(),
+        );
+
+        song.generate(44_100);
+        let audio = song.generated().unwrap();
+
+        assert_eq!(1000, audio.len());
+        assert_eq!(Frame::mono(0.5), audio[500]);
+    }
+
+    #[test]
+    fn block_tracks_stream_their_effects() {
+        let audio = noise(2000, 7).as_frames();
+        let delay = || Delay {
+            delay_time: 300,
+            feedback: Automation::Const(0.5),
+        };
+
+        let track = Track::new(TrackGenerator::blocks(BufferSource::new(audio.clone())))
+            .effects(EffectBundle(vec![Box::new(delay())]))
+            .gain(Automation::Const(0.5));
+        let mut song = Song::new(vec![track], SongConfig::default());
+
+        let rendered: Vec<Frame> = song.render(44_100, 128).flatten().collect();
+        let expected = audio
+            .effect(&delay())
+            .effect(&Volume {
+                mult: Automation::Const(0.5),
+            })
+            .effect(&Limiter::default());
+
+        assert_eq!(expected, rendered);
+    }
+
+    #[test]
+    fn rendering_matches_processing_the_whole_track() {
+        let volume = Automation::Loop(vec![0.2, 0.4, 0.6, 0.8]);
+        let config = SongConfig {
+            volume: volume.clone(),
+            duration: Duration::Samples(1500),
+            ..Default::default()
+        };
+        let track = |song: &Song| noise(song.seconds(0.02), 3).as_frames();
+        let mut song = Song::new(vec![track.into()], config);
+
+        let rendered: Vec<Frame> = song.render(44_100, 128).flatten().collect();
+        let expected = track(&song)
+            .effect(&Volume { mult: volume })
+            .take_samples(1500);
+
+        assert_eq!(expected, rendered);
+    }
+
+    #[test]
+    fn loud_songs_are_limited() {
+        let loud = |song: &Song| -> Vec<Frame> {
+            (0..song.seconds(0.1))
+                .map(|i| Frame::mono((i as f64 * 0.05).sin() * 2.))
+                .collect()
+        };
+        let mut song = Song::new(vec![loud.into()], SongConfig::default());
+        song.generate(44_100);
+
+        let ceiling = Limiter::default().ceiling;
+        let audio = song.generated().unwrap();
+        assert_eq!(song.seconds(0.1), audio.len());
+        assert!(audio
+            .iter()
+            .all(|frame| frame.left.abs() <= ceiling + 1e-12));
+        assert!(song.clipped_samples() > 0);
+
+        let mut quiet = Song::new(
+            vec![(|song: &Song| vec![Frame::mono(0.5); song.seconds(0.1)]).into()],
+            SongConfig::default(),
+        );
+        quiet.generate(44_100);
+        assert_eq!(0, quiet.clipped_samples());
+    }
+
+    #[test]
+    fn post_master_stems_go_through_the_master() {
+        let loud = |song: &Song| vec![Frame::mono(1.5); song.seconds(0.01)];
+        let mut song = Song::new(vec![loud.into()], SongConfig::default());
+
+        let ceiling = Limiter::default().ceiling;
+        let stems = song.stems(44_100, StemMode::PostMaster);
+        assert_eq!(song.generated().unwrap(), &stems[0].audio[..]);
+        assert!(stems[0]
+            .audio
+            .iter()
+            .all(|frame| frame.left.abs() <= ceiling + 1e-12));
+
+        let stems = song.stems(44_100, StemMode::PreMaster);
+        assert_eq!(Frame::mono(1.5), stems[0].audio[0]);
+    }
+
+    #[test]
+    fn master_effects_are_aligned_with_the_song() {
+        let track = |song: &Song| -> Vec<Frame> {
+            (0..song.seconds(0.02))
+                .map(|i| Frame::mono((i as f64 * 0.1).sin() * 1.5))
+                .collect()
+        };
+        let render = |master: EffectBundle| {
+            let config = SongConfig {
+                master,
+                ..Default::default()
+            };
+            let mut song = Song::new(vec![track.into()], config);
+            song.render(44_100, 100).flatten().collect::<Vec<Frame>>()
+        };
+
+        /// Limiter without a processor, so it gets applied to the whole song at once
+        struct Buffered(Limiter);
+        impl Effect for Buffered {
+            fn run(&self, input: Vec<Frame>) -> Vec<Frame> {
+                self.0.run(input)
+            }
+        }
+
+        let streamed = render(EffectBundle(vec![Box::new(Limiter::default())]));
+        let buffered = render(EffectBundle(vec![Box::new(Buffered(Limiter::default()))]));
+
+        assert_eq!(882, streamed.len());
+        assert_eq!(streamed, buffered);
+    }
+}
@@ -31,8 +31,21 @@ pub fn noise(length: usize, mut seed: u64) -> Vec<f64> {
         .collect()
 }
 
+use crate::song::Song;
 use crate::trigger::Frequency;
 use noise::{Fbm, NoiseFn, Seedable};
+use rand::Rng;
+
+/// Same as `noise`, with the seed taken from the random numbers of the current track, see `Song::rng`
+pub fn noise_for(song: &Song, length: usize) -> Vec<f64> {
+    noise(length, song.rng().gen())
+}
+
+/// Same as `fbm`, with the seed taken from the random numbers of the current track, see `Song::rng`
+pub fn fbm_for(song: &Song, length: usize, frequency: Frequency) -> Vec<f64> {
+    fbm(length, frequency, song.rng().gen())
+}
+
 pub fn fbm(length: usize, frequency: Frequency, seed: u32) -> Vec<f64> {
     let mut fbm = Fbm::new().set_seed(seed);
     fbm.frequency = frequency;
@@ -1,42 +1,108 @@
 use crate::{
-    effects::{Automation, EffectExtension, Volume},
+    bus::Bus,
+    effects::{Automation, EffectBundle, Limiter},
+    error::{Error, Result},
     frame::Frame,
-    helpers::*,
+    helpers::hashing::StableHasher,
+    loudness::{loudness_gain, Loudness},
     player::*,
-    sound_files::{enums::Metronome, io::*, Sound},
+    random::{TrackRng, TrackRngs},
+    region::{Region, Section},
+    render::*,
+    sound_files::{
+        cache::ResampleCache, downmix::Downmix, export::*, io::*, onsets::OnsetDetector, Sound,
+    },
+    tempo::{TempoChange, TempoMap},
+    time_signature::{Position, TimeSignature, TimeSignatureChange, TimeSignatureMap},
+    track::Track,
     vst::*,
 };
 
-use anyhow::Result;
 use std::collections::HashMap;
+use std::fs::create_dir_all;
+use std::hash::Hash;
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
 use std::path::Path;
 use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use std::sync::{mpsc, Arc, Mutex, PoisonError};
 use vst::host::PluginLoader;
 
+/// A loaded sound, which is None until the file has been opened
+type SoundEntry = Arc<Mutex<Option<Vec<Frame>>>>;
+
+/// Number of blocks that get rendered ahead of the player when streaming
+const STREAM_BUFFER_BLOCKS: usize = 32;
+
+type FallibleFn = dyn FnMut(&Song) -> Result<Vec<Frame>> + Send;
+
+/// Generates the audio of a track
+///
+/// Tracks get generated in parallel, so generators need to be `Send` and `Sync`
 pub enum TrackGenerator {
-    Fn(Box<dyn Fn(&Song) -> Vec<Frame>>),
-    FnMut(Box<Mutex<dyn FnMut(&Song) -> Vec<Frame>>>),
+    Fn(Box<dyn Fn(&Song) -> Vec<Frame> + Send + Sync>),
+    FnMut(Box<Mutex<dyn FnMut(&Song) -> Vec<Frame> + Send>>),
     Song(Box<Mutex<Song>>),
+    /// Generates the track one block at a time
+    Blocks(Box<Mutex<dyn BlockSource + Send>>),
+    /// Generator that can fail, see `TrackGenerator::fallible`
+    Fallible(Box<Mutex<FallibleFn>>),
 }
 impl TrackGenerator {
-    fn call(&self, song: &Song) -> Vec<Frame> {
+    /// Makes a track that gets rendered one block at a time
+    pub fn blocks(source: impl BlockSource + Send + 'static) -> Self {
+        Self::Blocks(Box::new(Mutex::from(source)))
+    }
+
+    /// Makes a track out of a generator that returns a `Result`
+    ///
+    /// If it fails, `Song::try_generate` will return the error along with the name of the track
+    pub fn fallible(f: impl FnMut(&Song) -> Result<Vec<Frame>> + Send + 'static) -> Self {
+        Self::Fallible(Box::new(Mutex::from(f)))
+    }
+
+    /// Generates the whole track
+    ///
+    /// Generators that can't return an error might still panic, like when a sound can't be opened.
+    /// The panic is returned as an error, so `Song::try_generate` can say which track failed
+    pub(crate) fn try_call(&self, song: &Song) -> Result<Vec<Frame>> {
         match self {
-            Self::Fn(f) => f(song),
+            Self::Fn(f) => catch_panic(|| f(song)),
             Self::FnMut(f) => {
-                let mut f = f.lock().unwrap();
-                f(song)
+                let mut f = f.lock().unwrap_or_else(PoisonError::into_inner);
+                catch_panic(|| f(song))
             }
             Self::Song(other) => {
                 let mut other = other.lock().unwrap();
-                other.generate(song.sample_rate.expect("Sample rate should have been set"));
-                other.generated.clone().unwrap()
+                other.try_generate(song.try_sample_rate()?)?;
+                Ok(other.generated.clone().unwrap())
+            }
+            Self::Blocks(source) => catch_panic(|| {
+                let mut source = source.lock().unwrap_or_else(PoisonError::into_inner);
+                source.reset();
+                collect_blocks(&mut *source, song, DEFAULT_BLOCK_SIZE)
+            }),
+            Self::Fallible(f) => {
+                let mut f = f.lock().unwrap();
+                f(song)
             }
         }
     }
 }
 
-impl<F: 'static + (FnMut(&Song) -> Vec<Frame>)> From<F> for TrackGenerator {
+/// Runs `f`, returning its panic as an error
+fn catch_panic(f: impl FnOnce() -> Vec<Frame>) -> Result<Vec<Frame>> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "The generator panicked".to_string());
+        Error::Custom(message)
+    })
+}
+
+impl<F: 'static + Send + (FnMut(&Song) -> Vec<Frame>)> From<F> for TrackGenerator {
     fn from(f: F) -> Self {
         Self::FnMut(Box::new(Mutex::from(f)))
     }
@@ -49,23 +115,72 @@ impl From<Song> for TrackGenerator {
 
 pub struct SongConfig {
     pub name: String,
+    /// Saved in the tags of exported files, along with the name
+    pub artist: String,
+    /// Seed for the random numbers of every track, see `Song::rng`
+    pub seed: u64,
+    /// Tempo at the start of the song
     pub bpm: f64,
+    /// Changes of tempo during the song
+    pub tempo_changes: Vec<TempoChange>,
+    /// Time signature at the start of the song
+    pub time_signature: TimeSignature,
+    /// Changes of time signature during the song
+    pub time_signature_changes: Vec<TimeSignatureChange>,
     pub volume: Automation<f64>,
+    /// Effects applied to the whole song after `volume`
+    ///
+    /// By default it has a `Limiter`, so the song doesn't clip
+    pub master: EffectBundle,
+    /// Integrated loudness in LUFS that the song gets normalized to when it's saved
+    ///
+    /// The mix is measured and the gain applied before `master`, so the limiter still keeps the peaks
+    /// under its ceiling. Master effects that change the level, like a limiter reducing the peaks,
+    /// leave the song that much away from the target
+    pub loudness_target: Option<f64>,
     pub duration: Duration,
 
     pub metronome: bool,
     pub loop_on_play: bool,
+
+    /// Named parts of the song, that can be rendered on their own with `generate_range`
+    pub sections: Vec<Section>,
+
+    /// Folder where tracks with `Track::cache` are saved, inside a folder named after the song
+    ///
+    /// If it's None, they are only cached in memory
+    pub track_cache_dir: Option<String>,
+
+    /// Where sounds are saved after being resampled to the sample rate of the song
+    pub resample_cache: ResampleCache,
+
+    /// Aux buses that tracks can send to, each with a different name
+    pub buses: Vec<Bus>,
 }
 impl Default for SongConfig {
     fn default() -> Self {
         Self {
             name: Default::default(),
+            artist: Default::default(),
+            seed: 0,
             bpm: 100.,
+            tempo_changes: vec![],
+            time_signature: TimeSignature::default(),
+            time_signature_changes: vec![],
             volume: Automation::Const(1.),
+            master: EffectBundle(vec![Box::new(Limiter::default())]),
+            loudness_target: None,
             duration: Duration::GeneratedTrack,
 
             metronome: false,
             loop_on_play: false,
+
+            sections: vec![],
+
+            track_cache_dir: Some("assets/processed/tracks".to_string()),
+            resample_cache: ResampleCache::default(),
+
+            buses: vec![],
         }
     }
 }
@@ -80,6 +195,7 @@ pub enum Duration {
     Samples(usize),
     Seconds(f64),
     Beats(f64),
+    Bars(f64),
 }
 impl Default for Duration {
     fn default() -> Self {
@@ -88,29 +204,50 @@ impl Default for Duration {
 }
 
 pub struct Song {
-    sample_rate: Option<u32>,
+    pub(crate) sample_rate: Option<u32>,
     /// Contains all of the loaded sounds used in a song
     /// Is wrapped in a Mutex so we can modify it with a non-mut reference
-    sounds: Mutex<HashMap<String, Vec<Frame>>>,
+    /// Each sound has its own lock, so different sounds can be loaded at the same time from different tracks
+    /// The key is the path and the downmix, since the same file can be mixed in different ways
+    sounds: Mutex<HashMap<(String, Downmix), SoundEntry>>,
     generated: Option<Vec<Frame>>,
+    /// Samples that went over 1 before the master effects, in the last generated song
+    clipped_samples: usize,
+    /// Audio of the tracks with `Track::cache`, by name, version and sample rate
+    track_cache: Mutex<HashMap<String, Vec<Frame>>>,
+    /// Random number generators of the tracks, which start again on every render
+    rngs: TrackRngs,
+    /// Part of the song that is being rendered, if it's not the whole song
+    range: Option<Range<usize>>,
 
     /// Shared VST host
     vst_host: Arc<Mutex<SimpleHost>>,
     vst_instances: Mutex<HashMap<PathBuf, Mutex<PluginLoader<SimpleHost>>>>,
 
-    tracks: Vec<TrackGenerator>,
-    config: SongConfig,
+    pub(crate) tracks: Vec<Track>,
+    pub(crate) config: SongConfig,
+    tempo: TempoMap,
+    time_signatures: TimeSignatureMap,
 }
 
 impl Song {
-    pub fn new(tracks: Vec<TrackGenerator>, config: SongConfig) -> Self {
+    pub fn new(tracks: Vec<Track>, config: SongConfig) -> Self {
         Self {
             sample_rate: None,
             sounds: Default::default(),
             generated: None,
+            clipped_samples: 0,
+            track_cache: Default::default(),
+            rngs: Default::default(),
+            range: None,
             vst_host: new_host(),
             vst_instances: Default::default(),
 
+            tempo: TempoMap::new(config.bpm, &config.tempo_changes),
+            time_signatures: TimeSignatureMap::new(
+                config.time_signature,
+                &config.time_signature_changes,
+            ),
             tracks,
             config,
         }
@@ -127,6 +264,7 @@ impl Song {
             Duration::Samples(a) => Some(a),
             Duration::Seconds(seconds) => Some(self.seconds(seconds)),
             Duration::Beats(beats) => Some(self.beats(beats)),
+            Duration::Bars(bars) => Some(self.bars(bars)),
         }
     }
 
@@ -134,6 +272,52 @@ impl Song {
         self.config.name.clone()
     }
 
+    /// Returns the samples that are being rendered, if only a region of the song was requested
+    ///
+    /// Generators can use it to skip the work outside of the range, but they should still
+    /// place their audio counting from the start of the song
+    pub fn range(&self) -> Option<Range<usize>> {
+        self.range.clone()
+    }
+
+    /// Returns the random number generator of the track that is being generated
+    ///
+    /// It's seeded from `SongConfig::seed` and the index of the track, and starts again
+    /// every time the song is rendered, so the song sounds the same every time.
+    /// Outside of a track, it returns a generator shared by the whole song
+    pub fn rng(&self) -> TrackRng {
+        self.rngs.current(self.config.seed)
+    }
+
+    /// Makes every track get the same random numbers again
+    pub(crate) fn reset_rngs(&self) {
+        self.rngs.reset();
+    }
+
+    /// Returns the section with the given name
+    pub fn section(&self, name: &str) -> Option<&Section> {
+        self.config
+            .sections
+            .iter()
+            .find(|section| section.name == name)
+    }
+
+    /// Returns the samples that `region` covers
+    pub fn region(&self, region: &Region) -> Result<Range<usize>> {
+        Ok(match region {
+            Region::Samples(start, end) => *start..*end,
+            Region::Seconds(start, end) => self.seconds(*start)..self.seconds(*end),
+            Region::Beats(start, end) => self.beats(*start)..self.beats(*end),
+            Region::Positions(start, end) => self.position(*start)..self.position(*end),
+            Region::Section(name) => {
+                let section = self
+                    .section(name)
+                    .ok_or_else(|| Error::SectionNotFound(name.clone()))?;
+                self.position(section.start)..self.position(section.end)
+            }
+        })
+    }
+
     /// Returns the number of samples in x seconds
     pub fn seconds(&self, x: f64) -> usize {
         (self.sample_rate.unwrap() as f64 * x) as usize
@@ -144,60 +328,271 @@ impl Song {
         (self.sample_rate.unwrap() as f64 * x * 0.001) as usize
     }
 
+    /// Returns the tempo at the start of the song
     pub const fn bpm(&self) -> f64 {
         self.config.bpm
     }
 
-    /// Returns the number of samples in x beats
+    /// Returns the tempo at beat `x`
+    pub fn bpm_at(&self, x: f64) -> f64 {
+        self.tempo.bpm_at(x)
+    }
+
+    pub const fn tempo(&self) -> &TempoMap {
+        &self.tempo
+    }
+
+    /// Returns the number of samples in x beats, counting from the start of the song
+    ///
+    /// This is also the sample at which beat x happens
     pub fn beats(&self, x: f64) -> usize {
-        let bps = self.config.bpm / 60.;
-        self.seconds(x / bps)
+        self.seconds(self.tempo.seconds_at(x))
+    }
+
+    /// Returns the number of samples in x beats, counting from beat `start`
+    pub fn beats_from(&self, start: f64, x: f64) -> usize {
+        self.beats(start + x) - self.beats(start)
+    }
+
+    /// Returns the beat that happens at `sample`
+    pub fn beat_at(&self, sample: usize) -> f64 {
+        self.tempo
+            .beat_at(sample as f64 / self.sample_rate() as f64)
+    }
+
+    pub const fn time_signatures(&self) -> &TimeSignatureMap {
+        &self.time_signatures
+    }
+
+    /// Returns the time signature of `bar`
+    pub fn time_signature_at(&self, bar: usize) -> TimeSignature {
+        self.time_signatures.time_signature_at(bar)
+    }
+
+    /// Returns the number of samples in x bars, counting from the start of the song
+    ///
+    /// This is also the sample at which bar x + 1 starts
+    pub fn bars(&self, x: f64) -> usize {
+        self.beats(self.time_signatures.bars_to_beats(x))
+    }
+
+    /// Returns the number of samples in x bars, counting from beat `start`
+    pub fn bars_from(&self, start: f64, x: f64) -> usize {
+        let bars = self.time_signatures.beats_to_bars(start) + x;
+        self.beats(self.time_signatures.bars_to_beats(bars)) - self.beats(start)
+    }
+
+    /// Returns the sample at which `beat` of `bar` happens. Both are counted from 1
+    pub fn at(&self, bar: usize, beat: usize) -> usize {
+        self.position(Position::new(bar, beat, 0))
+    }
+
+    /// Returns the sample at which `position` happens
+    pub fn position(&self, position: Position) -> usize {
+        self.beats(self.time_signatures.beat_of(position))
+    }
+
+    /// Returns the position of `sample`
+    pub fn position_at(&self, sample: usize) -> Position {
+        self.time_signatures.position_at(self.beat_at(sample))
+    }
+
+    /// Returns the audio of a track with `Track::cache`, calling `generate` only if it's not cached
+    ///
+    /// The cache in memory is checked first, and then the one on disk
+    pub(crate) fn cached_track(
+        &self,
+        name: &str,
+        version: &str,
+        generate: impl FnOnce() -> Result<Vec<Frame>>,
+    ) -> Result<Vec<Frame>> {
+        // Everything in the song that changes the audio of a track, besides the generator itself
+        let mut hasher = StableHasher::new();
+        version.hash(&mut hasher);
+        self.sample_rate().hash(&mut hasher);
+        self.config.bpm.to_bits().hash(&mut hasher);
+        format!("{:?}", self.config.tempo_changes).hash(&mut hasher);
+        format!("{:?}", self.config.time_signature).hash(&mut hasher);
+        format!("{:?}", self.config.time_signature_changes).hash(&mut hasher);
+        self.config.seed.hash(&mut hasher);
+
+        let key = format!("{}-{}", name.replace("/", "_"), hasher.to_hex());
+        if let Some(audio) = self.track_cache.lock().unwrap().get(&key) {
+            return Ok(audio.clone());
+        }
+
+        let path = self.config.track_cache_dir.as_ref().map(|dir| {
+            Path::new(dir)
+                .join(self.config.name.replace("/", "_"))
+                .join(format!("{}.frames", key))
+                .to_string_lossy()
+                .into_owned()
+        });
+
+        let audio = match &path {
+            Some(path) if Path::new(path).exists() => try_open_raw(path)?,
+            _ => {
+                let audio = generate()?;
+                if let Some(path) = &path {
+                    let folder = Path::new(path).parent().unwrap();
+                    create_dir_all(folder).map_err(Error::io(&folder.to_string_lossy()))?;
+                    try_save_raw(&audio, path)?;
+                }
+                audio
+            }
+        };
+
+        self.track_cache.lock().unwrap().insert(key, audio.clone());
+        Ok(audio)
+    }
+
+    /// Removes the tracks cached in memory, so they get read from disk or generated again
+    pub fn clear_track_cache(&mut self) {
+        self.track_cache.lock().unwrap().clear();
     }
 
     /// Loads a sound and saves it
     ///
     /// The file will only be opened the first time this method is called.
     /// Subsequent calls with the same sound will result in the vector being cloned from the hashmap
+    ///
+    /// # Panics
+    ///
+    /// Panics if the file can't be opened. See `try_sound` for a version that returns an error
     pub fn sound(&self, sound: impl Into<Sound>) -> Vec<Frame> {
-        assert!(self.sample_rate.is_some(), "Sample rate has not been set");
+        self.try_sound(sound)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
 
+    /// Loads a sound and saves it, returning an error if it can't be opened
+    ///
+    /// The slice, reverse, fades and gain of the sound are applied with the sample rate and tempo of the song
+    pub fn try_sound(&self, sound: impl Into<Sound>) -> Result<Vec<Frame>> {
+        let sample_rate = self.try_sample_rate()?;
         let sound: Sound = sound.into();
 
-        let mut sounds = self.sounds.lock().unwrap();
-
-        // If hashmap does not contain this audio, load it and insert it
-        if !sounds.contains_key(&sound.path) {
-            let vec = open_file(&sound.path, self.sample_rate.unwrap());
-            sounds.insert(sound.path.clone(), vec);
-        }
+        // Only hold the lock of the whole hashmap while getting the entry for this sound
+        let entry = Arc::clone(
+            self.sounds
+                .lock()
+                .unwrap()
+                .entry((sound.path.clone(), sound.downmix.clone()))
+                .or_default(),
+        );
 
         // If the audio is in the hashmap, return that
         // Else, load it and insert it in the hashmap
-        let vec = sounds.get(&sound.path).unwrap();
+        let mut entry = entry.lock().unwrap();
+        if entry.is_none() {
+            *entry = Some(self.config.resample_cache.try_open(
+                &sound.path,
+                sample_rate,
+                &sound.downmix,
+            )?);
+        }
+        let vec = entry.as_ref().unwrap();
 
         // Return only from begin to end
-        if let Some(end) = sound.end {
-            vec[sound.begin..end].to_vec()
-        } else {
-            vec[sound.begin..].to_vec()
+        let len = vec.len();
+        let file_rate = sound.file_sample_rate(self)?;
+        let begin = sound.begin.samples(self, len, file_rate);
+        let end = sound
+            .end
+            .map_or(len, |end| end.samples(self, len, file_rate));
+        if begin > end || end > len {
+            return Err(Error::SoundRange {
+                path: sound.path,
+                begin,
+                end,
+                len,
+            });
         }
+        let audio = vec[begin..end].to_vec();
+        drop(entry);
+        Ok(sound.process(audio, self, len, file_rate))
+    }
+
+    /// Splits a sound at its onsets, returning a slice for every hit
+    ///
+    /// The slices keep the rest of the settings of `sound`, with `begin` and `end` in samples,
+    /// so they can be used as the sounds of `SoundPattern`s
+    ///
+    /// # Panics
+    ///
+    /// Panics if the file can't be opened. See `try_slices` for a version that returns an error
+    pub fn slices(&self, sound: impl Into<Sound>, detector: &OnsetDetector) -> Vec<Sound> {
+        self.try_slices(sound, detector)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Splits a sound at its onsets, returning a slice for every hit
+    ///
+    /// The first slice always starts at the begin of `sound`, even if there's no hit there
+    pub fn try_slices(
+        &self,
+        sound: impl Into<Sound>,
+        detector: &OnsetDetector,
+    ) -> Result<Vec<Sound>> {
+        let sound: Sound = sound.into();
+        let file =
+            self.try_sound(Sound::from(sound.path.as_str()).downmix(sound.downmix.clone()))?;
+        let len = file.len();
+        let file_rate = sound.file_sample_rate(self)?;
+        let begin = sound.begin.samples(self, len, file_rate);
+        let end = sound
+            .end
+            .map_or(len, |end| end.samples(self, len, file_rate));
+        // Onsets are found in the audio before reversing, fading or changing the gain
+        let audio = file.get(begin..end).ok_or_else(|| Error::SoundRange {
+            path: sound.path.clone(),
+            begin,
+            end,
+            len,
+        })?;
+
+        let min_gap = self.seconds(detector.min_gap);
+        let mut points = vec![0];
+        points.extend(
+            detector
+                .detect(audio, self.sample_rate())
+                .into_iter()
+                .filter(|&onset| onset >= min_gap),
+        );
+        points.push(audio.len());
+
+        Ok(points
+            .windows(2)
+            .map(|points| {
+                sound
+                    .clone()
+                    .begin(begin + points[0])
+                    .end(begin + points[1])
+            })
+            .collect())
     }
 
     /// Returns a new instance of the plugin
     ///
     /// It reuses the loader, but returns a new instance
     pub fn get_new_plugin_instance<P: AsRef<Path>>(&self, path: P) -> VstPlugin {
+        self.try_get_new_plugin_instance(path)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Returns a new instance of the plugin, or an error if it can't be loaded
+    pub fn try_get_new_plugin_instance<P: AsRef<Path>>(&self, path: P) -> Result<VstPlugin> {
+        let sample_rate = self.try_sample_rate()?;
         let mut loaders = self.vst_instances.lock().unwrap();
 
         let path = path.as_ref().to_path_buf();
 
         if !loaders.contains_key(&path) {
-            let loader = load_plugin(&path, &self.vst_host);
+            let loader = try_load_plugin(&path, &self.vst_host)?;
             loaders.insert(path.clone(), Mutex::from(loader));
         }
 
         let mut loader = loaders.get(&path).unwrap().lock().unwrap();
-        VstPlugin::new(&mut loader, self.sample_rate())
+        VstPlugin::try_new(&mut loader, &path, sample_rate)
     }
 
     /// Returns the set sample rate
@@ -207,8 +602,13 @@ impl Song {
     /// Panics if the sample rate was not set before calling this song
     /// This should not be an issue if you call this method from a TrackGenerator
     pub fn sample_rate(&self) -> u32 {
-        self.sample_rate
-            .expect("Sample rate was not set before calling this method")
+        self.try_sample_rate()
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Returns the set sample rate, or an error if the song hasn't started generating
+    pub fn try_sample_rate(&self) -> Result<u32> {
+        self.sample_rate.ok_or(Error::SampleRateNotSet)
     }
 
     /// Returns a reference to the generated audio
@@ -217,6 +617,19 @@ impl Song {
         self.generated.as_deref()
     }
 
+    /// Number of samples of the generated song that went over 1 before the master effects
+    ///
+    /// With the default `Limiter` they don't clip, but it means the song is being limited
+    pub const fn clipped_samples(&self) -> usize {
+        self.clipped_samples
+    }
+
+    /// Measures the loudness of the generated song
+    /// None if it hasn't been generated yet
+    pub fn loudness(&self) -> Option<Loudness> {
+        Some(Loudness::measure(self.generated()?, self.sample_rate?))
+    }
+
     pub fn debug(&self, filename: &str, open_in_browser: bool) {
         if let Some(vec) = self.generated() {
             crate::debug::debug_frames_vec_web(vec, filename, open_in_browser)
@@ -226,89 +639,288 @@ impl Song {
     // Mutable methods
 
     /// Saves this song to a file
+    ///
+    /// The song is written as it's rendered, so it's never fully kept in memory.
+    /// If `loudness_target` is set, the whole song is generated first to measure it
     pub fn save_to_file(&mut self, bits_per_sample: u16, sample_rate: u32) {
-        self.generate(sample_rate);
-        save_file(
-            self.generated().unwrap(),
-            &format!("output/{}.wav", self.config.name),
-            self.sample_rate.unwrap(),
-            bits_per_sample,
+        self.try_save_to_file(bits_per_sample, sample_rate)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Saves this song to a file, returning an error if it fails to generate or be written
+    pub fn try_save_to_file(&mut self, bits_per_sample: u16, sample_rate: u32) -> Result<()> {
+        let options = ExportOptions::new(format!("output/{}.wav", self.config.name)).format(
+            ExportFormat::Int {
+                bits_per_sample,
+                dither: Dither::None,
+            },
         );
+        self.try_export(sample_rate, &options)
+    }
+
+    /// Renders the song at `sample_rate` and saves it with `options`
+    ///
+    /// Like `save_to_file`, the song is written as it's rendered when possible
+    pub fn export(&mut self, sample_rate: u32, options: &ExportOptions) {
+        self.try_export(sample_rate, options)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Renders the song at `sample_rate` and saves it with `options`, returning an error if it fails
+    ///
+    /// If the options don't have a title or artist, the ones in the `SongConfig` are used
+    pub fn try_export(&mut self, sample_rate: u32, options: &ExportOptions) -> Result<()> {
+        let mut options = options.clone();
+        if options.title.is_none() && !self.config.name.is_empty() {
+            options.title = Some(self.config.name.clone());
+        }
+        if options.artist.is_none() && !self.config.artist.is_empty() {
+            options.artist = Some(self.config.artist.clone());
+        }
+
+        let gain = match self.config.loudness_target {
+            Some(target) => {
+                let mix = self
+                    .try_render(sample_rate, DEFAULT_BLOCK_SIZE)?
+                    .collect_premaster();
+                loudness_gain(&mix, sample_rate, target)
+            }
+            None => 1.,
+        };
+
+        let mut renderer = self.try_render(sample_rate, DEFAULT_BLOCK_SIZE)?;
+        renderer.pre_master_gain(gain);
+        options.try_save_blocks(renderer, sample_rate)
+    }
+
+    /// Renders every track and bus separately
+    ///
+    /// All of the stems have the same length as the song, so they line up when imported together.
+    /// The mixdown also gets generated, and can be accessed with `generated`
+    pub fn stems(&mut self, sample_rate: u32, mode: StemMode) -> Vec<Stem> {
+        self.try_stems(sample_rate, mode)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Renders every track and bus separately, returning an error if any track fails
+    pub fn try_stems(&mut self, sample_rate: u32, mode: StemMode) -> Result<Vec<Stem>> {
+        let mut renderer = self.try_render(sample_rate, DEFAULT_BLOCK_SIZE)?;
+        renderer.record_stems(mode);
+
+        let mix = renderer.by_ref().flatten().collect();
+        let clipped = renderer.clipped_samples();
+        let stems = renderer.into_stems();
+
+        self.generated = Some(mix);
+        self.clipped_samples = clipped;
+        Ok(stems)
+    }
+
+    /// Saves every track and bus to its own file, inside `output/{song name}/`
+    pub fn save_stems(&mut self, bits_per_sample: u16, sample_rate: u32, mode: StemMode) {
+        self.try_save_stems(bits_per_sample, sample_rate, mode)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Saves every track and bus to its own file, returning an error if any of them fails
+    pub fn try_save_stems(
+        &mut self,
+        bits_per_sample: u16,
+        sample_rate: u32,
+        mode: StemMode,
+    ) -> Result<()> {
+        let folder = format!("output/{}", self.config.name);
+        create_dir_all(&folder).map_err(Error::io(&folder))?;
+
+        for stem in self.try_stems(sample_rate, mode)? {
+            let path = format!("{}/{}.wav", folder, stem.name.replace("/", "_"));
+            try_save_file(&stem.audio, &path, sample_rate, bits_per_sample)?;
+        }
+        Ok(())
     }
 
     /// Generate and start playing the song
     /// The sample rate will be chosen according to the output config from cpal
-    pub fn play(&mut self) -> Result<()> {
+    pub fn play(&mut self) -> anyhow::Result<()> {
         let config = get_player_config();
 
         self.generate(config.sample_rate);
 
         let player = Player {
-            audio: self.generated().unwrap().into(),
+            audio: PlayerAudio::Buffer(self.generated().unwrap().into()),
             cycle: self.config.loop_on_play,
         };
 
-        run_player(player, config)
+        run_player(player, config, || {})
+    }
+
+    /// Generates only `region` of the song and plays it
+    ///
+    /// If `loop_on_play` is set, the region is played in a loop
+    pub fn play_range(&mut self, region: impl Into<Region>) -> anyhow::Result<()> {
+        let config = get_player_config();
+
+        let audio = self.try_generate_range(config.sample_rate, region)?;
+
+        let player = Player {
+            audio: PlayerAudio::Buffer(audio.into()),
+            cycle: self.config.loop_on_play,
+        };
+
+        run_player(player, config, || {})
+    }
+
+    /// Starts playing the song while it's being rendered, instead of generating it fully first
+    ///
+    /// Only tracks made with `TrackGenerator::blocks` get rendered while playing, the rest are
+    /// generated before it starts. If the song takes longer to render than to play, there will be
+    /// gaps in the audio. `loop_on_play` is ignored, as the song is not kept in memory
+    pub fn play_streaming(&mut self) -> anyhow::Result<()> {
+        let config = get_player_config();
+
+        let (sender, receiver) = mpsc::sync_channel(STREAM_BUFFER_BLOCKS);
+        let mut blocks = self.render(config.sample_rate, DEFAULT_BLOCK_SIZE);
+
+        // Render a few blocks before starting, so we don't begin with a gap
+        for block in blocks.by_ref().take(STREAM_BUFFER_BLOCKS) {
+            sender.send(block)?;
+        }
+
+        let player = Player {
+            audio: PlayerAudio::Stream(receiver),
+            cycle: false,
+        };
+
+        run_player(player, config, move || {
+            for block in blocks {
+                // The player stopped, so there's no need to keep rendering
+                if sender.send(block).is_err() {
+                    break;
+                }
+            }
+        })
+    }
+
+    /// Starts rendering the song one block at a time
+    ///
+    /// Each item of the returned iterator has `block_size` frames, except maybe the last one.
+    /// Tracks generated with `TrackGenerator::blocks` only produce audio as it's requested,
+    /// the rest are generated fully before this returns
+    pub fn render(&mut self, sample_rate: u32, block_size: usize) -> SongRenderer<'_> {
+        self.try_render(sample_rate, block_size)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Starts rendering the song one block at a time, returning an error if a track fails
+    pub fn try_render(&mut self, sample_rate: u32, block_size: usize) -> Result<SongRenderer<'_>> {
+        if self.sample_rate != Some(sample_rate) {
+            self.generated = None;
+        }
+        self.sample_rate = Some(sample_rate);
+        self.range = None;
+
+        SongRenderer::try_new(self, block_size)
+    }
+
+    /// Starts rendering only `region` of the song, one block at a time
+    ///
+    /// The metronome, if enabled, plays its preroll right before the region.
+    /// `Song::range` returns the region until the song is rendered again
+    pub fn render_range(
+        &mut self,
+        sample_rate: u32,
+        block_size: usize,
+        region: impl Into<Region>,
+    ) -> SongRenderer<'_> {
+        self.try_render_range(sample_rate, block_size, region)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Starts rendering only `region` of the song, returning an error if a track fails
+    pub fn try_render_range(
+        &mut self,
+        sample_rate: u32,
+        block_size: usize,
+        region: impl Into<Region>,
+    ) -> Result<SongRenderer<'_>> {
+        if self.sample_rate != Some(sample_rate) {
+            self.generated = None;
+        }
+        self.sample_rate = Some(sample_rate);
+        self.range = Some(self.region(&region.into())?);
+
+        SongRenderer::try_new(self, block_size)
     }
 
     /// Generates the song and saves the value
+    ///
+    /// This renders all of the blocks and joins them, see `render` to get them one by one
     pub fn generate(&mut self, sample_rate: u32) {
+        self.try_generate(sample_rate)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Generates the song and saves the value, returning an error if any of the tracks fails
+    pub fn try_generate(&mut self, sample_rate: u32) -> Result<()> {
         // If it's already generated, and it's the same sample_rate, just exit
         if self.generated.is_some() {
             if let Some(prev_sample_rate) = self.sample_rate {
                 if prev_sample_rate == sample_rate {
-                    return;
+                    return Ok(());
                 }
             }
         }
 
         self.generated = None;
-        self.sample_rate = Some(sample_rate);
-
-        let mut tracks = vec![];
-        let generators = &self.tracks;
-        for track in generators {
-            tracks.push(track.call(self));
-        }
-
-        let tracks = join_tracks(tracks);
-
-        let mut vec = tracks
-            .effect(&Volume {
-                mult: self.config.volume.clone(),
-            })
-            // Add some delay in the front if we enable metronome
-            // This way we get like 3 beats of the metronome before we start
-            .delay(if self.config.metronome {
-                self.beats(3.)
-            } else {
-                0
-            });
 
-        if self.config.metronome {
-            let metronome = self
-                .sound(Metronome)
-                .take_samples(self.beats(0.2))
-                .chain(silence().take_samples(self.beats(0.8)))
-                .cycle_until_samples(vec.len());
-            vec = vec.add(&metronome);
-        }
+        let mut renderer = self.try_render(sample_rate, DEFAULT_BLOCK_SIZE)?;
+        let vec = renderer.by_ref().flatten().collect();
+        let clipped = renderer.clipped_samples();
+        drop(renderer);
 
-        // If a duration was specified, take that number of samples
-        if let Some(duration) = self.duration() {
-            vec = vec.take_samples(duration)
-        }
+        self.generated = Some(vec);
+        self.clipped_samples = clipped;
+        Ok(())
+    }
 
-        // Clamp song
-        vec = vec.iter().map(|a| a.clamp(-1., 1.)).collect();
+    /// Generates only `region` of the song and returns it
+    ///
+    /// The generated song is not saved, as it's only part of it
+    pub fn generate_range(&mut self, sample_rate: u32, region: impl Into<Region>) -> Vec<Frame> {
+        self.try_generate_range(sample_rate, region)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
 
-        self.generated = Some(vec);
+    /// Generates only `region` of the song, returning an error if any of the tracks fails
+    pub fn try_generate_range(
+        &mut self,
+        sample_rate: u32,
+        region: impl Into<Region>,
+    ) -> Result<Vec<Frame>> {
+        let audio = self
+            .try_render_range(sample_rate, DEFAULT_BLOCK_SIZE, region)
+            .map(|renderer| renderer.flatten().collect());
+        // The range only applies while rendering
+        self.range = None;
+        audio
     }
 }
 
+/// Song without tracks at 44.1kHz, for tests that only need its methods
+#[cfg(test)]
+pub(crate) fn test_song(config: SongConfig) -> Song {
+    let mut song = Song::new(vec![], config);
+    song.sample_rate = Some(44_100);
+    song
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::helpers::*;
+    use crate::sound_files::onsets::hits;
+    use crate::sound_files::SoundPosition;
+    use std::f64::consts::TAU;
 
     #[test]
     fn can_create_song() {
@@ -372,37 +984,35 @@ mod test {
         let path = "assets/examples/beep.wav";
         let _ = song.sound(path);
 
-        assert!(song.sounds.lock().unwrap().contains_key(path));
+        let key = (path.to_string(), Downmix::Auto);
+        assert!(song.sounds.lock().unwrap().contains_key(&key));
     }
 
     #[test]
     /// When loading a sound, it should get added to the hashmap so we don't load it twice
     fn can_get_sound_from_begin_to_end() {
-        let mut song = Song::new(vec![], SongConfig::default());
-        song.sample_rate = Some(44_100);
+        let song = test_song(SongConfig::default());
 
         let path = "assets/examples/beep.wav";
-        let audio = song.sound(Sound {
-            path: path.to_string(),
-            begin: 13,
-            end: Some(23),
-        });
+        let audio = song.sound(Sound::from(path).begin(13).end(23));
 
         // Returned audio is the correct slice
         assert_eq!(10, audio.len());
 
         // Vec gets still added to hashmap
-        assert!(song.sounds.lock().unwrap().contains_key(path));
+        let key = (path.to_string(), Downmix::Auto);
+        assert!(song.sounds.lock().unwrap().contains_key(&key));
         // Vec in hashmap has the full length of the audio
-        assert_eq!(441000, song.sounds.lock().unwrap().get(path).unwrap().len());
+        let sounds = song.sounds.lock().unwrap();
+        let sound = sounds.get(&key).unwrap().lock().unwrap();
+        assert_eq!(441000, sound.as_ref().unwrap().len());
         // Length of the beep.wav audio is 441000
     }
 
     #[test]
     #[should_panic]
     fn opening_nonexistant_sound_panics() {
-        let mut song = Song::new(vec![], SongConfig::default());
-        song.sample_rate = Some(44_100);
+        let song = test_song(SongConfig::default());
 
         let path = "assets/filethatdoesntexist.aaaaaaaaaaaaaa";
         let _ = song.sound(path);
@@ -424,6 +1034,593 @@ mod test {
         assert_eq!(23, audio.len());
     }
 
+    #[test]
+    fn song_can_be_shared_between_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Song>();
+        assert_send_sync::<TrackGenerator>();
+    }
+
+    #[test]
+    fn parallel_generation_is_the_same_as_serial() {
+        let tracks: Vec<Track> = (0..16)
+            .map(|i| {
+                (move |song: &Song| {
+                    crate::signals::noise::noise(song.seconds(0.01) * (i + 1), i as u64)
+                        .into_iter()
+                        .map(|val| Frame::mono(val * 0.05))
+                        .collect()
+                })
+                .into()
+            })
+            .collect();
+        let mut song = Song::new(tracks, SongConfig::default());
+        song.generate(44_100);
+
+        let serial = join_tracks(
+            song.tracks
+                .iter()
+                .map(|track| track.generator.try_call(&song).unwrap())
+                .collect(),
+        );
+        assert_eq!(serial, song.generated().unwrap());
+    }
+
+    #[test]
+    fn same_sound_can_be_loaded_from_multiple_tracks() {
+        let path = "assets/examples/beep.wav";
+        let tracks: Vec<Track> = (0..8)
+            .map(|_| (move |song: &Song| song.sound(path).take_samples(100)).into())
+            .collect();
+        let mut song = Song::new(tracks, SongConfig::default());
+        song.generate(44_100);
+
+        assert_eq!(1, song.sounds.lock().unwrap().len());
+        assert_eq!(100, song.generated().unwrap().len());
+    }
+
+    #[test]
+    fn loudness_is_normalized_before_the_limiter() {
+        let config = SongConfig {
+            loudness_target: Some(0.),
+            ..Default::default()
+        };
+        let mut song = Song::new(
+            vec![Track::new(|song: &Song| {
+                (0..song.seconds(1.))
+                    .map(|i| Frame::mono((i as f64 * TAU * 440. / 44_100.).sin() * 0.5))
+                    .collect()
+            })],
+            config,
+        );
+        let path = std::env::temp_dir().join("dawremi-loudness-target.wav");
+        let path = path.to_str().unwrap();
+        song.export(
+            44_100,
+            &ExportOptions::new(path).format(ExportFormat::Float),
+        );
+
+        let samples: Vec<f32> = hound::WavReader::open(path)
+            .unwrap()
+            .samples()
+            .map(|sample| sample.unwrap())
+            .collect();
+        let ceiling = Limiter::default().ceiling;
+        assert!(samples
+            .iter()
+            .all(|&sample| f64::from(sample.abs()) <= ceiling + 1e-6));
+        assert!(samples.iter().any(|&sample| f64::from(sample) > 0.85));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn exported_songs_reach_the_loudness_target() {
+        let config = SongConfig {
+            loudness_target: Some(-20.),
+            ..Default::default()
+        };
+        let mut song = Song::new(
+            vec![Track::new(|song: &Song| {
+                (0..song.seconds(1.))
+                    .map(|i| Frame::mono((i as f64 * TAU * 440. / 44_100.).sin() * 0.5))
+                    .collect()
+            })],
+            config,
+        );
+        let path = std::env::temp_dir().join("dawremi-loudness-measured.wav");
+        let path = path.to_str().unwrap();
+        song.export(
+            44_100,
+            &ExportOptions::new(path).format(ExportFormat::Float),
+        );
+
+        let audio: Vec<Frame> = hound::WavReader::open(path)
+            .unwrap()
+            .samples::<f32>()
+            .map(|sample| f64::from(sample.unwrap()))
+            .collect::<Vec<_>>()
+            .chunks(2)
+            .map(|frame| Frame::new(frame[0], frame[1]))
+            .collect();
+        let integrated = Loudness::measure(&audio, 44_100).integrated;
+        assert!((integrated + 20.).abs() < 0.5, "{}", integrated);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn stems_are_aligned_and_add_up_to_the_mix() {
+        let config = SongConfig {
+            volume: Automation::Const(0.5),
+            ..Default::default()
+        };
+        let mut song = Song::new(
+            vec![
+                Track::new(|_song: &Song| vec![Frame::mono(0.2); 100]).name("long"),
+                Track::new(|_song: &Song| vec![Frame::mono(0.4); 10]),
+            ],
+            config,
+        );
+
+        let stems = song.stems(44_100, StemMode::PostMaster);
+        let mix = song.generated().unwrap();
+
+        assert_eq!(2, stems.len());
+        assert_eq!("long", stems[0].name);
+        assert_eq!("Track 2", stems[1].name);
+        for stem in &stems {
+            assert_eq!(mix.len(), stem.audio.len());
+        }
+        for (idx, frame) in mix.iter().enumerate() {
+            assert_eq!(*frame, stems[0].audio[idx] + stems[1].audio[idx]);
+        }
+
+        let stems = song.stems(44_100, StemMode::PreMaster);
+        assert_eq!(Frame::mono(0.4), stems[1].audio[0]);
+        assert_eq!(Frame::default(), stems[1].audio[50]);
+    }
+
+    #[test]
+    fn missing_sounds_return_an_error() {
+        let mut song = Song::new(vec![], SongConfig::default());
+        assert!(matches!(
+            song.try_sound("assets/nonexistant.wav"),
+            Err(Error::SampleRateNotSet)
+        ));
+
+        song.sample_rate = Some(44_100);
+        assert!(matches!(
+            song.try_sound("assets/nonexistant.wav"),
+            Err(Error::Wav { .. })
+        ));
+        assert!(matches!(
+            song.try_sound(
+                Sound::from("assets/examples/beep.wav")
+                    .begin(10)
+                    .end(500_000)
+            ),
+            Err(Error::SoundRange { len: 441000, .. })
+        ));
+    }
+
+    #[test]
+    fn sounds_can_be_sliced_in_time_units() {
+        let song = test_song(SongConfig::default());
+        let path = "assets/examples/beep.wav";
+
+        let sound = Sound::from(path)
+            .begin(SoundPosition::Seconds(1.))
+            .end(SoundPosition::Fraction(0.2));
+        assert_eq!(44_100, song.sound(sound).len());
+
+        // At 100bpm a beat is 0.6 seconds
+        let sound = Sound::from(path).end(SoundPosition::Beats(1.));
+        assert_eq!(26_460, song.sound(sound).len());
+    }
+
+    #[test]
+    fn sounds_can_be_sliced_at_samples_of_the_file() {
+        let folder =
+            std::env::temp_dir().join(format!("dawremi-file-samples-{}", std::process::id()));
+        let config = SongConfig {
+            resample_cache: ResampleCache::new(&folder),
+            ..Default::default()
+        };
+        let mut song = Song::new(vec![], config);
+        song.sample_rate = Some(22_050);
+        let path = "assets/examples/beep.wav";
+
+        // The file is at 44.1kHz, so its samples are twice as short as the ones of the song
+        let sound = Sound::from(path)
+            .begin(SoundPosition::FileSamples(44_100))
+            .end(SoundPosition::FileSamples(88_200));
+        assert_eq!(22_050, song.sound(sound).len());
+        assert_eq!(1_000, song.sound(Sound::from(path).end(1_000)).len());
+
+        // Sounds can still be compared and kept in sets
+        let sounds: std::collections::BTreeSet<Sound> =
+            vec![Sound::from(path).gain(0.5), Sound::from(path).gain(0.5)]
+                .into_iter()
+                .collect();
+        assert_eq!(1, sounds.len());
+
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn sounds_can_be_reversed_faded_and_gained() {
+        let song = test_song(SongConfig::default());
+        let path = "assets/examples/beep.wav";
+
+        let original = song.sound(Sound::from(path).begin(1_000).end(1_100));
+        let audio = song.sound(
+            Sound::from(path)
+                .begin(1_000)
+                .end(1_100)
+                .reverse()
+                .gain(0.5)
+                .fade_in(10)
+                .fade_out(20),
+        );
+
+        assert_eq!(100, audio.len());
+        assert_eq!(Frame::default(), audio[0]);
+        assert_eq!(original[94] * 0.5 * 0.5, audio[5]);
+        assert_eq!(original[49] * 0.5, audio[50]);
+        assert_eq!(original[10] * 0.5 * 0.5, audio[89]);
+        assert_eq!(Frame::default(), audio[99]);
+    }
+
+    #[test]
+    fn sounds_can_pick_channels() {
+        let song = test_song(SongConfig::default());
+
+        let path = std::env::temp_dir().join("dawremi-three-channels.wav");
+        let path = path.to_str().unwrap();
+        let spec = hound::WavSpec {
+            channels: 3,
+            sample_rate: 44_100,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut writer = hound::WavWriter::create(path, spec).unwrap();
+        for sample in &[0.25_f32, 0.5, -0.5, 0.75, 1., -1.] {
+            writer.write_sample(*sample).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let sound = Sound::from(path).downmix(Downmix::Channels { left: 2, right: 0 });
+        assert_eq!(
+            vec![Frame::new(-0.5, 0.25), Frame::new(-1., 0.75)],
+            song.sound(sound)
+        );
+        assert_eq!(
+            vec![Frame::mono(0.5), Frame::mono(1.)],
+            song.sound(Sound::from(path).downmix(Downmix::channel(1)))
+        );
+        assert!(matches!(
+            song.try_sound(Sound::from(path).downmix(Downmix::surround())),
+            Err(Error::Downmix { channels: 3, .. })
+        ));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn sounds_can_be_sliced_at_their_onsets() {
+        let song = test_song(SongConfig::default());
+
+        // Hits every quarter of a second
+        let starts = [0, 11_025, 22_050, 33_075];
+        let audio: Vec<Frame> = hits(&starts).into_iter().map(|frame| frame * 0.5).collect();
+        let path = std::env::temp_dir().join("dawremi-breakbeat.wav");
+        let path = path.to_str().unwrap();
+        try_save_file(&audio, path, 44_100, 24).unwrap();
+
+        let slices = song.slices(Sound::from(path).gain(0.5), &OnsetDetector::default());
+        assert_eq!(4, slices.len());
+        assert_eq!(SoundPosition::Samples(0), slices[0].begin);
+        assert_eq!(Some(SoundPosition::Samples(44_100)), slices[3].end);
+        for (slice, start) in slices.iter().zip(&starts) {
+            match slice.begin {
+                SoundPosition::Samples(begin) => assert!(begin.abs_diff(*start) <= 64),
+                _ => panic!("Slices should begin in samples"),
+            }
+            assert_eq!(0.5, slice.gain);
+        }
+        for pair in slices.windows(2) {
+            assert_eq!(pair[0].end, Some(pair[1].begin));
+        }
+
+        // Slices of a part of the file are relative to the start of the file
+        let slices = song.slices(
+            Sound::from(path).begin(SoundPosition::Seconds(0.5)),
+            &OnsetDetector::default(),
+        );
+        assert_eq!(2, slices.len());
+        assert_eq!(SoundPosition::Samples(22_050), slices[0].begin);
+        assert_eq!(Some(SoundPosition::Samples(44_100)), slices[1].end);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn track_errors_say_which_track_failed() {
+        let mut song = Song::new(
+            vec![
+                Track::new(|_song: &Song| vec![Frame::default(); 100]).name("fine"),
+                Track::new(TrackGenerator::fallible(|song: &Song| {
+                    song.try_sound("assets/nonexistant.wav")
+                }))
+                .name("broken"),
+            ],
+            SongConfig::default(),
+        );
+
+        match song.try_generate(44_100) {
+            Err(Error::Track { name, source }) => {
+                assert_eq!("broken", name);
+                assert!(
+                    matches!(*source, Error::Wav { path, .. } if path == "assets/nonexistant.wav")
+                );
+            }
+            _ => panic!("Generating should have failed"),
+        }
+        assert!(song.generated().is_none());
+    }
+
+    #[test]
+    fn panicking_tracks_say_which_track_failed() {
+        let mut song = Song::new(
+            vec![
+                Track::new(|_song: &Song| vec![Frame::default(); 100]).name("fine"),
+                Track::new(|song: &Song| song.sound("assets/nonexistant.wav")).name("broken"),
+            ],
+            SongConfig::default(),
+        );
+
+        match song.try_generate(44_100) {
+            Err(Error::Track { name, source }) => {
+                assert_eq!("broken", name);
+                assert!(
+                    matches!(*source, Error::Custom(message) if message.contains("assets/nonexistant.wav"))
+                );
+            }
+            _ => panic!("Generating should have failed"),
+        }
+    }
+
+    #[test]
+    fn sample_rate_is_an_error_before_generating() {
+        let mut song = Song::new(vec![], SongConfig::default());
+        assert!(matches!(
+            song.try_sample_rate(),
+            Err(Error::SampleRateNotSet)
+        ));
+        song.sample_rate = Some(44_100);
+        assert_eq!(44_100, song.try_sample_rate().unwrap());
+    }
+
+    #[test]
+    fn beats_follow_tempo_changes() {
+        let config = SongConfig {
+            bpm: 120.,
+            tempo_changes: vec![TempoChange::jump(4., 60.)],
+            duration: Duration::Beats(6.),
+            ..Default::default()
+        };
+        let song = test_song(config);
+
+        assert_eq!(44_100 * 2, song.beats(4.));
+        assert_eq!(44_100 * 4, song.beats(6.));
+        assert_eq!(44_100, song.beats_from(4., 1.));
+        assert_eq!(Some(44_100 * 4), song.duration());
+        assert!((song.beat_at(44_100 * 3) - 5.).abs() < 1e-9);
+        assert_eq!(60., song.bpm_at(5.));
+    }
+
+    #[test]
+    fn triggers_follow_tempo_changes() {
+        use crate::trigger::*;
+
+        let config = SongConfig {
+            bpm: 120.,
+            tempo_changes: vec![TempoChange::jump(1., 60.)],
+            ..Default::default()
+        };
+        let song = test_song(config);
+
+        let mut lengths = vec![];
+        vec![440_f64.beats(1.), 440_f64.beats(1.)].generate(
+            &song,
+            &mut |_freq, length| {
+                lengths.push(length);
+                vec![Frame::default(); length]
+            },
+            crate::signals::adsr::Adsr::default(),
+        );
+
+        assert_eq!(vec![22_050, 44_100], lengths);
+    }
+
+    #[test]
+    fn triggers_can_start_later_in_the_song() {
+        use crate::trigger::*;
+
+        let config = SongConfig {
+            bpm: 120.,
+            tempo_changes: vec![TempoChange::jump(1., 60.)],
+            ..Default::default()
+        };
+        let song = test_song(config);
+
+        let mut lengths = vec![];
+        vec![440_f64.beats(1.), 440_f64.beats(1.)].generate_from(
+            &song,
+            1.,
+            &mut |_freq, length| {
+                lengths.push(length);
+                vec![Frame::default(); length]
+            },
+            crate::signals::adsr::Adsr::default(),
+        );
+
+        assert_eq!(vec![44_100, 44_100], lengths);
+    }
+
+    #[test]
+    fn metronome_clicks_on_every_beat() {
+        let config = SongConfig {
+            bpm: 120.,
+            tempo_changes: vec![TempoChange::jump(2., 60.)],
+            metronome: true,
+            ..Default::default()
+        };
+        let mut song = Song::new(
+            vec![(|song: &Song| vec![Frame::default(); song.beats(4.)]).into()],
+            config,
+        );
+        // Same sample rate as the click, so it doesn't get resampled
+        song.generate(24_000);
+        let audio = song.generated().unwrap();
+        let is_silent = |range: std::ops::Range<usize>| {
+            audio[range].iter().all(|&frame| frame == Frame::default())
+        };
+
+        // The preroll is 3 beats at 120 bpm, then beats at 0, 0.5, 1 and 2 seconds
+        let preroll = 3 * 12_000;
+        for &click in &[0, 12_000, 24_000, 48_000] {
+            assert!(!is_silent(preroll + click..preroll + click + 100));
+            assert!(is_silent(preroll + click + 3_000..preroll + click + 3_100));
+        }
+    }
+
+    #[test]
+    fn positions_follow_time_signature_changes() {
+        let config = SongConfig {
+            bpm: 120.,
+            time_signature: TimeSignature::new(3, 4),
+            time_signature_changes: vec![TimeSignatureChange::new(3, TimeSignature::new(6, 8))],
+            duration: Duration::Bars(3.),
+            ..Default::default()
+        };
+        let song = test_song(config);
+
+        // Two bars of 3 beats, then bars of 3 beats split in eighth notes
+        assert_eq!(song.beats(6.), song.bars(2.));
+        assert_eq!(song.beats(9.), song.bars(3.));
+        assert_eq!(song.beats(7.5), song.at(3, 4));
+        assert_eq!(song.beats(4.), song.at(2, 2));
+        assert_eq!(Some(song.beats(9.)), song.duration());
+        assert_eq!(Position::new(3, 4, 0), song.position_at(song.beats(7.5)));
+        assert_eq!(song.beats_from(1.5, 3.), song.bars_from(1.5, 1.));
+    }
+
+    #[test]
+    fn metronome_accents_downbeats() {
+        let config = SongConfig {
+            bpm: 120.,
+            time_signature: TimeSignature::new(3, 4),
+            metronome: true,
+            master: EffectBundle(vec![]),
+            ..Default::default()
+        };
+        let mut song = Song::new(
+            vec![(|song: &Song| vec![Frame::default(); song.bars(2.)]).into()],
+            config,
+        );
+        song.generate(24_000);
+        let audio = song.generated().unwrap();
+        let loudness = |start: usize| {
+            audio[start..start + 2_000]
+                .iter()
+                .map(|frame| frame.left.abs())
+                .sum::<f64>()
+        };
+
+        let preroll = 3 * 12_000;
+        let downbeat = loudness(preroll);
+        let beat = loudness(preroll + 12_000);
+        assert!((downbeat / beat - 2.).abs() < 1e-6);
+        assert!((downbeat - loudness(preroll + 36_000)).abs() < 1e-6);
+        assert!((beat - loudness(preroll + 24_000)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn ranges_match_the_full_song() {
+        use crate::effects::*;
+
+        let make_song = || {
+            let config = SongConfig {
+                bpm: 120.,
+                sections: vec![Section::new("bridge", Position::bar(2), Position::bar(3))],
+                master: EffectBundle(vec![]),
+                ..Default::default()
+            };
+            Song::new(
+                vec![Track::new(|song: &Song| {
+                    (0..song.bars(4.))
+                        .map(|i| Frame::mono((i % 100) as f64 / 200.))
+                        .collect()
+                })
+                .effects(EffectBundle(vec![Box::new(Delay {
+                    delay_time: 300,
+                    feedback: Automation::Const(0.5),
+                })]))],
+                config,
+            )
+        };
+
+        let mut song = make_song();
+        song.generate(1_000);
+        let full = song.generated().unwrap().to_vec();
+
+        // A bar at 120 bpm lasts 2 seconds
+        let bridge = make_song().generate_range(1_000, "bridge");
+        assert_eq!(&full[2_000..4_000], &bridge[..]);
+
+        let region = make_song().generate_range(1_000, Region::Samples(1_234, 5_678));
+        assert_eq!(&full[1_234..5_678], &region[..]);
+    }
+
+    #[test]
+    fn generators_know_the_range() {
+        let mut song = Song::new(
+            vec![Track::new(|song: &Song| {
+                let range = song.range().unwrap_or_else(|| 0..song.beats(4.));
+                let mut audio = vec![Frame::default(); range.end];
+                for frame in &mut audio[range] {
+                    *frame = Frame::mono(1.);
+                }
+                audio
+            })],
+            SongConfig {
+                master: EffectBundle(vec![]),
+                ..Default::default()
+            },
+        );
+
+        let audio = song.generate_range(1_000, Region::Beats(1., 2.));
+        assert_eq!(song.beats_from(1., 1.), audio.len());
+        assert!(audio.iter().all(|&frame| frame == Frame::mono(1.)));
+        assert_eq!(None, song.range());
+
+        // Full renders don't have a range
+        song.generate(1_000);
+        assert_eq!(song.beats(4.), song.generated().unwrap().len());
+    }
+
+    #[test]
+    fn missing_sections_are_an_error() {
+        let mut song = Song::new(
+            vec![(|_song: &Song| vec![Frame::default(); 10]).into()],
+            SongConfig::default(),
+        );
+
+        assert!(matches!(
+            song.try_generate_range(1_000, "chorus"),
+            Err(Error::SectionNotFound(name)) if name == "chorus"
+        ));
+    }
+
     #[test]
     fn can_compose_songs() {
         let inner_song = Song::new(
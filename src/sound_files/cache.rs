@@ -0,0 +1,365 @@
+//! Cache of resampled audio files on disk
+//!
+//! Entries are named after a hash of the content of the file, the sample rate, the resampler quality
+//! and the downmix, so they stay valid when files are moved or checked out again.
+//! When the cache gets bigger than its size limit, the entries that were used the longest time ago are removed
+
+use crate::error::{Error, Result};
+use crate::frame::Frame;
+use crate::helpers::resampling::{resample_frames_with, ResampleQuality};
+use crate::sound_files::decode::FileFormat;
+use crate::sound_files::downmix::Downmix;
+use crate::sound_files::io::{try_decode_file, try_file_sample_rate, try_open_raw, try_save_raw};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+
+/// Folder used by `ResampleCache::default`
+pub const DEFAULT_CACHE_DIR: &str = "assets/processed/resampled";
+/// Size limit used by `ResampleCache::default`, 1GiB
+pub const DEFAULT_CACHE_SIZE: u64 = 1 << 30;
+
+const EXTENSION: &str = "frames";
+
+/// Entries being saved by this process, to give each one a different temporary name
+static PARTIAL_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Opens audio files, saving the resampled ones so they are only resampled once
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResampleCache {
+    dir: PathBuf,
+    max_size: Option<u64>,
+    quality: ResampleQuality,
+}
+
+impl Default for ResampleCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_CACHE_DIR)
+    }
+}
+
+/// A resampled file saved in the cache
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CacheEntry {
+    pub path: PathBuf,
+    /// Size in bytes
+    pub size: u64,
+    pub last_used: SystemTime,
+}
+
+impl ResampleCache {
+    /// Cache saved in `dir`, with a limit of `DEFAULT_CACHE_SIZE` and linear resampling
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            max_size: Some(DEFAULT_CACHE_SIZE),
+            quality: ResampleQuality::default(),
+        }
+    }
+
+    /// Sets the size limit in bytes, or removes it with `None`
+    pub const fn max_size(mut self, max_size: Option<u64>) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Sets the interpolation used when resampling
+    pub const fn quality(mut self, quality: ResampleQuality) -> Self {
+        self.quality = quality;
+        self
+    }
+
+    /// Folder where the entries are saved
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Opens an audio file, mixing its channels with `downmix` and resampling it to `sample_rate` if needed
+    ///
+    /// # Panics
+    ///
+    /// Panics if the file can't be opened. See `try_open` for a version that returns an error
+    pub fn open(&self, path: &str, sample_rate: u32, downmix: &Downmix) -> Vec<Frame> {
+        self.try_open(path, sample_rate, downmix)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Opens an audio file, mixing its channels with `downmix` and resampling it to `sample_rate` if needed
+    pub fn try_open(&self, path: &str, sample_rate: u32, downmix: &Downmix) -> Result<Vec<Frame>> {
+        let format = FileFormat::detect(path)?;
+
+        // Files that already have the sample rate are read directly
+        if try_file_sample_rate(path, format)? == sample_rate {
+            return try_decode_file(path, format, downmix).map(|(frames, _)| frames);
+        }
+
+        let content = fs::read(path).map_err(Error::io(path))?;
+        let entry = self.entry_path(&content, sample_rate, downmix);
+        if let Ok(frames) = try_open_raw(&entry.to_string_lossy()) {
+            // The modification time marks when the entry was last used
+            if let Ok(file) = File::options().write(true).open(&entry) {
+                let _ = file.set_modified(SystemTime::now());
+            }
+            return Ok(frames);
+        }
+
+        let (orig, file_sample_rate) = try_decode_file(path, format, downmix)?;
+        let frames = resample_frames_with(orig, file_sample_rate, sample_rate, self.quality);
+
+        // Saved with a name no other writer uses first, so other threads and processes never read half of an entry
+        let dir = self.dir.to_string_lossy();
+        fs::create_dir_all(&self.dir).map_err(Error::io(&dir))?;
+        let partial = entry.with_extension(format!(
+            "{}-{}.partial",
+            std::process::id(),
+            PARTIAL_COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let partial_path = partial.to_string_lossy();
+        try_save_raw(&frames, &partial_path)?;
+        if let Err(err) = fs::rename(&partial, &entry) {
+            let _ = fs::remove_file(&partial);
+            // Another writer saved the same entry first
+            if !entry.exists() {
+                return Err(Error::io(&partial_path)(err));
+            }
+        }
+
+        self.try_evict()?;
+        Ok(frames)
+    }
+
+    fn entry_path(&self, content: &[u8], sample_rate: u32, downmix: &Downmix) -> PathBuf {
+        let hash = blake3::hash(content).to_hex();
+        let mut name = format!("{}-{}-{}", &hash[..32], sample_rate, self.quality.name());
+        if let Some(suffix) = downmix.file_suffix() {
+            name.push('-');
+            name.push_str(&suffix);
+        }
+        self.dir.join(name).with_extension(EXTENSION)
+    }
+
+    /// Lists the entries in the cache, from the least to the most recently used
+    ///
+    /// # Panics
+    ///
+    /// Panics if the folder can't be read. See `try_entries` for a version that returns an error
+    pub fn entries(&self) -> Vec<CacheEntry> {
+        self.try_entries().unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Lists the entries in the cache, from the least to the most recently used
+    ///
+    /// A folder that doesn't exist yet has no entries
+    pub fn try_entries(&self) -> Result<Vec<CacheEntry>> {
+        let dir = self.dir.to_string_lossy();
+        let read_dir = match fs::read_dir(&self.dir) {
+            Ok(read_dir) => read_dir,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(Error::io(&dir)(err)),
+        };
+
+        let mut entries = vec![];
+        for file in read_dir {
+            let path = file.map_err(Error::io(&dir))?.path();
+            if path.extension().is_some_and(|ext| ext == EXTENSION) {
+                let metadata = fs::metadata(&path).map_err(Error::io(&path.to_string_lossy()))?;
+                entries.push(CacheEntry {
+                    size: metadata.len(),
+                    last_used: metadata
+                        .modified()
+                        .map_err(Error::io(&path.to_string_lossy()))?,
+                    path,
+                });
+            }
+        }
+        entries.sort_by(|a, b| {
+            a.last_used
+                .cmp(&b.last_used)
+                .then_with(|| a.path.cmp(&b.path))
+        });
+        Ok(entries)
+    }
+
+    /// Total size of the entries in bytes
+    pub fn try_size(&self) -> Result<u64> {
+        Ok(self.try_entries()?.iter().map(|entry| entry.size).sum())
+    }
+
+    /// Removes the least recently used entries until the cache fits in its size limit
+    pub fn try_evict(&self) -> Result<()> {
+        let max_size = match self.max_size {
+            Some(max_size) => max_size,
+            None => return Ok(()),
+        };
+
+        let entries = self.try_entries()?;
+        let mut size: u64 = entries.iter().map(|entry| entry.size).sum();
+        for entry in entries {
+            if size <= max_size {
+                break;
+            }
+            remove_entry(&entry.path)?;
+            size -= entry.size;
+        }
+        Ok(())
+    }
+
+    /// Removes every entry in the cache
+    ///
+    /// # Panics
+    ///
+    /// Panics if an entry can't be removed. See `try_clear` for a version that returns an error
+    pub fn clear(&self) {
+        self.try_clear().unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Removes every entry in the cache
+    pub fn try_clear(&self) -> Result<()> {
+        for entry in self.try_entries()? {
+            remove_entry(&entry.path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Removes an entry, ignoring the ones that were already removed by another thread
+fn remove_entry(path: &Path) -> Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+            Err(Error::io(&path.to_string_lossy())(err))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sound_files::io::try_save_file;
+    use std::time::Duration;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(name)
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn write_sound(path: &str) {
+        let audio: Vec<Frame> = (0..1_000).map(|i| Frame::mono(i as f64 / 1_000.)).collect();
+        try_save_file(&audio, path, 22_050, 24).unwrap();
+    }
+
+    #[test]
+    fn entries_are_found_by_content() {
+        let cache = ResampleCache::new(temp_path("dawremi-cache-content"));
+        cache.clear();
+        let path = temp_path("dawremi-cache-content.wav");
+        let moved = temp_path("dawremi-cache-content-moved.wav");
+        write_sound(&path);
+
+        let audio = cache.open(&path, 44_100, &Downmix::Auto);
+        assert_eq!(2_000, audio.len());
+        assert_eq!(1, cache.entries().len());
+
+        // Same content in another path, so the entry is reused
+        fs::rename(&path, &moved).unwrap();
+        assert_eq!(audio, cache.open(&moved, 44_100, &Downmix::Auto));
+        assert_eq!(1, cache.entries().len());
+
+        // Other sample rates, qualities and downmixes are different entries
+        cache.open(&moved, 48_000, &Downmix::Auto);
+        cache.open(&moved, 44_100, &Downmix::channel(0));
+        let sinc = cache.clone().quality(ResampleQuality::Sinc);
+        sinc.open(&moved, 44_100, &Downmix::Auto);
+        assert_eq!(4, cache.entries().len());
+
+        // Files with the same sample rate aren't cached
+        cache.open(&moved, 22_050, &Downmix::Auto);
+        assert_eq!(4, cache.entries().len());
+
+        cache.clear();
+        assert!(cache.entries().is_empty());
+        fs::remove_file(&moved).unwrap();
+    }
+
+    #[test]
+    fn entries_can_be_saved_by_many_threads() {
+        let cache = ResampleCache::new(temp_path("dawremi-cache-threads"));
+        cache.clear();
+        let path = temp_path("dawremi-cache-threads.wav");
+        write_sound(&path);
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let (cache, path) = (cache.clone(), path.clone());
+                std::thread::spawn(move || cache.try_open(&path, 44_100, &Downmix::Auto))
+            })
+            .collect();
+        for handle in handles {
+            assert_eq!(2_000, handle.join().unwrap().unwrap().len());
+        }
+
+        // Only the entry is left, without any of the temporary files
+        assert_eq!(1, fs::read_dir(cache.dir()).unwrap().count());
+        cache.clear();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn least_recently_used_entries_are_evicted() {
+        let cache = ResampleCache::new(temp_path("dawremi-cache-eviction"));
+        cache.clear();
+        let path = temp_path("dawremi-cache-eviction.wav");
+        write_sound(&path);
+
+        // Entries are marked as used an hour apart, a while ago, so the order doesn't depend on
+        // the resolution of the clock. New entries are used now, after all of them
+        let hours_ago = |hours: u64| SystemTime::now() - Duration::from_secs(hours * 3_600);
+        let mark_used = |cache: &ResampleCache, suffix: &str, time: SystemTime| {
+            let entry = cache
+                .entries()
+                .into_iter()
+                .find(|entry| entry.path.to_string_lossy().ends_with(suffix))
+                .unwrap();
+            File::options()
+                .write(true)
+                .open(&entry.path)
+                .unwrap()
+                .set_modified(time)
+                .unwrap();
+        };
+
+        cache.open(&path, 44_100, &Downmix::Auto);
+        mark_used(&cache, "linear.frames", hours_ago(3));
+        let entry_size = cache.try_size().unwrap();
+        let cache = cache.max_size(Some(entry_size * 5 / 2));
+
+        cache.open(&path, 44_100, &Downmix::channel(0));
+        mark_used(&cache, "linear-ch0-0.frames", hours_ago(2));
+        // Using the first entry makes the second one the least recently used
+        cache.open(&path, 44_100, &Downmix::Auto);
+        mark_used(&cache, "linear.frames", hours_ago(1));
+        cache.open(&path, 44_100, &Downmix::channel(1));
+
+        let names: Vec<String> = cache
+            .entries()
+            .iter()
+            .map(|entry| {
+                entry
+                    .path
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect();
+        assert_eq!(2, names.len());
+        assert!(names[0].ends_with("linear.frames"));
+        assert!(names[1].ends_with("linear-ch1-1.frames"));
+
+        cache.clear();
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,243 @@
+//! Detects the format of audio files, and decodes the compressed ones with `symphonia`
+
+use crate::error::{Error, Result};
+use std::fs::File;
+use std::io::{ErrorKind, Read};
+use std::path::Path;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::DecoderOptions;
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::{FormatOptions, FormatReader};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+/// Formats of the files that can be opened
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileFormat {
+    Wav,
+    Flac,
+    Vorbis,
+    Mp3,
+}
+
+impl FileFormat {
+    /// Detects the format from the first bytes of the file, or from its extension if they are unknown
+    pub fn detect(path: &str) -> Result<Self> {
+        match read_header(path) {
+            Ok(header) => Self::from_header(&header)
+                .or_else(|| Self::from_extension(path))
+                .ok_or_else(|| Error::UnknownFormat(path.to_string())),
+            // The decoder of the extension reports that the file can't be opened
+            Err(err) => Self::from_extension(path).ok_or_else(|| Error::io(path)(err)),
+        }
+    }
+
+    fn from_header(header: &[u8]) -> Option<Self> {
+        match header {
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => Some(Self::Wav),
+            [b'f', b'L', b'a', b'C', ..] => Some(Self::Flac),
+            [b'O', b'g', b'g', b'S', ..] => Some(Self::Vorbis),
+            // Either ID3 tags or the sync bits of the first frame, with the bits of layer III
+            // AAC (ADTS) has the same sync bits, but with a layer of 0
+            [b'I', b'D', b'3', ..] => Some(Self::Mp3),
+            [0xFF, second, ..] if second & 0xE6 == 0xE2 => Some(Self::Mp3),
+            _ => None,
+        }
+    }
+
+    /// Format that files with the extension of `path` usually have
+    pub fn from_extension(path: &str) -> Option<Self> {
+        let extension = Path::new(path).extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "wav" | "wave" => Some(Self::Wav),
+            "flac" => Some(Self::Flac),
+            "ogg" | "oga" => Some(Self::Vorbis),
+            "mp3" => Some(Self::Mp3),
+            _ => None,
+        }
+    }
+}
+
+fn read_header(path: &str) -> std::io::Result<Vec<u8>> {
+    let mut header = vec![0; 12];
+    let read = File::open(path)?.read(&mut header)?;
+    header.truncate(read);
+    Ok(header)
+}
+
+/// Interleaved samples of a file, before its channels are mixed into frames
+pub struct Decoded {
+    pub samples: Vec<f64>,
+    pub channels: usize,
+    pub sample_rate: u32,
+}
+
+/// Opens a compressed file with `symphonia`, returning its first track and the id of the track
+fn open_compressed(path: &str, format: FileFormat) -> Result<(Box<dyn FormatReader>, u32)> {
+    let file = File::open(path).map_err(Error::io(path))?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    match format {
+        FileFormat::Vorbis => hint.with_extension("ogg"),
+        FileFormat::Mp3 => hint.with_extension("mp3"),
+        FileFormat::Wav | FileFormat::Flac => &mut hint,
+    };
+
+    let reader = symphonia::default::get_probe()
+        .format(
+            &hint,
+            stream,
+            // Removes the padding added by the encoder, so the sound starts right away
+            &FormatOptions {
+                enable_gapless: true,
+                ..Default::default()
+            },
+            &MetadataOptions::default(),
+        )
+        .map_err(Error::decode(path))?
+        .format;
+    let track = reader
+        .default_track()
+        .ok_or_else(|| Error::UnknownFormat(path.to_string()))?
+        .id;
+    Ok((reader, track))
+}
+
+/// Reads all the samples of an Ogg Vorbis or mp3 file
+pub fn try_open_compressed(path: &str, format: FileFormat) -> Result<Decoded> {
+    let (mut reader, track) = open_compressed(path, format)?;
+    let params = &reader
+        .tracks()
+        .iter()
+        .find(|t| t.id == track)
+        .unwrap()
+        .codec_params;
+    let mut sample_rate = params.sample_rate;
+    let mut channels = params.channels.map_or(0, |channels| channels.count());
+    let mut decoder = symphonia::default::get_codecs()
+        .make(params, &DecoderOptions::default())
+        .map_err(Error::decode(path))?;
+
+    let mut samples = vec![];
+    loop {
+        let packet = match reader.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(err)) if err.kind() == ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(Error::decode(path)(err)),
+        };
+        if packet.track_id() != track {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // Corrupted packets are skipped
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(err) => return Err(Error::decode(path)(err)),
+        };
+        let spec = *decoded.spec();
+        sample_rate = Some(spec.rate);
+        channels = spec.channels.count();
+
+        let mut buffer = SampleBuffer::<f64>::new(decoded.capacity() as u64, spec);
+        buffer.copy_interleaved_ref(decoded);
+        samples.extend_from_slice(buffer.samples());
+    }
+
+    let sample_rate = sample_rate.ok_or_else(|| Error::UnknownFormat(path.to_string()))?;
+    Ok(Decoded {
+        samples,
+        channels,
+        sample_rate,
+    })
+}
+
+/// Reads the sample rate of an Ogg Vorbis or mp3 file, without decoding all of it
+pub fn try_compressed_sample_rate(path: &str, format: FileFormat) -> Result<u32> {
+    let (reader, track) = open_compressed(path, format)?;
+    reader
+        .tracks()
+        .iter()
+        .find(|t| t.id == track)
+        .and_then(|track| track.codec_params.sample_rate)
+        .ok_or_else(|| Error::UnknownFormat(path.to_string()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::{remove_file, write};
+
+    #[test]
+    fn formats_are_detected_by_content_and_extension() {
+        let dir = std::env::temp_dir();
+        let detect = |name: &str, content: &[u8]| {
+            let path = dir.join(name);
+            let path = path.to_str().unwrap();
+            write(path, content).unwrap();
+            let format = FileFormat::detect(path);
+            remove_file(path).unwrap();
+            format.ok()
+        };
+
+        assert_eq!(
+            Some(FileFormat::Wav),
+            detect("dawremi-detect-wav", b"RIFF\0\0\0\0WAVEfmt ")
+        );
+        assert_eq!(
+            Some(FileFormat::Flac),
+            detect("dawremi-detect.wav", b"fLaC\0\0\0\x22")
+        );
+        assert_eq!(
+            Some(FileFormat::Vorbis),
+            detect("dawremi-detect", b"OggS\0\x02")
+        );
+        assert_eq!(
+            Some(FileFormat::Mp3),
+            detect("dawremi-detect-id3", b"ID3\x04\0")
+        );
+        assert_eq!(
+            Some(FileFormat::Mp3),
+            detect("dawremi-detect-sync", &[0xFF, 0xFB, 0x90, 0x64])
+        );
+        assert_eq!(
+            Some(FileFormat::Vorbis),
+            detect("dawremi-detect.OGG", b"unknown")
+        );
+        assert_eq!(None, detect("dawremi-detect.txt", b"unknown"));
+        assert_eq!(
+            None,
+            detect("dawremi-detect-aac", &[0xFF, 0xF1, 0x50, 0x80])
+        );
+    }
+
+    #[test]
+    fn compressed_files_are_decoded() {
+        // A tenth of a second of a 441Hz sine at 22,050Hz, with the right channel inverted
+        for &(path, format) in &[
+            ("assets/examples/sine.ogg", FileFormat::Vorbis),
+            ("assets/examples/sine.mp3", FileFormat::Mp3),
+        ] {
+            assert_eq!(format, FileFormat::detect(path).unwrap());
+            assert_eq!(22_050, try_compressed_sample_rate(path, format).unwrap());
+
+            let decoded = try_open_compressed(path, format).unwrap();
+            assert_eq!(2, decoded.channels);
+            assert_eq!(22_050, decoded.sample_rate);
+            assert!(decoded.samples.len() >= 2 * 2205);
+            if format == FileFormat::Mp3 {
+                // The LAME header says how much padding the encoder added, which gets removed
+                assert_eq!(2 * 2205, decoded.samples.len());
+            }
+            let peak = decoded
+                .samples
+                .iter()
+                .fold(0_f64, |peak, s| peak.max(s.abs()));
+            assert!((peak - 0.5).abs() < 0.05);
+            for frame in decoded.samples.chunks(2) {
+                assert!((frame[0] + frame[1]).abs() < 0.05);
+            }
+        }
+    }
+}
@@ -0,0 +1,234 @@
+//! Turns the channels of an audio file into stereo frames
+//!
+//! Files with one or two channels are read as they are, and files with more channels
+//! are mixed down with a matrix that says how much of each channel goes to each side
+
+use crate::frame::Frame;
+use crate::helpers::hashing::stable_hash;
+use std::cmp::Ordering;
+use std::f64::consts::FRAC_1_SQRT_2;
+use std::hash::{Hash, Hasher};
+
+/// How the channels of a file become the left and right channels of a `Frame`
+#[derive(Clone, Debug, Default)]
+pub enum Downmix {
+    /// Picks the matrix from the number of channels in the file
+    ///
+    /// Mono is copied to both sides, stereo is kept, quad and 5.1 use `Downmix::quad` and `Downmix::surround`,
+    /// and anything else is averaged to mono
+    #[default]
+    Auto,
+    /// Takes the channels with these indices as left and right, use the same index twice for mono
+    Channels { left: usize, right: usize },
+    /// Gains of every channel of the file for the left and right sides, in the order of the channels
+    Matrix(Vec<(f64, f64)>),
+}
+
+impl Downmix {
+    /// Takes only the channel with this index, as mono
+    pub const fn channel(idx: usize) -> Self {
+        Self::Channels {
+            left: idx,
+            right: idx,
+        }
+    }
+
+    /// Quad (front left, front right, rear left, rear right) to stereo, with the rear channels at -3dB
+    pub fn quad() -> Self {
+        Self::Matrix(vec![
+            (1., 0.),
+            (0., 1.),
+            (FRAC_1_SQRT_2, 0.),
+            (0., FRAC_1_SQRT_2),
+        ])
+    }
+
+    /// 5.1 to stereo following ITU-R BS.775, with the channels in the order used by wav files
+    /// (left, right, center, LFE, surround left, surround right)
+    ///
+    /// The center and surround channels are at -3dB and the LFE is dropped.
+    /// Peaks aren't scaled down, so they can go over 1
+    pub fn surround() -> Self {
+        Self::Matrix(vec![
+            (1., 0.),
+            (0., 1.),
+            (FRAC_1_SQRT_2, FRAC_1_SQRT_2),
+            (0., 0.),
+            (FRAC_1_SQRT_2, 0.),
+            (0., FRAC_1_SQRT_2),
+        ])
+    }
+
+    /// Matrix used for a file with this number of channels, or `None` if it doesn't fit the file
+    fn matrix(&self, channels: usize) -> Option<Vec<(f64, f64)>> {
+        match self {
+            Self::Auto => match channels {
+                0 => None,
+                1 => Some(vec![(1., 1.)]),
+                2 => Some(vec![(1., 0.), (0., 1.)]),
+                4 => Self::quad().matrix(channels),
+                6 => Self::surround().matrix(channels),
+                _ => {
+                    let gain = 1. / channels as f64;
+                    Some(vec![(gain, gain); channels])
+                }
+            },
+            Self::Channels { left, right } if *left < channels && *right < channels => {
+                let mut matrix = vec![(0., 0.); channels];
+                matrix[*left].0 = 1.;
+                matrix[*right].1 = 1.;
+                Some(matrix)
+            }
+            Self::Channels { .. } => None,
+            Self::Matrix(matrix) if matrix.len() == channels => Some(matrix.clone()),
+            Self::Matrix(_) => None,
+        }
+    }
+
+    /// Mixes interleaved samples with `channels` channels into frames
+    ///
+    /// Returns `None` if the channels don't fit this downmix,
+    /// like a matrix with a different number of channels or an index that's too big
+    pub fn apply(&self, samples: &[f64], channels: usize) -> Option<Vec<Frame>> {
+        let matrix = self.matrix(channels)?;
+        Some(
+            samples
+                .chunks_exact(channels)
+                .map(|sample| {
+                    sample.iter().zip(&matrix).fold(
+                        Frame::default(),
+                        |frame, (&val, &(left, right))| {
+                            Frame::new(
+                                left.mul_add(val, frame.left),
+                                right.mul_add(val, frame.right),
+                            )
+                        },
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    /// Short name used in the names of resampled files in the cache
+    pub(crate) fn file_suffix(&self) -> Option<String> {
+        match self {
+            Self::Auto => None,
+            Self::Channels { left, right } => Some(format!("ch{}-{}", left, right)),
+            Self::Matrix(_) => Some(format!("mix{}", stable_hash(self))),
+        }
+    }
+
+    /// Everything that identifies the downmix, with the gains as bits so it can be hashed and compared
+    fn key(&self) -> (u8, Vec<u64>) {
+        match self {
+            Self::Auto => (0, vec![]),
+            Self::Channels { left, right } => (1, vec![*left as u64, *right as u64]),
+            Self::Matrix(matrix) => (
+                2,
+                matrix
+                    .iter()
+                    .flat_map(|(left, right)| vec![left.to_bits(), right.to_bits()])
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl PartialEq for Downmix {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+impl Eq for Downmix {}
+
+impl Hash for Downmix {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+impl PartialOrd for Downmix {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Downmix {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mono_and_stereo_are_kept() {
+        assert_eq!(
+            Some(vec![Frame::mono(0.5), Frame::mono(-0.25)]),
+            Downmix::Auto.apply(&[0.5, -0.25], 1)
+        );
+        assert_eq!(
+            Some(vec![Frame::new(0.5, -0.25), Frame::new(0.1, 0.2)]),
+            Downmix::Auto.apply(&[0.5, -0.25, 0.1, 0.2], 2)
+        );
+    }
+
+    #[test]
+    fn surround_is_mixed_following_itu() {
+        // Left, right, center, LFE, surround left, surround right
+        let frames = Downmix::Auto
+            .apply(&[0.1, 0.2, 0.4, 1., 0.3, 0.], 6)
+            .unwrap();
+        assert_eq!(1, frames.len());
+        assert!((frames[0].left - 0.7_f64.mul_add(FRAC_1_SQRT_2, 0.1)).abs() < 1e-12);
+        assert!((frames[0].right - 0.4_f64.mul_add(FRAC_1_SQRT_2, 0.2)).abs() < 1e-12);
+
+        let frames = Downmix::Auto.apply(&[0.1, 0.2, 0.4, 0.], 4).unwrap();
+        assert!((frames[0].left - 0.4_f64.mul_add(FRAC_1_SQRT_2, 0.1)).abs() < 1e-12);
+        assert!((frames[0].right - 0.2).abs() < 1e-12);
+    }
+
+    #[test]
+    fn channels_can_be_picked() {
+        let samples = [0.1, 0.2, 0.3, 0.4, 0.5, 0.6];
+        assert_eq!(
+            Some(vec![Frame::new(0.3, 0.2), Frame::new(0.6, 0.5)]),
+            Downmix::Channels { left: 2, right: 1 }.apply(&samples, 3)
+        );
+        assert_eq!(
+            Some(vec![Frame::mono(0.1), Frame::mono(0.4)]),
+            Downmix::channel(0).apply(&samples, 3)
+        );
+        assert_eq!(None, Downmix::channel(3).apply(&samples, 3));
+    }
+
+    #[test]
+    fn matrices_need_a_gain_for_every_channel() {
+        let matrix = Downmix::Matrix(vec![(0.5, 0.), (0.5, 1.)]);
+        assert_eq!(
+            Some(vec![Frame::new(0.5, 0.5)]),
+            matrix.apply(&[0.5, 0.5], 2)
+        );
+        assert_eq!(None, matrix.apply(&[0.5, 0.5, 0.5], 3));
+        assert_eq!(None, Downmix::Auto.apply(&[], 0));
+    }
+
+    #[test]
+    fn file_suffixes_dont_change() {
+        assert_eq!(None, Downmix::Auto.file_suffix());
+        assert_eq!(
+            Some("ch2-1".to_string()),
+            Downmix::Channels { left: 2, right: 1 }.file_suffix()
+        );
+        // The names of files in the cache are kept between runs
+        assert_eq!(
+            Some("mixbcb8be79ad81f2d9d7a2a6439717cd89".to_string()),
+            Downmix::quad().file_suffix()
+        );
+        assert_ne!(
+            Downmix::quad().file_suffix(),
+            Downmix::surround().file_suffix()
+        );
+    }
+}
@@ -28,11 +28,7 @@ macro_rules! enum_to_str {
         impl From<$name> for Sound {
             fn from(f: $name) -> Self {
                 let path: &str = f.into();
-                Self {
-                    path: path.to_string(),
-                    begin: 0,
-                    end: None,
-                }
+                Self::from(path)
             }
         }
     }
@@ -74,11 +70,7 @@ impl From<Metronome> for &'static str {
 impl From<Metronome> for Sound {
     fn from(_: Metronome) -> Self {
         let path: &str = Metronome.into();
-        Self {
-            path: path.to_string(),
-            begin: 0,
-            end: None,
-        }
+        Self::from(path)
     }
 }
 
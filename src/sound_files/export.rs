@@ -0,0 +1,449 @@
+//! Options for saving audio to a file, see `ExportOptions`
+
+use crate::error::{Error, Result};
+use crate::frame::Frame;
+use crate::helpers::resampling::resample_frames;
+use crate::random::default_rng;
+use crate::sound_files::flac::FlacWriter;
+use crate::sound_files::io::f_to_i;
+use hound::{SampleFormat, WavSpec, WavWriter};
+use rand::Rng;
+use rand_chacha::ChaCha8Rng;
+use std::fs::{create_dir_all, File};
+use std::io::BufWriter;
+use std::path::Path;
+
+/// Noise added before converting to integers, so the rounding error doesn't follow the signal
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Dither {
+    /// Just rounds to the closest integer
+    None,
+    /// Triangular noise of one bit, which hides the rounding error in white noise
+    Tpdf,
+    /// Triangular noise with the rounding error pushed to the high frequencies, where it's less audible
+    NoiseShaped,
+}
+
+/// How the samples are stored in the file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// 32-bit float wav, which keeps samples over 1
+    Float,
+    /// Integer wav of 8, 16, 24 or 32 bits
+    Int {
+        bits_per_sample: u16,
+        dither: Dither,
+    },
+    /// Lossless compressed file of 16 or 24 bits, which also keeps the title and artist
+    Flac {
+        bits_per_sample: u16,
+        dither: Dither,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Channels {
+    /// Both sides get mixed together
+    Mono,
+    Stereo,
+}
+impl Channels {
+    const fn count(self) -> u16 {
+        match self {
+            Self::Mono => 1,
+            Self::Stereo => 2,
+        }
+    }
+}
+
+/// Where and how to save audio
+///
+/// Can be used with `Song::export` or to save any list of frames
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExportOptions {
+    pub path: String,
+    pub format: ExportFormat,
+    /// Sample rate of the file, if it should be different from the audio's
+    pub sample_rate: Option<u32>,
+    pub channels: Channels,
+    /// Saved in the tags of formats that have them
+    pub title: Option<String>,
+    pub artist: Option<String>,
+}
+
+impl ExportOptions {
+    /// Stereo 24-bit wav with triangular dither, at the sample rate of the audio
+    pub fn new(path: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            format: ExportFormat::Int {
+                bits_per_sample: 24,
+                dither: Dither::Tpdf,
+            },
+            sample_rate: None,
+            channels: Channels::Stereo,
+            title: None,
+            artist: None,
+        }
+    }
+
+    pub const fn format(mut self, format: ExportFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Resamples the audio to `sample_rate` before saving it
+    pub const fn sample_rate(mut self, sample_rate: u32) -> Self {
+        self.sample_rate = Some(sample_rate);
+        self
+    }
+
+    pub const fn channels(mut self, channels: Channels) -> Self {
+        self.channels = channels;
+        self
+    }
+
+    pub fn title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    pub fn artist(mut self, artist: impl Into<String>) -> Self {
+        self.artist = Some(artist.into());
+        self
+    }
+
+    /// Saves `audio`, which has `sample_rate`
+    ///
+    /// # Panics
+    ///
+    /// Panics if the file can't be written. See `try_save` for a version that returns an error
+    pub fn save(&self, audio: &[Frame], sample_rate: u32) {
+        self.try_save(audio, sample_rate)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Saves `audio`, which has `sample_rate`
+    pub fn try_save(&self, audio: &[Frame], sample_rate: u32) -> Result<()> {
+        self.try_save_blocks(std::iter::once(audio), sample_rate)
+    }
+
+    /// Saves the blocks as they are received, like `save_blocks`
+    ///
+    /// If the audio gets resampled, all of the blocks are kept until the end
+    pub fn try_save_blocks(
+        &self,
+        blocks: impl IntoIterator<Item = impl AsRef<[Frame]>>,
+        sample_rate: u32,
+    ) -> Result<()> {
+        match self.sample_rate {
+            Some(file_rate) if file_rate != sample_rate => {
+                let audio: Vec<Frame> = blocks
+                    .into_iter()
+                    .flat_map(|block| block.as_ref().to_vec())
+                    .collect();
+                let audio = resample_frames(audio, sample_rate, file_rate);
+                self.write(std::iter::once(audio), file_rate)
+            }
+            _ => self.write(blocks, sample_rate),
+        }
+    }
+
+    fn write(
+        &self,
+        blocks: impl IntoIterator<Item = impl AsRef<[Frame]>>,
+        sample_rate: u32,
+    ) -> Result<()> {
+        let path = self.path.as_str();
+        if let Some(folder) = Path::new(path).parent() {
+            if !folder.as_os_str().is_empty() {
+                create_dir_all(folder).map_err(Error::io(path))?;
+            }
+        }
+
+        let mut writer = FileWriter::create(self, sample_rate)?;
+        let mut left = Quantizer::new(self.format, 0);
+        let mut right = Quantizer::new(self.format, 1);
+
+        for block in blocks {
+            for frame in block.as_ref() {
+                match self.channels {
+                    Channels::Mono => left.write(&mut writer, frame.to_mono())?,
+                    Channels::Stereo => {
+                        left.write(&mut writer, frame.left)?;
+                        right.write(&mut writer, frame.right)?;
+                    }
+                }
+            }
+        }
+        writer.finalize()
+    }
+}
+
+/// File that gets written by `ExportOptions`
+enum FileWriter<'a> {
+    Wav(WavWriter<BufWriter<File>>, &'a str),
+    Flac(FlacWriter, &'a str),
+}
+
+impl<'a> FileWriter<'a> {
+    fn create(options: &'a ExportOptions, sample_rate: u32) -> Result<Self> {
+        let path = options.path.as_str();
+        let channels = options.channels.count();
+
+        let (bits_per_sample, sample_format) = match options.format {
+            ExportFormat::Float => (32, SampleFormat::Float),
+            ExportFormat::Int {
+                bits_per_sample, ..
+            } if [8, 16, 24, 32].contains(&bits_per_sample) => (bits_per_sample, SampleFormat::Int),
+            ExportFormat::Flac {
+                bits_per_sample, ..
+            } if [16, 24].contains(&bits_per_sample) => {
+                let tags: Vec<(&str, &str)> =
+                    [("TITLE", &options.title), ("ARTIST", &options.artist)]
+                        .iter()
+                        .filter_map(|(key, value)| Some((*key, value.as_deref()?)))
+                        .collect();
+                let writer =
+                    FlacWriter::create(path, channels, sample_rate, bits_per_sample, &tags)
+                        .map_err(Error::io(path))?;
+                return Ok(Self::Flac(writer, path));
+            }
+            ExportFormat::Int {
+                bits_per_sample, ..
+            }
+            | ExportFormat::Flac {
+                bits_per_sample, ..
+            } => {
+                return Err(Error::BitsPerSample {
+                    path: path.to_string(),
+                    bits_per_sample,
+                })
+            }
+        };
+
+        let spec = WavSpec {
+            channels,
+            sample_rate,
+            bits_per_sample,
+            sample_format,
+        };
+        let writer = WavWriter::create(path, spec).map_err(Error::wav(path))?;
+        Ok(Self::Wav(writer, path))
+    }
+
+    fn write_int(&mut self, sample: i32) -> Result<()> {
+        match self {
+            Self::Wav(writer, path) => writer.write_sample(sample).map_err(Error::wav(path)),
+            Self::Flac(writer, path) => writer.write_sample(sample).map_err(Error::io(path)),
+        }
+    }
+
+    fn write_float(&mut self, sample: f32) -> Result<()> {
+        match self {
+            Self::Wav(writer, path) => writer.write_sample(sample).map_err(Error::wav(path)),
+            Self::Flac(..) => unreachable!("Flac files only have integer samples"),
+        }
+    }
+
+    fn finalize(self) -> Result<()> {
+        match self {
+            Self::Wav(writer, path) => writer.finalize().map_err(Error::wav(path)),
+            Self::Flac(writer, path) => writer.finalize().map_err(Error::io(path)),
+        }
+    }
+}
+
+/// Converts the samples of one channel to the format of the file
+struct Quantizer {
+    format: ExportFormat,
+    rng: ChaCha8Rng,
+    /// Last two rounding errors, for noise shaping
+    errors: [f64; 2],
+}
+
+impl Quantizer {
+    /// Each channel gets its own stream of random numbers, so their dither isn't correlated
+    fn new(format: ExportFormat, channel: u64) -> Self {
+        let mut rng = default_rng();
+        rng.set_stream(channel);
+        Self {
+            format,
+            rng,
+            errors: [0.; 2],
+        }
+    }
+
+    fn write(&mut self, writer: &mut FileWriter, sample: f64) -> Result<()> {
+        match self.format {
+            ExportFormat::Float => writer.write_float(sample as f32),
+            ExportFormat::Int {
+                bits_per_sample,
+                dither,
+            }
+            | ExportFormat::Flac {
+                bits_per_sample,
+                dither,
+            } => writer.write_int(self.quantize(sample, bits_per_sample, dither)),
+        }
+    }
+
+    fn quantize(&mut self, sample: f64, bits_per_sample: u16, dither: Dither) -> i32 {
+        let max = (1_i64 << (bits_per_sample - 1)) as f64;
+        let mut value = sample * max;
+        if dither == Dither::NoiseShaped {
+            // The error gets filtered by (1 - z^-1)^2, which is a high pass
+            value -= 2_f64.mul_add(self.errors[0], -self.errors[1]);
+        }
+
+        let noise = match dither {
+            Dither::None => 0.,
+            Dither::Tpdf | Dither::NoiseShaped => self.rng.gen::<f64>() - self.rng.gen::<f64>(),
+        };
+        let quantized = f_to_i((value + noise) / max, bits_per_sample);
+        // Clipped samples would make the error grow forever
+        self.errors = [(quantized as f64 - value).clamp(-2., 2.), self.errors[0]];
+
+        quantized
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hound::WavReader;
+    use std::fs::remove_file;
+
+    fn path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("dawremi-export-{}.wav", name));
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn float_files_keep_loud_samples() {
+        let path = path("float");
+        let audio = vec![Frame::new(1.5, -0.25), Frame::new(0.125, 2.)];
+        ExportOptions::new(&path)
+            .format(ExportFormat::Float)
+            .save(&audio, 44_100);
+
+        let mut reader = WavReader::open(&path).unwrap();
+        assert_eq!(SampleFormat::Float, reader.spec().sample_format);
+        let samples: Vec<f32> = reader.samples().map(|s| s.unwrap()).collect();
+        assert_eq!(vec![1.5, -0.25, 0.125, 2.], samples);
+        remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn flac_files_can_be_opened() {
+        let path = std::env::temp_dir().join("dawremi-export-flac.flac");
+        let path = path.to_str().unwrap();
+        let audio: Vec<Frame> = (0..5_000)
+            .map(|i| Frame::new((i as f64 * 0.01).sin() * 0.5, 0.25))
+            .collect();
+        ExportOptions::new(path)
+            .format(ExportFormat::Flac {
+                bits_per_sample: 24,
+                dither: Dither::None,
+            })
+            .title("Song")
+            .artist("Someone")
+            .save(&audio, 44_100);
+
+        let reader = claxon::FlacReader::open(path).unwrap();
+        assert_eq!(Some("Song"), reader.get_tag("TITLE").next());
+        assert_eq!(Some("Someone"), reader.get_tag("ARTIST").next());
+
+        let opened = crate::sound_files::io::try_open_file(path, 44_100).unwrap();
+        assert_eq!(audio.len(), opened.len());
+        for (a, b) in audio.iter().zip(&opened) {
+            assert!((a.left - b.left).abs() < 1e-6 && (a.right - b.right).abs() < 1e-6);
+        }
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn mono_and_resampled_files() {
+        let path = path("mono");
+        let audio = vec![Frame::new(0.5, 0.); 100];
+        ExportOptions::new(&path)
+            .format(ExportFormat::Int {
+                bits_per_sample: 16,
+                dither: Dither::None,
+            })
+            .channels(Channels::Mono)
+            .sample_rate(200)
+            .save(&audio, 100);
+
+        let mut reader = WavReader::open(&path).unwrap();
+        assert_eq!(1, reader.spec().channels);
+        assert_eq!(200, reader.spec().sample_rate);
+        let samples: Vec<i16> = reader.samples().map(|s| s.unwrap()).collect();
+        assert_eq!(200, samples.len());
+        assert_eq!(1 << 13, samples[50]);
+        remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn dither_hides_the_rounding_error() {
+        // A quarter of a bit always rounds to 0, but with dither it's kept on average
+        let value = 0.25 / (1 << 15) as f64;
+        for &dither in &[Dither::Tpdf, Dither::NoiseShaped] {
+            let mut quantizer = Quantizer::new(ExportFormat::Float, 0);
+            let sum: i32 = (0..10_000)
+                .map(|_| quantizer.quantize(value, 16, dither))
+                .sum();
+            assert!((sum as f64 / 10_000. - 0.25).abs() < 0.05);
+        }
+
+        let mut quantizer = Quantizer::new(ExportFormat::Float, 0);
+        assert_eq!(0, quantizer.quantize(value, 16, Dither::None));
+        assert_eq!(127, quantizer.quantize(1., 8, Dither::None));
+    }
+
+    #[test]
+    fn channels_are_dithered_separately() {
+        let mut left = Quantizer::new(ExportFormat::Float, 0);
+        let mut right = Quantizer::new(ExportFormat::Float, 1);
+        let value = 0.25 / (1 << 15) as f64;
+        let left: Vec<i32> = (0..100)
+            .map(|_| left.quantize(value, 16, Dither::Tpdf))
+            .collect();
+        let right: Vec<i32> = (0..100)
+            .map(|_| right.quantize(value, 16, Dither::Tpdf))
+            .collect();
+        assert_ne!(left, right);
+    }
+
+    #[test]
+    fn unsupported_bits_per_sample_fail() {
+        let path = path("bits");
+        for &bits_per_sample in &[0, 12, 64] {
+            let result = ExportOptions::new(&path)
+                .format(ExportFormat::Int {
+                    bits_per_sample,
+                    dither: Dither::Tpdf,
+                })
+                .try_save(&[Frame::mono(0.5)], 44_100);
+            assert!(matches!(
+                result,
+                Err(Error::BitsPerSample { bits_per_sample: bits, .. }) if bits == bits_per_sample
+            ));
+        }
+        assert!(!Path::new(&path).exists());
+
+        let result = ExportOptions::new(&path)
+            .format(ExportFormat::Flac {
+                bits_per_sample: 8,
+                dither: Dither::Tpdf,
+            })
+            .try_save(&[Frame::mono(0.5)], 44_100);
+        assert!(matches!(
+            result,
+            Err(Error::BitsPerSample {
+                bits_per_sample: 8,
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,430 @@
+//! Minimal FLAC encoder, and decoding through `claxon`
+//!
+//! The encoder uses the fixed predictors of the format with Rice coded residuals,
+//! which is simple but gets most of the compression of the reference encoder
+
+use crate::error::{Error, Result};
+use crate::sound_files::decode::Decoded;
+use claxon::FlacReader;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+
+/// Samples of each channel in every FLAC frame
+const BLOCK_SIZE: usize = 4096;
+/// Highest Rice parameter, as 15 is used as an escape code
+const MAX_RICE_PARAMETER: u32 = 14;
+
+/// Reads all the samples of a FLAC file
+pub fn try_open_flac(path: &str) -> Result<Decoded> {
+    let mut reader = FlacReader::open(path).map_err(Error::flac(path))?;
+    let info = reader.streaminfo();
+    let max = (1_i64 << (info.bits_per_sample - 1)) as f64;
+
+    let samples = reader
+        .samples()
+        .map(|sample| sample.map(|val| val as f64 / max))
+        .collect::<claxon::Result<Vec<f64>>>()
+        .map_err(Error::flac(path))?;
+
+    Ok(Decoded {
+        samples,
+        channels: info.channels as usize,
+        sample_rate: info.sample_rate,
+    })
+}
+
+/// Reads the sample rate of a FLAC file, without decoding it
+pub fn try_flac_sample_rate(path: &str) -> Result<u32> {
+    let reader = FlacReader::open(path).map_err(Error::flac(path))?;
+    Ok(reader.streaminfo().sample_rate)
+}
+
+/// Writes interleaved integer samples to a FLAC file
+pub struct FlacWriter {
+    writer: BufWriter<File>,
+    channels: usize,
+    sample_rate: u32,
+    bits_per_sample: u32,
+    /// Interleaved samples that haven't been encoded yet
+    buffer: Vec<i64>,
+    frame_number: u64,
+    total_samples: u64,
+    min_frame_size: usize,
+    max_frame_size: usize,
+}
+
+impl FlacWriter {
+    /// Creates the file and writes its metadata
+    ///
+    /// `tags` are Vorbis comments, like `("TITLE", "Song name")`
+    pub fn create(
+        path: &str,
+        channels: u16,
+        sample_rate: u32,
+        bits_per_sample: u16,
+        tags: &[(&str, &str)],
+    ) -> io::Result<Self> {
+        let mut flac = Self {
+            writer: BufWriter::new(File::create(path)?),
+            channels: channels.into(),
+            sample_rate,
+            bits_per_sample: bits_per_sample.into(),
+            buffer: Vec::with_capacity(BLOCK_SIZE * channels as usize),
+            frame_number: 0,
+            total_samples: 0,
+            min_frame_size: usize::MAX,
+            max_frame_size: 0,
+        };
+
+        flac.writer.write_all(b"fLaC")?;
+        // Not the last metadata block, type 0, 34 bytes
+        flac.writer.write_all(&[0, 0, 0, 34])?;
+        let streaminfo = flac.streaminfo();
+        flac.writer.write_all(&streaminfo)?;
+
+        let mut comments = vec![];
+        let vendor = b"dawremi";
+        comments.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+        comments.extend_from_slice(vendor);
+        comments.extend_from_slice(&(tags.len() as u32).to_le_bytes());
+        for (key, value) in tags {
+            let comment = format!("{}={}", key, value);
+            comments.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+            comments.extend_from_slice(comment.as_bytes());
+        }
+        // Last metadata block, type 4
+        let length = (comments.len() as u32).to_be_bytes();
+        flac.writer
+            .write_all(&[0x80 | 4, length[1], length[2], length[3]])?;
+        flac.writer.write_all(&comments)?;
+
+        Ok(flac)
+    }
+
+    /// Adds a sample, with the channels interleaved
+    pub fn write_sample(&mut self, sample: i32) -> io::Result<()> {
+        self.buffer.push(sample.into());
+        if self.buffer.len() == BLOCK_SIZE * self.channels {
+            self.write_frame()?;
+        }
+        Ok(())
+    }
+
+    /// Encodes the samples that are left and fills in the length of the stream
+    pub fn finalize(mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            self.write_frame()?;
+        }
+        if self.min_frame_size == usize::MAX {
+            self.min_frame_size = 0;
+        }
+
+        let streaminfo = self.streaminfo();
+        self.writer.seek(SeekFrom::Start(8))?;
+        self.writer.write_all(&streaminfo)?;
+        self.writer.flush()
+    }
+
+    fn streaminfo(&self) -> Vec<u8> {
+        let mut bits = BitWriter::default();
+        bits.write(16, BLOCK_SIZE as u64);
+        bits.write(16, BLOCK_SIZE as u64);
+        bits.write(24, self.min_frame_size as u64);
+        bits.write(24, self.max_frame_size as u64);
+        bits.write(20, self.sample_rate.into());
+        bits.write(3, self.channels as u64 - 1);
+        bits.write(5, u64::from(self.bits_per_sample) - 1);
+        bits.write(36, self.total_samples);
+        // The MD5 of the audio is optional
+        bits.write(32, 0);
+        bits.write(32, 0);
+        bits.write(32, 0);
+        bits.write(32, 0);
+        bits.bytes
+    }
+
+    fn write_frame(&mut self) -> io::Result<()> {
+        let block_size = self.buffer.len() / self.channels;
+        let mut bits = BitWriter::default();
+
+        // Sync code, with a fixed block size
+        bits.write(16, 0xFFF8);
+        // Block size is at the end of the header, and the sample rate is in the streaminfo
+        bits.write(4, 0b0111);
+        bits.write(4, 0);
+        // Independent channels
+        bits.write(4, self.channels as u64 - 1);
+        bits.write(3, sample_size_code(self.bits_per_sample));
+        bits.write(1, 0);
+        write_utf8_number(&mut bits, self.frame_number);
+        bits.write(16, block_size as u64 - 1);
+        let crc = crc8(&bits.bytes);
+        bits.write(8, crc.into());
+
+        for channel in 0..self.channels {
+            let samples: Vec<i64> = self
+                .buffer
+                .iter()
+                .skip(channel)
+                .step_by(self.channels)
+                .copied()
+                .collect();
+            write_subframe(&mut bits, &samples, self.bits_per_sample);
+        }
+
+        bits.align();
+        let crc = crc16(&bits.bytes);
+        bits.write(16, crc.into());
+
+        self.writer.write_all(&bits.bytes)?;
+        self.min_frame_size = self.min_frame_size.min(bits.bytes.len());
+        self.max_frame_size = self.max_frame_size.max(bits.bytes.len());
+        self.frame_number += 1;
+        self.total_samples += block_size as u64;
+        self.buffer.clear();
+        Ok(())
+    }
+}
+
+const fn sample_size_code(bits_per_sample: u32) -> u64 {
+    match bits_per_sample {
+        8 => 0b001,
+        12 => 0b010,
+        16 => 0b100,
+        20 => 0b101,
+        24 => 0b110,
+        // Read from the streaminfo
+        _ => 0b000,
+    }
+}
+
+/// Writes the samples of one channel with whichever subframe type is shortest
+fn write_subframe(bits: &mut BitWriter, samples: &[i64], bits_per_sample: u32) {
+    if samples.iter().all(|&sample| sample == samples[0]) {
+        bits.write(8, 0);
+        bits.write_signed(bits_per_sample, samples[0]);
+        return;
+    }
+
+    let verbatim = samples.len() as u64 * u64::from(bits_per_sample);
+    let best = (0..=4.min(samples.len() - 1))
+        .map(|order| {
+            let residuals = fixed_residuals(samples, order);
+            let parameter = rice_parameter(&residuals);
+            let size =
+                order as u64 * u64::from(bits_per_sample) + 10 + rice_size(&residuals, parameter);
+            (size, order, residuals, parameter)
+        })
+        .min_by_key(|(size, ..)| *size);
+
+    match best {
+        Some((size, order, residuals, parameter)) if size < verbatim => {
+            bits.write(8, (0b001000 | order as u64) << 1);
+            for &sample in &samples[..order] {
+                bits.write_signed(bits_per_sample, sample);
+            }
+            // Rice coding with 4-bit parameters, and a single partition
+            bits.write(2, 0);
+            bits.write(4, 0);
+            bits.write(4, parameter.into());
+            for &residual in &residuals {
+                bits.write_rice(parameter, residual);
+            }
+        }
+        _ => {
+            bits.write(8, 0b000001 << 1);
+            for &sample in samples {
+                bits.write_signed(bits_per_sample, sample);
+            }
+        }
+    }
+}
+
+/// Difference between the samples and the prediction of the fixed predictor of `order`
+fn fixed_residuals(samples: &[i64], order: usize) -> Vec<i64> {
+    (order..samples.len())
+        .map(|i| {
+            let s = |offset: usize| samples[i - offset];
+            match order {
+                0 => s(0),
+                1 => s(0) - s(1),
+                2 => s(0) - 2 * s(1) + s(2),
+                3 => s(0) - 3 * s(1) + 3 * s(2) - s(3),
+                _ => s(0) - 4 * s(1) + 6 * s(2) - 4 * s(3) + s(4),
+            }
+        })
+        .collect()
+}
+
+/// Maps signed values to unsigned ones, so small negative values stay small
+const fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn rice_size(residuals: &[i64], parameter: u32) -> u64 {
+    residuals
+        .iter()
+        .map(|&residual| (zigzag(residual) >> parameter) + 1 + u64::from(parameter))
+        .sum()
+}
+
+fn rice_parameter(residuals: &[i64]) -> u32 {
+    (0..=MAX_RICE_PARAMETER)
+        .min_by_key(|&parameter| rice_size(residuals, parameter))
+        .unwrap()
+}
+
+/// Frame numbers are coded like UTF-8 characters, but with up to 36 bits
+fn write_utf8_number(bits: &mut BitWriter, number: u64) {
+    if number < 0x80 {
+        bits.write(8, number);
+        return;
+    }
+
+    let mut continuation = vec![];
+    let mut rest = number;
+    // Bits that fit in the first byte, with `continuation.len() + 1` leading ones
+    while rest >= 1 << (6 - continuation.len()) {
+        continuation.push(0x80 | (rest & 0x3F));
+        rest >>= 6;
+    }
+    let count = continuation.len() as u64 + 1;
+    let leading = (0xFF_u64 << (8 - count)) & 0xFF;
+    bits.write(8, leading | rest);
+    for byte in continuation.into_iter().rev() {
+        bits.write(8, byte);
+    }
+}
+
+fn crc8(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ byte, |crc, _| {
+            if crc & 0x80 == 0 {
+                crc << 1
+            } else {
+                (crc << 1) ^ 0x07
+            }
+        })
+    })
+}
+
+fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0, |crc, &byte| {
+        (0..8).fold(crc ^ (u16::from(byte) << 8), |crc, _| {
+            if crc & 0x8000 == 0 {
+                crc << 1
+            } else {
+                (crc << 1) ^ 0x8005
+            }
+        })
+    })
+}
+
+/// Writes values of any number of bits, most significant bit first
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    /// Bits that don't fill a byte yet
+    partial: u64,
+    partial_len: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, count: u32, value: u64) {
+        if count > 32 {
+            self.write(count - 32, value >> 32);
+            self.write(32, value & 0xFFFF_FFFF);
+            return;
+        }
+
+        self.partial = (self.partial << count) | (value & ((1 << count) - 1));
+        self.partial_len += count;
+        while self.partial_len >= 8 {
+            self.partial_len -= 8;
+            self.bytes.push((self.partial >> self.partial_len) as u8);
+        }
+        self.partial &= (1 << self.partial_len) - 1;
+    }
+
+    fn write_signed(&mut self, count: u32, value: i64) {
+        self.write(count, value as u64);
+    }
+
+    fn write_rice(&mut self, parameter: u32, value: i64) {
+        let value = zigzag(value);
+        let mut quotient = value >> parameter;
+        while quotient >= 32 {
+            self.write(32, 0);
+            quotient -= 32;
+        }
+        self.write(quotient as u32 + 1, 1);
+        self.write(parameter, value);
+    }
+
+    /// Pads with zeros until the end of the byte
+    fn align(&mut self) {
+        if self.partial_len > 0 {
+            self.write(8 - self.partial_len, 0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs::remove_file;
+
+    fn round_trip(name: &str, samples: &[i32], channels: u16, bits_per_sample: u16) {
+        let path = std::env::temp_dir().join(format!("dawremi-flac-{}.flac", name));
+        let path = path.to_str().unwrap();
+
+        let mut writer = FlacWriter::create(
+            path,
+            channels,
+            44_100,
+            bits_per_sample,
+            &[("TITLE", "Test")],
+        )
+        .unwrap();
+        for &sample in samples {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let mut reader = FlacReader::open(path).unwrap();
+        let info = reader.streaminfo();
+        assert_eq!(
+            Some(samples.len() as u64 / u64::from(channels)),
+            info.samples
+        );
+        assert_eq!(Some("Test"), reader.get_tag("TITLE").next());
+        let decoded: Vec<i32> = reader.samples().map(|s| s.unwrap()).collect();
+        assert_eq!(samples, &decoded[..]);
+        remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn files_decode_to_the_same_samples() {
+        let sine: Vec<i32> = (0..10_000)
+            .map(|i| ((i as f64 * 0.01).sin() * 30_000.) as i32)
+            .collect();
+        round_trip("sine", &sine, 1, 16);
+
+        let noisy: Vec<i32> = (0..20_001_i32)
+            .map(|i| (i * 7919 % 16_777_216) - 8_388_608)
+            .collect();
+        round_trip("noise", &noisy[..20_000], 2, 24);
+
+        round_trip("silence", &[0; 100], 2, 16);
+        round_trip("single", &[5], 1, 16);
+    }
+
+    #[test]
+    fn frame_numbers_are_utf8() {
+        let mut bits = BitWriter::default();
+        write_utf8_number(&mut bits, 0x7F);
+        write_utf8_number(&mut bits, 0x80);
+        write_utf8_number(&mut bits, 0x800);
+        assert_eq!(vec![0x7F, 0xC2, 0x80, 0xE0, 0xA0, 0x80], bits.bytes);
+    }
+}
@@ -1,155 +1,198 @@
+use crate::error::{Error, Result};
 use crate::frame::*;
-use crate::helpers::resampling::resample_frames;
+use crate::sound_files::cache::ResampleCache;
+use crate::sound_files::decode::*;
+use crate::sound_files::downmix::Downmix;
+use crate::sound_files::export::{Dither, ExportFormat, ExportOptions};
+use crate::sound_files::flac::{try_flac_sample_rate, try_open_flac};
 use hound::SampleFormat;
 use hound::WavReader;
-use hound::WavWriter;
+use std::convert::TryInto;
 use std::fs::File;
 use std::fs::*;
 use std::io::BufReader;
 
+/// Opens an audio file, resampling it to `sample_rate` if needed
+///
+/// # Panics
+///
+/// Panics if the file can't be opened. See `try_open_file` for a version that returns an error
 pub fn open_file(path: &str, sample_rate: u32) -> Vec<Frame> {
-    let reader =
-        hound::WavReader::open(path).unwrap_or_else(|_| panic!("File {} should exist", path));
-    let spec = reader.spec();
+    try_open_file(path, sample_rate).unwrap_or_else(|err| panic!("{}", err))
+}
 
-    // Check if the file has the same sample rate as the song
-    // If it doesn't we resample the file
-    // If it does, we just return the file
-    if spec.sample_rate != sample_rate {
-        let processed_filename = format!(
-            "assets/processed/{}-{}",
-            sample_rate,
-            path.replace("/", "_")
-        );
-
-        // If the processed file exists and hasn't been modified, just return it
-        let modified = has_file_been_modified(path, &processed_filename);
-        if metadata(&processed_filename).is_ok() && !modified {
-            if let Ok(processed_file) = hound::WavReader::open(&processed_filename) {
-                let processed_spec = processed_file.spec();
-                return transform_samples_to_frames(
-                    processed_file,
-                    processed_spec.channels,
-                    processed_spec.sample_format,
-                    processed_spec.bits_per_sample,
-                );
-            };
-        }
+/// Opens an audio file, resampling it to `sample_rate` if needed
+///
+/// Wav, flac, Ogg Vorbis and mp3 files are supported.
+/// The format is detected from the start of the file, or from its extension.
+/// Files with more than two channels are mixed down with `Downmix::Auto`
+pub fn try_open_file(path: &str, sample_rate: u32) -> Result<Vec<Frame>> {
+    try_open_file_downmixed(path, sample_rate, &Downmix::Auto)
+}
+
+/// Opens an audio file, mixing its channels with `downmix` and resampling it to `sample_rate` if needed
+///
+/// # Panics
+///
+/// Panics if the file can't be opened. See `try_open_file_downmixed` for a version that returns an error
+pub fn open_file_downmixed(path: &str, sample_rate: u32, downmix: &Downmix) -> Vec<Frame> {
+    try_open_file_downmixed(path, sample_rate, downmix).unwrap_or_else(|err| panic!("{}", err))
+}
+
+/// Opens an audio file, mixing its channels with `downmix` and resampling it to `sample_rate` if needed
+///
+/// Resampled files are saved in the default `ResampleCache`
+pub fn try_open_file_downmixed(
+    path: &str,
+    sample_rate: u32,
+    downmix: &Downmix,
+) -> Result<Vec<Frame>> {
+    ResampleCache::default().try_open(path, sample_rate, downmix)
+}
 
-        // Otherwise we resample it, save it as a new file, and return it
-        resample_and_save(reader, &processed_filename, sample_rate)
-    } else {
-        transform_samples_to_frames(
-            reader,
-            spec.channels,
-            spec.sample_format,
-            spec.bits_per_sample,
-        )
+/// Reads the sample rate of the file, decoding as little as possible
+pub(crate) fn try_file_sample_rate(path: &str, format: FileFormat) -> Result<u32> {
+    match format {
+        FileFormat::Wav => {
+            let reader = WavReader::open(path).map_err(Error::wav(path))?;
+            Ok(reader.spec().sample_rate)
+        }
+        FileFormat::Flac => try_flac_sample_rate(path),
+        FileFormat::Vorbis | FileFormat::Mp3 => try_compressed_sample_rate(path, format),
     }
 }
 
-fn transform_samples_to_frames(
+/// Reads every frame of the file, along with its sample rate
+pub(crate) fn try_decode_file(
+    path: &str,
+    format: FileFormat,
+    downmix: &Downmix,
+) -> Result<(Vec<Frame>, u32)> {
+    let decoded = match format {
+        FileFormat::Wav => try_open_wav(path),
+        FileFormat::Flac => try_open_flac(path),
+        FileFormat::Vorbis | FileFormat::Mp3 => try_open_compressed(path, format),
+    }?;
+
+    let frames = downmix
+        .apply(&decoded.samples, decoded.channels)
+        .ok_or_else(|| Error::Downmix {
+            path: path.to_string(),
+            channels: decoded.channels,
+        })?;
+    Ok((frames, decoded.sample_rate))
+}
+
+fn try_open_wav(path: &str) -> Result<Decoded> {
+    let reader = WavReader::open(path).map_err(Error::wav(path))?;
+    let spec = reader.spec();
+    let samples =
+        read_samples(reader, spec.sample_format, spec.bits_per_sample).map_err(Error::wav(path))?;
+    Ok(Decoded {
+        samples,
+        channels: spec.channels.into(),
+        sample_rate: spec.sample_rate,
+    })
+}
+
+fn read_samples(
     samples: WavReader<BufReader<File>>,
-    num_channels: u16,
     sample_format: SampleFormat,
     bits_per_sample: u16,
-) -> Vec<Frame> {
+) -> hound::Result<Vec<f64>> {
     match sample_format {
         SampleFormat::Float => samples
             .into_samples::<f32>()
-            .map(Result::unwrap)
-            .map(f64::from)
-            .collect::<Vec<f64>>()
-            .chunks(num_channels.into())
-            .map(|sample| match sample {
-                [left, right] => Frame::new(*left, *right),
-                [a, ..] => Frame::mono(*a),
-                [] => panic!("Sample has 0 channels"),
-            })
-            .collect::<Vec<Frame>>(),
+            .map(|val| val.map(f64::from))
+            .collect(),
         SampleFormat::Int => samples
             .into_samples::<i32>()
-            .map(Result::unwrap)
-            .map(|val| i_to_f(val, bits_per_sample))
-            .collect::<Vec<f64>>()
-            .chunks(num_channels.into())
-            .map(|sample| match sample {
-                [left, right] => Frame::new(*left, *right),
-                [a, ..] => Frame::mono(*a),
-                [] => panic!("Sample has 0 channels"),
-            })
-            .collect::<Vec<Frame>>(),
+            .map(|val| val.map(|val| i_to_f(val, bits_per_sample)))
+            .collect(),
     }
 }
 
-/// Returns true if file at path1 has been changed after path2
-fn has_file_been_modified(path1: &str, path2: &str) -> bool {
-    // Okay this code is horrible, but my brain isn't working well now
-    // I'll fix later to make it nicer
-
-    if let Ok(metadata1) = metadata(path1) {
-        if let Ok(metadata2) = metadata(path2) {
-            if let Ok(time1) = metadata1.modified() {
-                if let Ok(time2) = metadata2.modified() {
-                    return time1 > time2;
-                }
-            }
-        }
-    }
-
-    false
+/// Saves the audio to a wav file
+///
+/// # Panics
+///
+/// Panics if the file can't be written. See `try_save_file` for a version that returns an error
+pub fn save_file(audio: &[Frame], path: &str, sample_rate: u32, bits_per_sample: u16) {
+    try_save_file(audio, path, sample_rate, bits_per_sample).unwrap_or_else(|err| panic!("{}", err))
 }
 
-fn resample_and_save(
-    reader: WavReader<BufReader<File>>,
-    processed_filename: &str,
+/// Saves the audio to a wav file
+pub fn try_save_file(
+    audio: &[Frame],
+    path: &str,
     sample_rate: u32,
-) -> Vec<Frame> {
-    let spec = reader.spec();
-    let orig = transform_samples_to_frames(
-        reader,
-        spec.channels,
-        spec.sample_format,
-        spec.bits_per_sample,
-    );
-
-    let vec = resample_frames(orig, spec.sample_rate, sample_rate);
+    bits_per_sample: u16,
+) -> Result<()> {
+    try_save_blocks(std::iter::once(audio), path, sample_rate, bits_per_sample)
+}
 
-    save_file(&vec, processed_filename, sample_rate, 24);
+/// Saves the blocks to a file as they are received
+/// This way the whole audio never needs to be in memory, which is useful with `Song::render`
+///
+/// # Panics
+///
+/// Panics if the file can't be written. See `try_save_blocks` for a version that returns an error
+pub fn save_blocks(
+    blocks: impl IntoIterator<Item = impl AsRef<[Frame]>>,
+    path: &str,
+    sample_rate: u32,
+    bits_per_sample: u16,
+) {
+    try_save_blocks(blocks, path, sample_rate, bits_per_sample)
+        .unwrap_or_else(|err| panic!("{}", err))
+}
 
-    vec
+/// Saves the blocks to a file as they are received
+pub fn try_save_blocks(
+    blocks: impl IntoIterator<Item = impl AsRef<[Frame]>>,
+    path: &str,
+    sample_rate: u32,
+    bits_per_sample: u16,
+) -> Result<()> {
+    ExportOptions::new(path)
+        .format(ExportFormat::Int {
+            bits_per_sample,
+            dither: Dither::None,
+        })
+        .try_save_blocks(blocks, sample_rate)
 }
 
-pub fn save_file(audio: &[Frame], path: &str, sample_rate: u32, bits_per_sample: u16) {
-    let spec = hound::WavSpec {
-        channels: 2,
-        sample_rate,
-        bits_per_sample,
-        sample_format: hound::SampleFormat::Int,
-    };
-    let mut writer = WavWriter::create(path, spec)
-        .unwrap_or_else(|_| panic!("File could not be saved at {}", path));
-
-    for val in audio {
-        let val = val.clamp(-1., 1.);
-
-        let left = f_to_i(val.left, bits_per_sample);
-        writer
-            .write_sample(left)
-            .expect("Frame's left value could not be written");
-        let right = f_to_i(val.right, bits_per_sample);
-        writer
-            .write_sample(right)
-            .expect("Frame's left value could not be written");
+/// Saves the frames exactly as they are, without clamping or converting them
+///
+/// Used for caches, where the audio needs to be read back without any loss
+pub(crate) fn try_save_raw(audio: &[Frame], path: &str) -> Result<()> {
+    let mut bytes = Vec::with_capacity(audio.len() * 16);
+    for frame in audio {
+        bytes.extend_from_slice(&frame.left.to_le_bytes());
+        bytes.extend_from_slice(&frame.right.to_le_bytes());
     }
+    write(path, bytes).map_err(Error::io(path))
+}
+
+/// Opens a file saved with `try_save_raw`
+pub(crate) fn try_open_raw(path: &str) -> Result<Vec<Frame>> {
+    let bytes = read(path).map_err(Error::io(path))?;
+    let sample = |bytes: &[u8]| f64::from_le_bytes(bytes.try_into().unwrap());
+
+    Ok(bytes
+        .chunks_exact(16)
+        .map(|frame| Frame::new(sample(&frame[..8]), sample(&frame[8..])))
+        .collect())
 }
 
 fn i_to_f(val: i32, bits_per_sample: u16) -> f64 {
     val as f64 / (1_usize << (bits_per_sample - 1)) as f64
 }
 
-fn f_to_i(val: f64, bits_per_sample: u16) -> i32 {
-    (val * (1_usize << (bits_per_sample - 1)) as f64) as i32
+/// Rounds to the closest integer, clamping values that don't fit
+pub(crate) fn f_to_i(val: f64, bits_per_sample: u16) -> i32 {
+    let max = (1_i64 << (bits_per_sample - 1)) as f64;
+    (val * max).round().clamp(-max, max - 1.) as i32
 }
 
 #[cfg(test)]
@@ -166,8 +209,8 @@ mod test {
         assert_eq!(-(1 << 22), f_to_i(-0.5_f64, 24));
         assert_eq!(-(1 << 30), f_to_i(-0.5_f64, 32));
 
-        assert_eq!(1 << 15, f_to_i(1.0_f64, 16));
-        assert_eq!(1 << 23, f_to_i(1.0_f64, 24));
+        assert_eq!((1 << 15) - 1, f_to_i(1.0_f64, 16));
+        assert_eq!((1 << 23) - 1, f_to_i(1.0_f64, 24));
         assert_eq!(i32::MAX, f_to_i(1.0_f64, 32));
 
         assert_eq!(-(1 << 15), f_to_i(-1.0_f64, 16));
@@ -175,6 +218,17 @@ mod test {
         assert_eq!(i32::MIN, f_to_i(-1.0_f64, 32));
     }
 
+    #[test]
+    fn raw_files_keep_the_frames() {
+        let audio = vec![Frame::new(0.1, -2.5), Frame::new(1e-9, 3.)];
+        let path = std::env::temp_dir().join("dawremi-raw-test.frames");
+        let path = path.to_str().unwrap();
+
+        try_save_raw(&audio, path).unwrap();
+        assert_eq!(audio, try_open_raw(path).unwrap());
+        remove_file(path).unwrap();
+    }
+
     #[test]
     fn i_to_f_for_0() {
         assert_eq!(0., i_to_f(0, 16));
@@ -0,0 +1,573 @@
+//! Libraries of samples, with their names, tags, root notes and loop points
+//!
+//! A library is a folder of audio files. Every file is named after its path inside the folder,
+//! and tagged with the names of the folders it's in. Wav files can have a `smpl` or `inst` chunk
+//! with their root note and loop points, and a `library.toml` in the folder can add more:
+//!
+//! ```toml
+//! [samples.piano-c3]
+//! path = "piano/c3.wav"
+//! root = "C3"
+//! loop = [1000, 20000]
+//! tags = ["piano", "acoustic"]
+//! ```
+
+use super::decode::FileFormat;
+use super::io::try_file_sample_rate;
+use super::Sound;
+use crate::error::{Error, Result};
+use crate::trigger::Frequency;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Name of the manifest file inside the folder of a library
+pub const MANIFEST_FILE: &str = "library.toml";
+
+/// A sound in a `SampleLibrary`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sample {
+    pub name: String,
+    /// Path to the audio file, including the folder of the library
+    pub path: String,
+    pub tags: Vec<String>,
+    /// MIDI key of the note that was recorded, where 60 is C4
+    pub root_key: Option<u8>,
+    /// Cents that the recording is above `root_key`
+    pub fine_tune: f64,
+    /// Start and end of the loop, in frames at the sample rate of the file
+    pub loop_points: Option<(usize, usize)>,
+    /// Sample rate of the file
+    pub sample_rate: u32,
+}
+
+impl Sample {
+    /// Sound that plays the whole file
+    pub fn sound(&self) -> Sound {
+        Sound::from(self.path.clone())
+    }
+
+    /// Frequency of the recorded note
+    pub fn frequency(&self) -> Option<Frequency> {
+        self.root_key.map(|key| {
+            let semitones = (f64::from(key) - 69.) + self.fine_tune / 100.;
+            440. * (semitones / 12.).exp2()
+        })
+    }
+
+    /// Loop points resampled to `sample_rate`, to use with the audio of `Song::sound`
+    pub fn loop_points_at(&self, sample_rate: u32) -> Option<(usize, usize)> {
+        let ratio = f64::from(sample_rate) / f64::from(self.sample_rate);
+        self.loop_points.map(|(start, end)| {
+            (
+                (start as f64 * ratio).round() as usize,
+                (end as f64 * ratio).round() as usize,
+            )
+        })
+    }
+
+    /// Whether the sample has `tag`, ignoring case
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t.eq_ignore_ascii_case(tag))
+    }
+}
+
+impl From<&Sample> for Sound {
+    fn from(sample: &Sample) -> Self {
+        sample.sound()
+    }
+}
+
+/// Samples in a folder, see the module documentation
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SampleLibrary {
+    samples: BTreeMap<String, Sample>,
+}
+
+#[derive(Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    samples: BTreeMap<String, ManifestEntry>,
+}
+
+#[derive(Deserialize)]
+struct ManifestEntry {
+    path: PathBuf,
+    root: Option<RootNote>,
+    fine_tune: Option<f64>,
+    #[serde(rename = "loop")]
+    loop_points: Option<(usize, usize)>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+/// Root note as a MIDI key or a name like "C3" or "F#4"
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RootNote {
+    Key(u8),
+    Name(String),
+}
+
+impl SampleLibrary {
+    /// Loads the library in `dir`
+    ///
+    /// # Panics
+    ///
+    /// Panics if the library can't be loaded. See `try_open` for a version that returns an error
+    pub fn open(dir: impl AsRef<Path>) -> Self {
+        Self::try_open(dir).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Loads the library in `dir`, reading the metadata of the files and the manifest
+    pub fn try_open(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref();
+        let mut samples: BTreeMap<PathBuf, Sample> = BTreeMap::new();
+
+        for entry in WalkDir::new(dir).follow_links(true).sort_by_file_name() {
+            let entry = entry.map_err(|err| Error::io(&dir.to_string_lossy())(err.into()))?;
+            let relative = entry.path().strip_prefix(dir).unwrap().to_path_buf();
+            let hidden = relative
+                .components()
+                .any(|c| c.as_os_str().to_string_lossy().starts_with('.'));
+            if hidden || !entry.file_type().is_file() {
+                continue;
+            }
+            if let Some(sample) = read_sample(entry.path(), &relative)? {
+                samples.insert(relative, sample);
+            }
+        }
+
+        let manifest_path = dir.join(MANIFEST_FILE);
+        if manifest_path.exists() {
+            apply_manifest(&manifest_path, &mut samples)?;
+        }
+
+        let mut library = Self {
+            samples: BTreeMap::new(),
+        };
+        for sample in samples.into_values() {
+            if let Some(other) = library.samples.get(&sample.name) {
+                return Err(Error::SampleLibrary {
+                    path: dir.to_string_lossy().into_owned(),
+                    message: format!(
+                        "Samples {} and {} are both named {}",
+                        other.path, sample.path, sample.name
+                    ),
+                });
+            }
+            library.samples.insert(sample.name.clone(), sample);
+        }
+        Ok(library)
+    }
+
+    /// Every sample, sorted by name
+    pub fn samples(&self) -> impl Iterator<Item = &Sample> {
+        self.samples.values()
+    }
+
+    pub fn sample(&self, name: &str) -> Option<&Sample> {
+        self.samples.get(name)
+    }
+
+    /// Samples that have `tag`, ignoring case
+    pub fn tagged<'a>(&'a self, tag: &'a str) -> impl Iterator<Item = &'a Sample> {
+        self.samples().filter(move |sample| sample.has_tag(tag))
+    }
+
+    /// Samples that have every one of `tags`
+    pub fn tagged_all<'a>(&'a self, tags: &'a [&str]) -> impl Iterator<Item = &'a Sample> {
+        self.samples()
+            .filter(move |sample| tags.iter().all(|tag| sample.has_tag(tag)))
+    }
+
+    /// Sample with the same file as `sound`, like the variants made by `generate_sound_enums`
+    pub fn find(&self, sound: impl Into<Sound>) -> Option<&Sample> {
+        let path = PathBuf::from(sound.into().path);
+        self.samples()
+            .find(|sample| Path::new(&sample.path) == path)
+    }
+}
+
+/// Reads the sample rate and metadata of an audio file, or `None` if it's not an audio file
+pub(crate) fn read_sample(path: &Path, relative: &Path) -> Result<Option<Sample>> {
+    let path_str = path.to_string_lossy().into_owned();
+    let format = match FileFormat::from_extension(&path_str) {
+        Some(format) => format,
+        None => return Ok(None),
+    };
+
+    let mut sample = Sample {
+        name: relative
+            .with_extension("")
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/"),
+        tags: relative
+            .parent()
+            .into_iter()
+            .flat_map(Path::components)
+            .map(|c| c.as_os_str().to_string_lossy().to_lowercase())
+            .collect(),
+        root_key: None,
+        fine_tune: 0.,
+        loop_points: None,
+        sample_rate: try_file_sample_rate(&path_str, format)?,
+        path: path_str,
+    };
+    if format == FileFormat::Wav {
+        read_wav_chunks(&mut sample).map_err(Error::io(&sample.path))?;
+    }
+    Ok(Some(sample))
+}
+
+/// Reads the root note and loop points from the `smpl` and `inst` chunks of a wav file
+///
+/// The `smpl` chunk is preferred, since it has more precise tuning and the loop points
+fn read_wav_chunks(sample: &mut Sample) -> std::io::Result<()> {
+    let mut file = File::open(&sample.path)?;
+    let len = file.metadata()?.len();
+    let mut header = [0; 12];
+    file.read_exact(&mut header)?;
+
+    let mut inst = None;
+    let mut smpl = None;
+    let mut chunk_header = [0; 8];
+    while file.read_exact(&mut chunk_header).is_ok() {
+        let size = u32::from_le_bytes([
+            chunk_header[4],
+            chunk_header[5],
+            chunk_header[6],
+            chunk_header[7],
+        ]);
+        let padded = u64::from(size) + u64::from(size % 2);
+        match &chunk_header[..4] {
+            b"smpl" | b"inst" => {
+                // The size comes from the file, so it's checked before allocating the body
+                if u64::from(size) > len.saturating_sub(file.stream_position()?) {
+                    return Err(std::io::Error::new(
+                        ErrorKind::InvalidData,
+                        "Chunk is longer than the file",
+                    ));
+                }
+                let mut body = vec![0; size as usize];
+                file.read_exact(&mut body)?;
+                file.seek(SeekFrom::Current((padded - u64::from(size)) as i64))?;
+                if &chunk_header[..4] == b"smpl" {
+                    smpl = Some(body);
+                } else {
+                    inst = Some(body);
+                }
+            }
+            _ => {
+                file.seek(SeekFrom::Current(padded as i64))?;
+            }
+        }
+    }
+
+    if let Some(inst) = inst.filter(|inst| inst.len() >= 7) {
+        sample.root_key = Some(inst[0]);
+        sample.fine_tune = f64::from(inst[1] as i8);
+    }
+    if let Some(smpl) = smpl.filter(|smpl| smpl.len() >= 36) {
+        let word = |idx: usize| {
+            u32::from_le_bytes([smpl[idx], smpl[idx + 1], smpl[idx + 2], smpl[idx + 3]])
+        };
+        // The pitch fraction is a fraction of a semitone, where 0x80000000 is half
+        if let Ok(key) = u8::try_from(word(12)) {
+            sample.root_key = Some(key);
+            sample.fine_tune = f64::from(word(16)) / 4_294_967_296. * 100.;
+        }
+        // The end of the first loop is inclusive
+        if word(28) > 0 && smpl.len() >= 36 + 24 {
+            sample.loop_points = Some((word(36 + 8) as usize, word(36 + 12) as usize + 1));
+        }
+    }
+    Ok(())
+}
+
+/// Adds the names, tags, root notes and loop points of the manifest to the samples
+fn apply_manifest(path: &Path, samples: &mut BTreeMap<PathBuf, Sample>) -> Result<()> {
+    let path_str = path.to_string_lossy();
+    let content = fs::read_to_string(path).map_err(Error::io(&path_str))?;
+    let manifest: Manifest = toml::from_str(&content).map_err(Error::manifest(&path_str))?;
+
+    let mut names: BTreeMap<&Path, &str> = BTreeMap::new();
+    for (name, entry) in &manifest.samples {
+        if let Some(other) = names.insert(&entry.path, name) {
+            return Err(Error::manifest(&path_str)(format!(
+                "Samples {} and {} both point to {}",
+                other,
+                name,
+                entry.path.display()
+            )));
+        }
+    }
+
+    for (name, entry) in manifest.samples {
+        let sample = samples.get_mut(&entry.path).ok_or_else(|| {
+            Error::manifest(&path_str)(format!(
+                "Sample {} has a missing file {}",
+                name,
+                entry.path.display()
+            ))
+        })?;
+
+        sample.name = name;
+        for tag in entry.tags {
+            if !sample.has_tag(&tag) {
+                sample.tags.push(tag);
+            }
+        }
+        match entry.root {
+            Some(RootNote::Key(key)) => sample.root_key = Some(key),
+            Some(RootNote::Name(note)) => {
+                sample.root_key = Some(parse_note_name(&note).ok_or_else(|| {
+                    Error::manifest(&path_str)(format!("{} is not a note", note))
+                })?);
+            }
+            None => {}
+        }
+        if let Some(fine_tune) = entry.fine_tune {
+            sample.fine_tune = fine_tune;
+        }
+        if entry.loop_points.is_some() {
+            sample.loop_points = entry.loop_points;
+        }
+    }
+    Ok(())
+}
+
+/// MIDI key of a note name like "C3", "F#4" or "Bb-1", where C4 is 60
+pub(crate) fn parse_note_name(name: &str) -> Option<u8> {
+    let mut chars = name.chars();
+    let class: i32 = match chars.next()?.to_ascii_uppercase() {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return None,
+    };
+    let rest = chars.as_str();
+    let (accidental, octave) = match rest.chars().next()? {
+        '#' | 's' => (1, &rest[1..]),
+        'b' => (-1, &rest[1..]),
+        _ => (0, rest),
+    };
+    let octave: i32 = octave.parse().ok()?;
+    u8::try_from((octave + 1) * 12 + class + accidental)
+        .ok()
+        .filter(|key| *key < 128)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Wav file with some silence and extra chunks at the end
+    fn write_wav(path: &Path, chunks: &[(&[u8; 4], Vec<u8>)]) {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 22_050,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut bytes = Cursor::new(vec![]);
+        let mut writer = hound::WavWriter::new(&mut bytes, spec).unwrap();
+        for _ in 0..100 {
+            writer.write_sample(0_i16).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let mut bytes = bytes.into_inner();
+        for (id, body) in chunks {
+            bytes.extend_from_slice(*id);
+            bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(body);
+            if body.len() % 2 == 1 {
+                bytes.push(0);
+            }
+        }
+        let riff_size = (bytes.len() as u32 - 8).to_le_bytes();
+        bytes[4..8].copy_from_slice(&riff_size);
+
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, bytes).unwrap();
+    }
+
+    fn smpl_chunk(key: u32, fraction: u32, loop_points: (u32, u32)) -> Vec<u8> {
+        let mut words = vec![0, 0, 45_351, key, fraction, 0, 0, 1, 0];
+        words.extend_from_slice(&[0, 0, loop_points.0, loop_points.1, 0, 0]);
+        words
+            .iter()
+            .flat_map(|word: &u32| word.to_le_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn note_names_are_midi_keys() {
+        assert_eq!(Some(60), parse_note_name("C4"));
+        assert_eq!(Some(69), parse_note_name("A4"));
+        assert_eq!(Some(48), parse_note_name("c3"));
+        assert_eq!(Some(66), parse_note_name("F#4"));
+        assert_eq!(Some(70), parse_note_name("Bb4"));
+        assert_eq!(Some(0), parse_note_name("C-1"));
+        assert_eq!(None, parse_note_name("H2"));
+        assert_eq!(None, parse_note_name("C"));
+        assert_eq!(Some(127), parse_note_name("G9"));
+        assert_eq!(None, parse_note_name("A9"));
+    }
+
+    #[test]
+    fn libraries_read_chunks_and_manifests() {
+        let dir = std::env::temp_dir().join("dawremi-library");
+        let _ = fs::remove_dir_all(&dir);
+
+        write_wav(
+            &dir.join("Piano/c3.wav"),
+            &[(b"smpl", smpl_chunk(48, 1 << 31, (10, 89)))],
+        );
+        write_wav(
+            &dir.join("drums/acoustic/kick.wav"),
+            &[(b"inst", vec![36, (-10_i8) as u8, 0, 0, 127, 1, 127])],
+        );
+        write_wav(&dir.join("drums/snare.wav"), &[]);
+        fs::write(dir.join("notes.txt"), "not a sample").unwrap();
+        fs::write(
+            dir.join(MANIFEST_FILE),
+            r#"
+            [samples.big-kick]
+            path = "drums/acoustic/kick.wav"
+            tags = ["kick", "Acoustic"]
+
+            [samples.snare]
+            path = "drums/snare.wav"
+            root = "D3"
+            loop = [5, 50]
+            "#,
+        )
+        .unwrap();
+
+        let library = SampleLibrary::open(&dir);
+        let names: Vec<&str> = library.samples().map(|s| s.name.as_str()).collect();
+        assert_eq!(vec!["Piano/c3", "big-kick", "snare"], names);
+
+        let piano = library.sample("Piano/c3").unwrap();
+        assert_eq!(Some(48), piano.root_key);
+        assert!((piano.fine_tune - 50.).abs() < 1e-6);
+        assert_eq!(Some((10, 90)), piano.loop_points);
+        assert_eq!(Some((20, 180)), piano.loop_points_at(44_100));
+        assert_eq!(vec!["piano".to_string()], piano.tags);
+
+        let kick = library.sample("big-kick").unwrap();
+        assert_eq!(Some(36), kick.root_key);
+        assert_eq!(-10., kick.fine_tune);
+        assert_eq!(vec!["drums", "acoustic", "kick"], kick.tags);
+
+        let snare = library.sample("snare").unwrap();
+        assert_eq!(Some(50), snare.root_key);
+        assert_eq!(Some((5, 50)), snare.loop_points);
+        assert!((snare.frequency().unwrap() - 146.832).abs() < 0.001);
+
+        let drums: Vec<&str> = library.tagged("DRUMS").map(|s| s.name.as_str()).collect();
+        assert_eq!(vec!["big-kick", "snare"], drums);
+        assert_eq!(1, library.tagged_all(&["drums", "acoustic"]).count());
+
+        let path = dir.join("drums/snare.wav");
+        let sound = Sound::from(path.to_str().unwrap());
+        assert_eq!(Some(snare), library.find(sound));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn manifests_with_missing_files_fail() {
+        let dir = std::env::temp_dir().join("dawremi-library-missing");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join(MANIFEST_FILE),
+            "[samples.ghost]\npath = \"ghost.wav\"\n",
+        )
+        .unwrap();
+
+        assert!(matches!(
+            SampleLibrary::try_open(&dir),
+            Err(Error::Manifest { .. })
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn samples_with_the_same_name_fail() {
+        let dir = std::env::temp_dir().join("dawremi-library-duplicates");
+        let _ = fs::remove_dir_all(&dir);
+        write_wav(&dir.join("kick.wav"), &[]);
+        write_wav(&dir.join("snare.wav"), &[]);
+        fs::write(
+            dir.join(MANIFEST_FILE),
+            "[samples.kick]\npath = \"snare.wav\"\n",
+        )
+        .unwrap();
+
+        assert!(matches!(
+            SampleLibrary::try_open(&dir),
+            Err(Error::SampleLibrary { path, .. }) if path == dir.to_string_lossy()
+        ));
+
+        // Files with different extensions get the same name too
+        fs::remove_file(dir.join(MANIFEST_FILE)).unwrap();
+        fs::copy("assets/examples/sine.ogg", dir.join("kick.ogg")).unwrap();
+        assert!(matches!(
+            SampleLibrary::try_open(&dir),
+            Err(Error::SampleLibrary { message, .. }) if message.ends_with("both named kick")
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn manifest_entries_with_the_same_path_fail() {
+        let dir = std::env::temp_dir().join("dawremi-library-same-path");
+        let _ = fs::remove_dir_all(&dir);
+        write_wav(&dir.join("kick.wav"), &[]);
+        fs::write(
+            dir.join(MANIFEST_FILE),
+            "[samples.kick]\npath = \"kick.wav\"\n[samples.bass-drum]\npath = \"kick.wav\"\n",
+        )
+        .unwrap();
+
+        assert!(matches!(
+            SampleLibrary::try_open(&dir),
+            Err(Error::Manifest { .. })
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn chunks_longer_than_the_file_fail() {
+        let dir = std::env::temp_dir().join("dawremi-library-chunks");
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("broken.wav");
+        write_wav(&path, &[(b"smpl", smpl_chunk(48, 0, (0, 0)))]);
+        // Says the chunk is almost 4GB long
+        let mut bytes = fs::read(&path).unwrap();
+        let size = bytes.len() - 60 - 4;
+        bytes[size..size + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        fs::write(&path, bytes).unwrap();
+
+        assert!(matches!(
+            SampleLibrary::try_open(&dir),
+            Err(Error::Io { .. })
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,34 +1,290 @@
 //! Contains stuff dealing with audio files
 
+pub mod cache;
+mod decode;
+pub mod downmix;
 pub mod enums;
+pub mod export;
+mod flac;
 pub mod io;
+pub mod library;
+pub mod onsets;
 pub mod pattern;
+pub mod sf2;
+pub mod sfz;
 
-#[derive(Eq, PartialEq, Ord, PartialOrd, Hash, Clone)]
+use crate::error::Result;
+use crate::frame::Frame;
+use crate::song::Song;
+use decode::FileFormat;
+use downmix::Downmix;
+use io::try_file_sample_rate;
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+
+/// Where a `Sound` starts or ends, or how long its fades are
+#[derive(Clone, Copy, Debug)]
+pub enum SoundPosition {
+    /// Samples at the sample rate of the song
+    Samples(usize),
+    /// Samples at the sample rate of the file, like the positions shown by an audio editor
+    FileSamples(usize),
+    Seconds(f64),
+    /// Beats of the song, with the tempo counted from the start of the song
+    Beats(f64),
+    /// Fraction of the length of the file, from 0 to 1
+    Fraction(f64),
+}
+
+impl Default for SoundPosition {
+    fn default() -> Self {
+        Self::Samples(0)
+    }
+}
+
+/// Numbers are samples at the sample rate of the song, see `SoundPosition::FileSamples` for the ones of the file
+impl From<usize> for SoundPosition {
+    fn from(samples: usize) -> Self {
+        Self::Samples(samples)
+    }
+}
+
+impl SoundPosition {
+    /// Number of samples at the sample rate of `song`, for a file that is `len` samples long
+    /// at the sample rate of the song and was saved at `file_sample_rate`
+    pub fn samples(self, song: &Song, len: usize, file_sample_rate: u32) -> usize {
+        match self {
+            Self::Samples(samples) => samples,
+            Self::FileSamples(samples) => (samples as f64 * song.sample_rate() as f64
+                / file_sample_rate as f64)
+                .round() as usize,
+            Self::Seconds(seconds) => song.seconds(seconds),
+            Self::Beats(beats) => song.beats(beats),
+            Self::Fraction(fraction) => (len as f64 * fraction).round() as usize,
+        }
+    }
+
+    /// Everything that identifies the position, with the numbers as bits so it can be hashed and compared
+    const fn key(self) -> (u8, u64) {
+        match self {
+            Self::Samples(samples) => (0, samples as u64),
+            Self::FileSamples(samples) => (1, samples as u64),
+            Self::Seconds(seconds) => (2, seconds.to_bits()),
+            Self::Beats(beats) => (3, beats.to_bits()),
+            Self::Fraction(fraction) => (4, fraction.to_bits()),
+        }
+    }
+}
+
+impl PartialEq for SoundPosition {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+impl Eq for SoundPosition {}
+
+impl Hash for SoundPosition {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+impl PartialOrd for SoundPosition {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for SoundPosition {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+/// Path, begin, end, downmix, gain as bits, reverse, fade in and fade out of a `Sound`
+type SoundKey<'a> = (
+    &'a str,
+    SoundPosition,
+    Option<SoundPosition>,
+    &'a Downmix,
+    u64,
+    bool,
+    SoundPosition,
+    SoundPosition,
+);
+
+/// Sounds can be compared and hashed, with `gain` compared by its bits like the numbers of `SoundPosition`
+#[derive(Clone, Debug)]
 pub struct Sound {
     /// Path to audio file
     pub path: String,
     /// From where the audio should start
-    pub begin: usize,
+    pub begin: SoundPosition,
     /// None represents until end of sound
-    pub end: Option<usize>,
+    pub end: Option<SoundPosition>,
+    /// How the channels of the file are mixed into stereo
+    pub downmix: Downmix,
+    /// Multiplies the audio
+    pub gain: f64,
+    /// Plays the slice backwards
+    pub reverse: bool,
+    /// Length of the linear fade at the start, after reversing
+    pub fade_in: SoundPosition,
+    /// Length of the linear fade at the end, after reversing
+    pub fade_out: SoundPosition,
+}
+
+impl Sound {
+    /// Starts the sound at `begin`
+    pub fn begin(mut self, begin: impl Into<SoundPosition>) -> Self {
+        self.begin = begin.into();
+        self
+    }
+
+    /// Ends the sound at `end`, instead of at the end of the file
+    pub fn end(mut self, end: impl Into<SoundPosition>) -> Self {
+        self.end = Some(end.into());
+        self
+    }
+
+    /// Mixes the channels of the file with `downmix`, like picking some of them with `Downmix::Channels`
+    pub fn downmix(mut self, downmix: Downmix) -> Self {
+        self.downmix = downmix;
+        self
+    }
+
+    /// Multiplies the audio by `gain`
+    pub const fn gain(mut self, gain: f64) -> Self {
+        self.gain = gain;
+        self
+    }
+
+    /// Plays the sound backwards, or forwards again if it was already reversed
+    pub const fn reverse(mut self) -> Self {
+        self.reverse = !self.reverse;
+        self
+    }
+
+    /// Fades the start of the sound in, during `fade_in`
+    pub fn fade_in(mut self, fade_in: impl Into<SoundPosition>) -> Self {
+        self.fade_in = fade_in.into();
+        self
+    }
+
+    /// Fades the end of the sound out, during `fade_out`
+    pub fn fade_out(mut self, fade_out: impl Into<SoundPosition>) -> Self {
+        self.fade_out = fade_out.into();
+        self
+    }
+
+    /// Sample rate the file was saved at, which is only read if a position is in `FileSamples`
+    ///
+    /// Otherwise the sample rate of the song is returned, as it isn't used
+    pub(crate) fn file_sample_rate(&self, song: &Song) -> Result<u32> {
+        let positions = [
+            Some(self.begin),
+            self.end,
+            Some(self.fade_in),
+            Some(self.fade_out),
+        ];
+        if positions
+            .iter()
+            .flatten()
+            .any(|position| matches!(position, SoundPosition::FileSamples(_)))
+        {
+            try_file_sample_rate(&self.path, FileFormat::detect(&self.path)?)
+        } else {
+            song.try_sample_rate()
+        }
+    }
+
+    /// Applies the reverse, fades and gain to a slice of the file
+    pub(crate) fn process(
+        &self,
+        mut audio: Vec<Frame>,
+        song: &Song,
+        len: usize,
+        file_sample_rate: u32,
+    ) -> Vec<Frame> {
+        if self.reverse {
+            audio.reverse();
+        }
+
+        let fade_in = self
+            .fade_in
+            .samples(song, len, file_sample_rate)
+            .min(audio.len());
+        for (idx, frame) in audio.iter_mut().take(fade_in).enumerate() {
+            *frame *= idx as f64 / fade_in as f64;
+        }
+        let fade_out = self
+            .fade_out
+            .samples(song, len, file_sample_rate)
+            .min(audio.len());
+        for (idx, frame) in audio.iter_mut().rev().take(fade_out).enumerate() {
+            *frame *= idx as f64 / fade_out as f64;
+        }
+
+        for frame in &mut audio {
+            *frame *= self.gain;
+        }
+        audio
+    }
+
+    /// Everything that identifies the sound, with the gain as bits so it can be hashed and compared
+    fn key(&self) -> SoundKey<'_> {
+        (
+            &self.path,
+            self.begin,
+            self.end,
+            &self.downmix,
+            self.gain.to_bits(),
+            self.reverse,
+            self.fade_in,
+            self.fade_out,
+        )
+    }
+}
+
+impl PartialEq for Sound {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+impl Eq for Sound {}
+
+impl Hash for Sound {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+impl PartialOrd for Sound {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Sound {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
 }
 
 impl From<String> for Sound {
     fn from(path: String) -> Self {
         Self {
             path,
-            begin: 0,
+            begin: SoundPosition::default(),
             end: None,
+            downmix: Downmix::Auto,
+            gain: 1.,
+            reverse: false,
+            fade_in: SoundPosition::default(),
+            fade_out: SoundPosition::default(),
         }
     }
 }
 impl From<&str> for Sound {
     fn from(path: &str) -> Self {
-        Self {
-            path: path.to_string(),
-            begin: 0,
-            end: None,
-        }
+        Self::from(path.to_string())
     }
 }
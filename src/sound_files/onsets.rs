@@ -0,0 +1,256 @@
+//! Finds the transients of a sound, to chop it into slices with `Song::slices`
+//!
+//! The audio is split into overlapping windows, and a novelty function measures how much each window
+//! grows from the previous one. The peaks of that function that stand out from its moving average are the onsets
+
+use crate::frame::Frame;
+use realfft::RealFftPlanner;
+use std::f64::consts::TAU;
+
+/// Windows on each side that a peak has to be bigger than
+const PEAK_WINDOWS: usize = 3;
+/// Windows on each side used for the moving average
+const AVERAGE_WINDOWS: usize = 8;
+/// Samples in the blocks used to find where the onset starts inside its window
+const REFINE_BLOCK: usize = 64;
+/// Blocks before each block that it's compared to, enough to cover a period of low notes
+const REFINE_HISTORY: usize = 4;
+
+/// How the novelty of each window is measured
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OnsetFunction {
+    /// Sum of the increases of the magnitude of every frequency, good for most material
+    #[default]
+    SpectralFlux,
+    /// Increase of the energy, the fastest but it misses hits hidden by louder sounds
+    Energy,
+    /// Increase of the energy weighted by frequency, good for sharp hits like snares and hi-hats
+    HighFrequencyContent,
+}
+
+/// Finds the positions where new sounds start
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OnsetDetector {
+    pub function: OnsetFunction,
+    /// Size of the analysis windows in samples
+    pub window: usize,
+    /// Samples between the starts of the windows
+    pub hop: usize,
+    /// Onsets weaker than this fraction of the strongest one, over the moving average, are ignored
+    pub threshold: f64,
+    /// Minimum time between onsets, in seconds
+    pub min_gap: f64,
+}
+
+impl Default for OnsetDetector {
+    fn default() -> Self {
+        Self {
+            function: OnsetFunction::default(),
+            window: 1024,
+            hop: 256,
+            threshold: 0.1,
+            min_gap: 0.05,
+        }
+    }
+}
+
+impl OnsetDetector {
+    pub const fn function(mut self, function: OnsetFunction) -> Self {
+        self.function = function;
+        self
+    }
+
+    pub const fn window(mut self, window: usize) -> Self {
+        self.window = window;
+        self
+    }
+
+    pub const fn hop(mut self, hop: usize) -> Self {
+        self.hop = hop;
+        self
+    }
+
+    pub const fn threshold(mut self, threshold: f64) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    pub const fn min_gap(mut self, min_gap: f64) -> Self {
+        self.min_gap = min_gap;
+        self
+    }
+
+    /// Returns the positions of the onsets in samples, in order
+    pub fn detect(&self, audio: &[Frame], sample_rate: u32) -> Vec<usize> {
+        let mono: Vec<f64> = audio.iter().map(Frame::to_mono).collect();
+        let hop = self.hop.max(1);
+        let novelty = self.novelty(&mono);
+        let max = novelty.iter().copied().fold(0., f64::max);
+        if max <= 0. {
+            return vec![];
+        }
+        let novelty: Vec<f64> = novelty.iter().map(|value| value / max).collect();
+        let min_gap = (self.min_gap * f64::from(sample_rate)) as usize;
+
+        let mut onsets: Vec<usize> = vec![];
+        for (idx, &value) in novelty.iter().enumerate() {
+            let around = |windows: usize| {
+                &novelty[idx.saturating_sub(windows)..(idx + windows + 1).min(novelty.len())]
+            };
+            let is_peak = around(PEAK_WINDOWS).iter().all(|&other| other <= value);
+            let average =
+                around(AVERAGE_WINDOWS).iter().sum::<f64>() / around(AVERAGE_WINDOWS).len() as f64;
+            if !is_peak || value < self.threshold + average {
+                continue;
+            }
+
+            let onset = refine(&mono, idx * hop, self.window);
+            if onsets.last().is_none_or(|&last| onset >= last + min_gap) {
+                onsets.push(onset);
+            }
+        }
+        onsets
+    }
+
+    /// Novelty of every window, with the window `idx` centered on `idx * hop`
+    fn novelty(&self, mono: &[f64]) -> Vec<f64> {
+        let window = self.window.max(2);
+        let hop = self.hop.max(1);
+        let hann: Vec<f64> = (0..window)
+            .map(|idx| 0.5_f64.mul_add(-(TAU * idx as f64 / window as f64).cos(), 0.5))
+            .collect();
+
+        let mut planner = RealFftPlanner::<f64>::new();
+        let fft = planner.plan_fft_forward(window);
+        let mut input = fft.make_input_vec();
+        let mut spectrum = fft.make_output_vec();
+        let mut previous_value = 0_f64;
+        let mut previous_magnitudes = vec![0.; spectrum.len()];
+
+        (0..mono.len() / hop + 1)
+            .map(|idx| {
+                for (offset, value) in input.iter_mut().enumerate() {
+                    let sample = (idx * hop + offset).checked_sub(window / 2);
+                    *value = sample
+                        .and_then(|sample| mono.get(sample))
+                        .map_or(0., |sample| sample * hann[offset]);
+                }
+
+                let value: f64 = match self.function {
+                    OnsetFunction::Energy => input.iter().map(|sample| sample * sample).sum(),
+                    OnsetFunction::HighFrequencyContent => {
+                        fft.process(&mut input, &mut spectrum).unwrap();
+                        spectrum
+                            .iter()
+                            .enumerate()
+                            .map(|(bin, value)| bin as f64 * value.norm_sqr())
+                            .sum()
+                    }
+                    OnsetFunction::SpectralFlux => {
+                        fft.process(&mut input, &mut spectrum).unwrap();
+                        // Compressed, so quiet hits count too
+                        let magnitudes: Vec<f64> =
+                            spectrum.iter().map(|value| value.norm().ln_1p()).collect();
+                        let flux = magnitudes
+                            .iter()
+                            .zip(&previous_magnitudes)
+                            .map(|(magnitude, previous)| (magnitude - previous).max(0.))
+                            .sum();
+                        previous_magnitudes = magnitudes;
+                        return flux;
+                    }
+                };
+                let increase = (value - previous_value).max(0.);
+                previous_value = value;
+                increase
+            })
+            .collect()
+    }
+}
+
+/// Moves an onset found in the window centered on `center` to the block where the energy jumps the most
+/// over the blocks before it
+fn refine(mono: &[f64], center: usize, window: usize) -> usize {
+    let start = center.saturating_sub(window / 2);
+    let end = (center + window / 2).min(mono.len());
+    if start >= end {
+        return start.min(mono.len());
+    }
+    let history = REFINE_HISTORY.min(start / REFINE_BLOCK);
+    let first = start - history * REFINE_BLOCK;
+    let energies: Vec<f64> = mono[first..end]
+        .chunks(REFINE_BLOCK)
+        .map(|block| block.iter().map(|sample| sample * sample).sum())
+        .collect();
+
+    let mut best = (f64::MIN, start);
+    for (idx, energy) in energies.iter().enumerate().skip(history) {
+        let before = energies[idx.saturating_sub(REFINE_HISTORY)..idx]
+            .iter()
+            .copied()
+            .fold(0., f64::max);
+        if energy - before > best.0 {
+            best = (energy - before, first + idx * REFINE_BLOCK);
+        }
+    }
+    best.1
+}
+
+/// Decaying sines starting at `starts`, in a second of audio at 44.1kHz
+#[cfg(test)]
+pub(crate) fn hits(starts: &[usize]) -> Vec<Frame> {
+    let mut audio = vec![Frame::default(); 44_100];
+    for (idx, &start) in starts.iter().enumerate() {
+        let frequency = 150. * (idx + 1) as f64;
+        for (offset, frame) in audio[start..].iter_mut().enumerate() {
+            let time = offset as f64 / 44_100.;
+            *frame += Frame::mono((TAU * frequency * time).sin() * (-time / 0.03).exp());
+        }
+    }
+    audio
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn every_function_finds_the_hits() {
+        let starts = [0, 11_025, 22_050, 33_075];
+        let audio = hits(&starts);
+        for function in &[
+            OnsetFunction::SpectralFlux,
+            OnsetFunction::Energy,
+            OnsetFunction::HighFrequencyContent,
+        ] {
+            let onsets = OnsetDetector::default()
+                .function(*function)
+                .detect(&audio, 44_100);
+            assert_eq!(starts.len(), onsets.len(), "{:?}: {:?}", function, onsets);
+            for (onset, start) in onsets.iter().zip(&starts) {
+                assert!(
+                    onset.abs_diff(*start) <= REFINE_BLOCK,
+                    "{:?}: {:?}",
+                    function,
+                    onsets
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn onsets_closer_than_the_gap_are_merged() {
+        let audio = hits(&[11_025, 11_025 + 4_410]);
+        assert_eq!(2, OnsetDetector::default().detect(&audio, 44_100).len());
+        let onsets = OnsetDetector::default().min_gap(0.2).detect(&audio, 44_100);
+        assert_eq!(1, onsets.len());
+        assert!(onsets[0].abs_diff(11_025) <= REFINE_BLOCK);
+    }
+
+    #[test]
+    fn silence_has_no_onsets() {
+        let audio = vec![Frame::default(); 10_000];
+        assert!(OnsetDetector::default().detect(&audio, 44_100).is_empty());
+        assert!(OnsetDetector::default().detect(&[], 44_100).is_empty());
+    }
+}
@@ -4,25 +4,56 @@
 use super::*;
 use crate::frame::Frame;
 use crate::song::Song;
+use rand::Rng;
 
-/// Contains a pattern and a sound
+/// Contains a pattern and a sound, with the velocity, probability and nudge of every step
 #[derive(Clone)]
 pub struct SoundPattern<const LEN: usize> {
     pub(crate) pattern: [bool; LEN],
     pub(crate) sound: Sound,
+    pub(crate) velocities: [f64; LEN],
+    pub(crate) probabilities: [f64; LEN],
+    pub(crate) nudges: [f64; LEN],
 }
 impl<const LEN: usize> SoundPattern<LEN> {
-    /// Returns the opposite pattern
-    pub fn neg(self) -> Self {
-        let mut pattern = [false; LEN];
-        for i in 0..LEN {
-            pattern[i] = !self.pattern[i];
-        }
-        SoundPattern {
-            sound: self.sound,
+    /// Pattern where every step that's on always plays at full velocity, on the grid
+    pub(crate) const fn new(pattern: [bool; LEN], sound: Sound) -> Self {
+        Self {
             pattern,
+            sound,
+            velocities: [1.; LEN],
+            probabilities: [1.; LEN],
+            nudges: [0.; LEN],
         }
     }
+
+    /// Returns the opposite pattern
+    pub fn neg(mut self) -> Self {
+        for on in &mut self.pattern {
+            *on = !*on;
+        }
+        self
+    }
+
+    /// Sets the gain of every step
+    pub const fn velocity(mut self, velocities: [f64; LEN]) -> Self {
+        self.velocities = velocities;
+        self
+    }
+
+    /// Sets the chance of every step to play, from 0 to 1
+    ///
+    /// The random numbers come from `Song::rng`, so the song sounds the same every time it's rendered
+    pub const fn probability(mut self, probabilities: [f64; LEN]) -> Self {
+        self.probabilities = probabilities;
+        self
+    }
+
+    /// Moves every step by a fraction of the length of a step, late if it's positive and early if it's negative
+    pub const fn nudge(mut self, nudges: [f64; LEN]) -> Self {
+        self.nudges = nudges;
+        self
+    }
 }
 
 /// Used to denote when a step is on and when it's off
@@ -40,29 +71,50 @@ pub trait IntoSoundPattern<const LEN: usize> {
 
 impl<const LEN: usize> IntoSoundPattern<LEN> for [bool; LEN] {
     fn sound(self, sound: impl Into<Sound>) -> SoundPattern<LEN> {
-        SoundPattern {
-            pattern: self,
-            sound: sound.into(),
-        }
+        SoundPattern::new(self, sound.into())
     }
 }
 impl<const LEN: usize> IntoSoundPattern<LEN> for [PatternStep; LEN] {
     fn sound(self, sound: impl Into<Sound>) -> SoundPattern<LEN> {
-        SoundPattern {
-            pattern: map_steps_to_bools(self),
-            sound: sound.into(),
-        }
+        SoundPattern::new(map_steps_to_bools(self), sound.into())
     }
 }
 impl<const LEN: usize> IntoSoundPattern<LEN> for usize {
     fn sound(self, sound: impl Into<Sound>) -> SoundPattern<LEN> {
-        SoundPattern {
-            pattern: map_number_to_bools(self),
-            sound: sound.into(),
-        }
+        SoundPattern::new(map_number_to_bools(self), sound.into())
     }
 }
 
+/// Makes a pattern for every sound used in `steps`, where each step plays the sound with that index,
+/// or nothing if it's `None`
+///
+/// Useful to play the slices returned by `Song::slices` in another order
+///
+/// # Panics
+///
+/// Panics if a step has an index that's not in `sounds`
+pub fn sequence_sounds<const LEN: usize>(
+    sounds: &[Sound],
+    steps: [Option<usize>; LEN],
+) -> Vec<SoundPattern<LEN>> {
+    let mut used: Vec<usize> = steps.iter().flatten().copied().collect();
+    used.sort_unstable();
+    used.dedup();
+
+    used.into_iter()
+        .map(|idx| {
+            let sound = sounds.get(idx).unwrap_or_else(|| {
+                panic!("There's no sound {} in a list of {}", idx, sounds.len())
+            });
+            let mut pattern = [false; LEN];
+            for (on, step) in pattern.iter_mut().zip(&steps) {
+                *on = *step == Some(idx);
+            }
+            pattern.sound(sound.clone())
+        })
+        .collect()
+}
+
 pub trait SoundPatternListExtension<const LEN: usize> {
     /// Generates the drum patterns into audio
     /// Will loop `loops` times
@@ -76,21 +128,7 @@ impl<const N: usize, const LEN: usize> SoundPatternListExtension<LEN> for [Sound
         self.generate_beat_len(song, loops, 1.)
     }
     fn generate_beat_len(&self, song: &Song, loops: usize, length: f64) -> Vec<Frame> {
-        let beat = song.beats(length);
-        let mut vec = vec![Frame::default(); beat * LEN];
-
-        for pat in self {
-            let sound = song.sound(pat.sound.clone());
-
-            for (i, &on) in pat.pattern.iter().cycle().take(LEN * loops).enumerate() {
-                if on {
-                    let start = i * beat;
-                    vec = add_vecs_starting_from(vec, start, &sound);
-                }
-            }
-        }
-
-        vec
+        generate_patterns(self, song, loops, length)
     }
 }
 impl<const LEN: usize> SoundPatternListExtension<LEN> for Vec<SoundPattern<LEN>> {
@@ -98,33 +136,58 @@ impl<const LEN: usize> SoundPatternListExtension<LEN> for Vec<SoundPattern<LEN>>
         self.generate_beat_len(song, loops, 1.)
     }
     fn generate_beat_len(&self, song: &Song, loops: usize, length: f64) -> Vec<Frame> {
-        let beat = song.beats(length);
-        let mut vec = vec![Frame::default(); beat * LEN];
+        generate_patterns(self, song, loops, length)
+    }
+}
+
+/// Generates the patterns, with each step lasting `length` beats
+///
+/// Steps are placed on the beats of the song, so they follow tempo changes
+fn generate_patterns<const LEN: usize>(
+    patterns: &[SoundPattern<LEN>],
+    song: &Song,
+    loops: usize,
+    length: f64,
+) -> Vec<Frame> {
+    let mut vec = vec![Frame::default(); song.beats(length * LEN as f64)];
+    let mut rng = song.rng();
 
-        for pat in self {
-            let sound = song.sound(pat.sound.clone());
+    for pat in patterns {
+        let sound = song.sound(pat.sound.clone());
 
-            for (i, &on) in pat.pattern.iter().cycle().take(LEN * loops).enumerate() {
-                if on {
-                    let start = i * beat;
-                    vec = add_vecs_starting_from(vec, start, &sound);
-                }
+        for (i, &on) in pat.pattern.iter().cycle().take(LEN * loops).enumerate() {
+            let step = i % LEN;
+            if !on {
+                continue;
+            }
+            // Steps that always play don't take a number, so they don't change the ones of the rest of the track
+            let probability = pat.probabilities[step];
+            if probability < 1. && rng.gen::<f64>() >= probability {
+                continue;
             }
-        }
 
-        vec
+            let start = song.beats((i as f64 + pat.nudges[step]).max(0.) * length);
+            vec = add_vecs_starting_from(vec, start, &sound, pat.velocities[step]);
+        }
     }
+
+    vec
 }
 
-/// Adds two vectors, but starts `other` from `start`
-fn add_vecs_starting_from(mut base: Vec<Frame>, start: usize, other: &[Frame]) -> Vec<Frame> {
+/// Adds two vectors, but starts `other` from `start` and multiplies it by `gain`
+pub(crate) fn add_vecs_starting_from(
+    mut base: Vec<Frame>,
+    start: usize,
+    other: &[Frame],
+    gain: f64,
+) -> Vec<Frame> {
     let end = start + other.len(); // Not the actual end, just of other
     let new_len = base.len().max(end);
 
     base.resize(new_len, Frame::default());
 
     for i in start..end {
-        base[i] += other[i - start];
+        base[i] += other[i - start] * gain;
     }
 
     base
@@ -151,6 +214,67 @@ fn map_number_to_bools<const LEN: usize>(num: usize) -> [bool; LEN] {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::song::{test_song, SongConfig};
+
+    fn beep() -> Sound {
+        Sound::from("assets/examples/beep.wav")
+            .begin(1_000)
+            .end(1_100)
+    }
+
+    #[test]
+    fn steps_have_velocities_and_nudges() {
+        let song = test_song(SongConfig::default());
+        let beep_audio = song.sound(beep());
+        // At 100bpm a beat is 26460 samples
+        let audio = [[true, true, false]
+            .sound(beep())
+            .velocity([1., 0.5, 1.])
+            .nudge([0., 0.25, 0.])]
+        .generate(&song, 1);
+
+        assert_eq!(beep_audio[..], audio[..100]);
+        let nudged = 26_460 + 26_460 / 4;
+        assert_eq!(Frame::default(), audio[26_460 + 10]);
+        for idx in 0..100 {
+            assert_eq!(beep_audio[idx] * 0.5, audio[nudged + idx]);
+        }
+    }
+
+    #[test]
+    fn steps_play_with_their_probability() {
+        let song = test_song(SongConfig::default());
+        let pattern = [[true; 16].sound(beep()).probability([0.5; 16])];
+        let hits = |audio: &[Frame]| {
+            (0..16 * 4)
+                .filter(|step| audio[song.beats(*step as f64) + 50] != Frame::default())
+                .count()
+        };
+
+        let audio = pattern.generate(&song, 4);
+        let count = hits(&audio);
+        assert!((16..=48).contains(&count), "{} hits", count);
+
+        // The numbers come from the seed of the song
+        song.reset_rngs();
+        assert_eq!(audio, pattern.generate(&song, 4));
+
+        // Steps with no chance never play, and the ones that always play don't use random numbers
+        let audio = [[true; 4].sound(beep()).probability([0., 1., 0., 1.])].generate(&song, 1);
+        assert_eq!(
+            2,
+            (0..4)
+                .filter(|step| audio[song.beats(*step as f64) + 50] != Frame::default())
+                .count()
+        );
+    }
+
+    #[test]
+    fn negating_keeps_the_settings_of_the_steps() {
+        let pattern = [true, false].sound(beep()).velocity([0.2, 0.7]).neg();
+        assert_eq!([false, true], pattern.pattern);
+        assert_eq!([0.2, 0.7], pattern.velocities);
+    }
 
     #[test]
     fn test_adding_vecs_from() {
@@ -158,7 +282,7 @@ mod test {
 
         let other = vec![Frame::mono(1.); 5];
 
-        let res = add_vecs_starting_from(base, 7, &other);
+        let res = add_vecs_starting_from(base, 7, &other, 1.);
 
         assert_eq!(12, res.len());
         assert_eq!(Frame::default(), res[6]);
@@ -176,6 +300,26 @@ mod test {
         assert_eq!(res, map_steps_to_bools(pat));
     }
 
+    #[test]
+    fn sounds_are_sequenced_by_index() {
+        let sounds: Vec<Sound> = (0..3)
+            .map(|idx| Sound::from("loop.wav").begin(idx))
+            .collect();
+        let patterns = sequence_sounds(&sounds, [Some(2), None, Some(0), Some(2)]);
+
+        assert_eq!(2, patterns.len());
+        assert_eq!(sounds[0], patterns[0].sound);
+        assert_eq!([false, false, true, false], patterns[0].pattern);
+        assert_eq!(sounds[2], patterns[1].sound);
+        assert_eq!([true, false, false, true], patterns[1].pattern);
+    }
+
+    #[test]
+    #[should_panic]
+    fn sequencing_missing_sounds_panics() {
+        sequence_sounds(&[Sound::from("loop.wav")], [Some(0), Some(1)]);
+    }
+
     #[test]
     fn nums_to_bools() {
         let num = 0b01001usize;
@@ -0,0 +1,928 @@
+//! SoundFont 2 files, and a player that renders notes with one of their presets
+//!
+//! A preset is made of zones that point to instruments, whose zones point to samples.
+//! The player supports the generators of the sample addresses, loops, tuning, pan,
+//! attenuation and volume envelope. Modulators are ignored, except for the default curve
+//! that makes soft notes quieter
+
+use super::sfz::LoopMode;
+use crate::error::{Error, Result};
+use crate::frame::Frame;
+use crate::signals::adsr::Adsr;
+use crate::song::Song;
+use crate::synth::Instrument;
+use crate::trigger::{Frequency, TriggerListExtension};
+use std::collections::BTreeMap;
+use std::fs;
+
+// Operators of the generators used by the player
+const START_ADDRS_OFFSET: u16 = 0;
+const END_ADDRS_OFFSET: u16 = 1;
+const STARTLOOP_ADDRS_OFFSET: u16 = 2;
+const ENDLOOP_ADDRS_OFFSET: u16 = 3;
+const START_ADDRS_COARSE_OFFSET: u16 = 4;
+const END_ADDRS_COARSE_OFFSET: u16 = 12;
+const PAN: u16 = 17;
+const DELAY_VOL_ENV: u16 = 33;
+const ATTACK_VOL_ENV: u16 = 34;
+const HOLD_VOL_ENV: u16 = 35;
+const DECAY_VOL_ENV: u16 = 36;
+const SUSTAIN_VOL_ENV: u16 = 37;
+const RELEASE_VOL_ENV: u16 = 38;
+const KEYNUM_TO_VOL_ENV_HOLD: u16 = 39;
+const KEYNUM_TO_VOL_ENV_DECAY: u16 = 40;
+const INSTRUMENT: u16 = 41;
+const KEY_RANGE: u16 = 43;
+const VEL_RANGE: u16 = 44;
+const STARTLOOP_ADDRS_COARSE_OFFSET: u16 = 45;
+const KEYNUM: u16 = 46;
+const VELOCITY: u16 = 47;
+const INITIAL_ATTENUATION: u16 = 48;
+const ENDLOOP_ADDRS_COARSE_OFFSET: u16 = 50;
+const COARSE_TUNE: u16 = 51;
+const FINE_TUNE: u16 = 52;
+const SAMPLE_ID: u16 = 53;
+const SAMPLE_MODES: u16 = 54;
+const SCALE_TUNING: u16 = 56;
+const EXCLUSIVE_CLASS: u16 = 57;
+const OVERRIDING_ROOT_KEY: u16 = 58;
+
+/// Value of a generator that isn't in the zones
+const fn default_amount(op: u16) -> i32 {
+    match op {
+        DELAY_VOL_ENV..=DECAY_VOL_ENV | RELEASE_VOL_ENV => -12_000,
+        SCALE_TUNING => 100,
+        KEYNUM | VELOCITY | OVERRIDING_ROOT_KEY => -1,
+        _ => 0,
+    }
+}
+
+/// Generators that are ignored in preset zones
+const fn is_instrument_only(op: u16) -> bool {
+    matches!(
+        op,
+        START_ADDRS_OFFSET..=START_ADDRS_COARSE_OFFSET
+            | END_ADDRS_COARSE_OFFSET
+            | STARTLOOP_ADDRS_COARSE_OFFSET..=VELOCITY
+            | ENDLOOP_ADDRS_COARSE_OFFSET
+            | SAMPLE_MODES
+            | EXCLUSIVE_CLASS
+            | OVERRIDING_ROOT_KEY
+    )
+}
+
+/// Part of a preset or instrument that plays for a range of keys and velocities
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sf2Zone {
+    pub key_range: (u8, u8),
+    pub vel_range: (u8, u8),
+    /// Amounts of the generators by operator, including the ones of the global zone
+    pub generators: BTreeMap<u16, i16>,
+    /// Index of the instrument for preset zones, or of the sample for instrument zones
+    pub link: usize,
+}
+
+impl Default for Sf2Zone {
+    fn default() -> Self {
+        Self {
+            key_range: (0, 127),
+            vel_range: (0, 127),
+            generators: BTreeMap::new(),
+            link: 0,
+        }
+    }
+}
+
+impl Sf2Zone {
+    fn matches(&self, key: u8, velocity: u8) -> bool {
+        (self.key_range.0..=self.key_range.1).contains(&key)
+            && (self.vel_range.0..=self.vel_range.1).contains(&velocity)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sf2Preset {
+    pub name: String,
+    pub bank: u16,
+    pub preset: u16,
+    pub zones: Vec<Sf2Zone>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sf2Instrument {
+    pub name: String,
+    pub zones: Vec<Sf2Zone>,
+}
+
+/// Header of a sample, with the positions in samples from the start of the sample data
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sf2Sample {
+    pub name: String,
+    pub start: usize,
+    /// First sample after the end
+    pub end: usize,
+    pub loop_start: usize,
+    /// First sample after the loop
+    pub loop_end: usize,
+    pub sample_rate: u32,
+    /// Key recorded in the sample, 255 if it's unpitched
+    pub original_pitch: u8,
+    /// Cents to add to the pitch
+    pub pitch_correction: i8,
+}
+
+/// Presets, instruments and sample data of an SF2 file
+#[derive(Clone, Debug)]
+pub struct SoundFont {
+    path: String,
+    pub name: String,
+    pub presets: Vec<Sf2Preset>,
+    pub instruments: Vec<Sf2Instrument>,
+    pub samples: Vec<Sf2Sample>,
+    /// Every sample of the file, from -1 to 1, in `f32` to halve the memory of big files
+    data: Vec<f32>,
+}
+
+impl SoundFont {
+    /// Loads an SF2 file, including all its samples
+    ///
+    /// # Panics
+    ///
+    /// Panics if the file can't be loaded. See `try_open` for a version that returns an error
+    pub fn open(path: &str) -> Self {
+        Self::try_open(path).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Loads an SF2 file, including all its samples
+    pub fn try_open(path: &str) -> Result<Self> {
+        let bytes = fs::read(path).map_err(Error::io(path))?;
+        let font = parse(&bytes).map_err(|message| Error::SoundFont {
+            path: path.to_string(),
+            message,
+        })?;
+        Ok(Self {
+            path: path.to_string(),
+            ..font
+        })
+    }
+
+    /// Preset with this bank and number, where General MIDI drums are in bank 128
+    pub fn preset(&self, bank: u16, preset: u16) -> Option<&Sf2Preset> {
+        self.presets
+            .iter()
+            .find(|p| p.bank == bank && p.preset == preset)
+    }
+
+    /// Player for a preset
+    ///
+    /// # Panics
+    ///
+    /// Panics if there's no such preset. See `try_player` for a version that returns an error
+    pub fn player(&self, bank: u16, preset: u16) -> SoundFontPlayer<'_> {
+        self.try_player(bank, preset)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Player for a preset
+    pub fn try_player(&self, bank: u16, preset: u16) -> Result<SoundFontPlayer<'_>> {
+        let found = self.preset(bank, preset).ok_or_else(|| Error::SoundFont {
+            path: self.path.clone(),
+            message: format!("There's no preset {} in bank {}", preset, bank),
+        })?;
+        Ok(SoundFontPlayer {
+            font: self,
+            preset: found,
+            velocity: 100,
+        })
+    }
+}
+
+/// Generators of an instrument zone, with the ones of the preset zone added
+#[derive(Clone, Copy)]
+struct Generators<'a> {
+    preset: &'a Sf2Zone,
+    instrument: &'a Sf2Zone,
+}
+
+impl Generators<'_> {
+    fn amount(&self, op: u16) -> i32 {
+        let value = self
+            .instrument
+            .generators
+            .get(&op)
+            .map_or_else(|| default_amount(op), |&value| i32::from(value));
+        if is_instrument_only(op) {
+            value
+        } else {
+            value + self.preset.generators.get(&op).map_or(0, |&v| i32::from(v))
+        }
+    }
+
+    /// Time in seconds of a generator in timecents
+    fn seconds(&self, op: u16) -> f64 {
+        (f64::from(self.amount(op)) / 1200.).exp2()
+    }
+
+    fn matches(&self, key: u8, velocity: u8) -> bool {
+        self.preset.matches(key, velocity) && self.instrument.matches(key, velocity)
+    }
+}
+
+/// Volume envelope of a voice, with the times in seconds
+///
+/// The attack is linear and the decay and release fall in decibels,
+/// 100dB over their time
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sf2Envelope {
+    pub delay: f64,
+    pub attack: f64,
+    pub hold: f64,
+    pub decay: f64,
+    /// Level after the decay, from 0 to 1
+    pub sustain: f64,
+    pub release: f64,
+}
+
+impl Sf2Envelope {
+    /// Level at `time` seconds after the start of a note that's still held
+    fn held_level(&self, time: f64) -> f64 {
+        let mut time = time - self.delay;
+        if time < 0. {
+            return 0.;
+        }
+        if time < self.attack {
+            return time / self.attack;
+        }
+        time -= self.attack + self.hold;
+        if time < 0. || self.decay <= 0. {
+            return if time < 0. { 1. } else { self.sustain };
+        }
+        let decibels = -100. * time / self.decay;
+        10_f64.powf(decibels / 20.).max(self.sustain)
+    }
+
+    /// Level at `time` seconds, with the note released at `note_off` seconds
+    fn level(&self, time: f64, note_off: f64) -> f64 {
+        if time < note_off {
+            return self.held_level(time);
+        }
+        let released = time - note_off;
+        if released >= self.release {
+            0.
+        } else {
+            self.held_level(note_off) * 10_f64.powf(-5. * released / self.release)
+        }
+    }
+}
+
+/// A sample played by a note, with the generators of its zones applied
+struct Voice<'a> {
+    audio: &'a [f32],
+    loop_mode: LoopMode,
+    /// Start and end of the loop from the start of `audio`, with the end excluded
+    loop_points: Option<(usize, usize)>,
+    /// Samples of `audio` to move for every output sample
+    step: f64,
+    left: f64,
+    right: f64,
+    envelope: Sf2Envelope,
+}
+
+impl Voice<'_> {
+    fn render(&self, sample_rate: u32, note_off: usize, out: &mut [Frame]) {
+        let loop_points = self
+            .loop_points
+            .map(|(start, end)| (start as f64, end as f64))
+            .filter(|(start, end)| end > start);
+        let seconds = |samples: usize| samples as f64 / f64::from(sample_rate);
+
+        let mut position = 0.;
+        for (idx, frame) in out.iter_mut().enumerate() {
+            let looping = match self.loop_mode {
+                LoopMode::Continuous => true,
+                LoopMode::Sustain => idx < note_off,
+                LoopMode::NoLoop | LoopMode::OneShot => false,
+            };
+            if let Some((start, end)) = loop_points.filter(|_| looping) {
+                if position >= end {
+                    position = start + (position - start) % (end - start);
+                }
+            }
+
+            let sample = position as usize;
+            if sample >= self.audio.len() {
+                break;
+            }
+            let fraction = position - sample as f64;
+            let next = self.audio.get(sample + 1).copied().unwrap_or_default();
+            let value =
+                f64::from(self.audio[sample]).mul_add(1. - fraction, f64::from(next) * fraction);
+
+            let value = value * self.envelope.level(seconds(idx), seconds(note_off));
+            *frame += Frame::new(value * self.left, value * self.right);
+            position += self.step;
+        }
+    }
+}
+
+/// Plays notes with a preset of a `SoundFont`
+#[derive(Clone, Debug)]
+pub struct SoundFontPlayer<'a> {
+    font: &'a SoundFont,
+    preset: &'a Sf2Preset,
+    /// Velocity used for every note, from 1 to 127
+    pub velocity: u8,
+}
+
+impl<'a> SoundFontPlayer<'a> {
+    /// Sets the velocity used for every note
+    pub fn velocity(mut self, velocity: u8) -> Self {
+        self.velocity = velocity.clamp(1, 127);
+        self
+    }
+
+    /// Every instrument zone of the preset, with its preset zone
+    fn zones(&self) -> impl Iterator<Item = Generators<'a>> + 'a {
+        let font = self.font;
+        self.preset
+            .zones
+            .iter()
+            .filter_map(move |preset| {
+                font.instruments
+                    .get(preset.link)
+                    .map(|instrument| (preset, instrument))
+            })
+            .flat_map(|(preset, instrument)| {
+                instrument
+                    .zones
+                    .iter()
+                    .map(move |instrument| Generators { preset, instrument })
+            })
+    }
+
+    /// Longest release of the zones, in samples
+    pub fn release(&self, sample_rate: u32) -> usize {
+        self.zones()
+            .map(|zone| (zone.seconds(RELEASE_VOL_ENV) * f64::from(sample_rate)).ceil() as usize)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Adsr to pass to `TriggerListExtension::generate`, so notes overlap during the release
+    pub fn default_adsr(&self, sample_rate: u32) -> Adsr {
+        Adsr {
+            release: self.release(sample_rate),
+            ..Default::default()
+        }
+    }
+
+    /// Plays `frequency` for `length` samples, with the release at the end
+    pub fn note(&self, song: &Song, frequency: Frequency, length: usize) -> Vec<Frame> {
+        self.note_at(frequency, length, song.sample_rate())
+    }
+
+    fn note_at(&self, frequency: Frequency, length: usize, sample_rate: u32) -> Vec<Frame> {
+        let key = 12_f64.mul_add((frequency / 440.).log2(), 69.);
+        let rounded = key.round().clamp(0., 127.) as u8;
+        let note_off = length.saturating_sub(self.release(sample_rate));
+
+        let mut out = vec![Frame::default(); length];
+        for zone in self
+            .zones()
+            .filter(|zone| zone.matches(rounded, self.velocity))
+        {
+            if let Some(voice) = self.voice(zone, key, sample_rate) {
+                voice.render(sample_rate, note_off, &mut out);
+            }
+        }
+        out
+    }
+
+    fn voice(&self, zone: Generators<'a>, key: f64, sample_rate: u32) -> Option<Voice<'a>> {
+        let sample = self.font.samples.get(zone.instrument.link)?;
+        let data = &self.font.data;
+        let address = |base: usize, fine: u16, coarse: u16| {
+            let address = base as i64
+                + i64::from(zone.amount(fine))
+                + 32_768 * i64::from(zone.amount(coarse));
+            address.clamp(0, data.len() as i64) as usize
+        };
+        let start = address(sample.start, START_ADDRS_OFFSET, START_ADDRS_COARSE_OFFSET);
+        let end = address(sample.end, END_ADDRS_OFFSET, END_ADDRS_COARSE_OFFSET).max(start);
+        let loop_start = address(
+            sample.loop_start,
+            STARTLOOP_ADDRS_OFFSET,
+            STARTLOOP_ADDRS_COARSE_OFFSET,
+        );
+        let loop_end = address(
+            sample.loop_end,
+            ENDLOOP_ADDRS_OFFSET,
+            ENDLOOP_ADDRS_COARSE_OFFSET,
+        );
+
+        let key = match zone.amount(KEYNUM) {
+            keynum if keynum >= 0 => f64::from(keynum),
+            _ => key,
+        };
+        let velocity = match zone.amount(VELOCITY) {
+            velocity if velocity > 0 => f64::from(velocity.min(127)),
+            _ => f64::from(self.velocity),
+        };
+        let root = match zone.amount(OVERRIDING_ROOT_KEY) {
+            root if root >= 0 => f64::from(root),
+            _ if sample.original_pitch <= 127 => f64::from(sample.original_pitch),
+            _ => 60.,
+        };
+        let cents = (key - root).mul_add(
+            f64::from(zone.amount(SCALE_TUNING)),
+            f64::from(
+                zone.amount(COARSE_TUNE) * 100
+                    + zone.amount(FINE_TUNE)
+                    + i32::from(sample.pitch_correction),
+            ),
+        );
+        let step = (cents / 1200.).exp2() * f64::from(sample.sample_rate) / f64::from(sample_rate);
+
+        // Attenuation in centibels, with soft notes quieter like the default velocity modulator
+        let attenuation = f64::from(zone.amount(INITIAL_ATTENUATION).max(0));
+        let gain = 10_f64.powf(-attenuation / 200.) * (velocity / 127.).powi(2);
+        let pan = f64::from(zone.amount(PAN).clamp(-500, 500)) / 500.;
+
+        // Hold and decay change with the key, in timecents per key away from C4
+        let key_scaling = |op: u16| (f64::from(zone.amount(op)) * (60. - key) / 1200.).exp2();
+        let envelope = Sf2Envelope {
+            delay: zone.seconds(DELAY_VOL_ENV),
+            attack: zone.seconds(ATTACK_VOL_ENV),
+            hold: zone.seconds(HOLD_VOL_ENV) * key_scaling(KEYNUM_TO_VOL_ENV_HOLD),
+            decay: zone.seconds(DECAY_VOL_ENV) * key_scaling(KEYNUM_TO_VOL_ENV_DECAY),
+            sustain: 10_f64.powf(-f64::from(zone.amount(SUSTAIN_VOL_ENV).clamp(0, 1440)) / 200.),
+            release: zone.seconds(RELEASE_VOL_ENV),
+        };
+
+        Some(Voice {
+            audio: &data[start..end],
+            loop_mode: match zone.amount(SAMPLE_MODES) & 3 {
+                1 => LoopMode::Continuous,
+                3 => LoopMode::Sustain,
+                _ => LoopMode::NoLoop,
+            },
+            loop_points: Some((
+                loop_start.saturating_sub(start),
+                loop_end.saturating_sub(start),
+            )),
+            step,
+            left: gain * (1. - pan).min(1.),
+            right: gain * (1. + pan).min(1.),
+            envelope,
+        })
+    }
+
+    /// Renders the triggers
+    pub fn render(&self, song: &Song, triggers: &impl TriggerListExtension) -> Vec<Frame> {
+        triggers.generate(
+            song,
+            &mut |frequency, length| self.note(song, frequency, length),
+            self.default_adsr(song.sample_rate()),
+        )
+    }
+}
+
+impl Instrument for SoundFontPlayer<'_> {
+    fn generate(&self, length: usize, frequency: Frequency, sample_rate: u32) -> Vec<Frame> {
+        self.note_at(frequency, length, sample_rate)
+    }
+}
+
+/// Id and content of a RIFF chunk
+type Chunk<'a> = (&'a [u8], &'a [u8]);
+
+/// Splits RIFF data into chunks
+fn chunks(mut data: &[u8]) -> std::result::Result<Vec<Chunk<'_>>, String> {
+    let mut chunks = vec![];
+    while data.len() >= 8 {
+        let size = u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as usize;
+        let content = data
+            .get(8..8 + size)
+            .ok_or_else(|| "A chunk is longer than the file".to_string())?;
+        chunks.push((&data[..4], content));
+        // Chunks are padded to an even size
+        data = data.get(8 + size + size % 2..).unwrap_or_default();
+    }
+    Ok(chunks)
+}
+
+/// Records of a chunk with fixed size records, including the terminal record
+fn records<'a>(
+    chunks: &[Chunk<'a>],
+    id: &[u8],
+    size: usize,
+) -> std::result::Result<Vec<&'a [u8]>, String> {
+    let name = String::from_utf8_lossy(id);
+    let (_, content) = chunks
+        .iter()
+        .find(|(chunk_id, _)| *chunk_id == id)
+        .ok_or_else(|| format!("Missing {} chunk", name))?;
+    if content.len() % size != 0 || content.len() < size {
+        return Err(format!("Invalid size of the {} chunk", name));
+    }
+    Ok(content.chunks_exact(size).collect())
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+/// Name in the first 20 bytes of a record, which ends at the first null
+fn record_name(bytes: &[u8]) -> String {
+    let name = &bytes[..20];
+    let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+    String::from_utf8_lossy(&name[..len]).trim().to_string()
+}
+
+/// Zones of a preset or instrument from its bags, merging the global zone into the others
+///
+/// `link` is the operator that ends every zone that isn't global
+fn read_zones(
+    bags: &[usize],
+    generators: &[&[u8]],
+    range: std::ops::Range<usize>,
+    link: u16,
+) -> std::result::Result<Vec<Sf2Zone>, String> {
+    let invalid = || "Invalid zone indices".to_string();
+    let mut zones = vec![];
+    let mut global = None;
+    for bag in range.clone() {
+        let first = *bags.get(bag).ok_or_else(invalid)?;
+        let last = *bags.get(bag + 1).ok_or_else(invalid)?;
+        let mut zone: Sf2Zone = global.clone().unwrap_or_default();
+        let mut linked = None;
+        for generator in generators.get(first..last).ok_or_else(invalid)? {
+            let op = u16_at(generator, 0);
+            let amount = [generator[2], generator[3]];
+            match op {
+                KEY_RANGE => zone.key_range = amount.into(),
+                VEL_RANGE => zone.vel_range = amount.into(),
+                op if op == link => linked = Some(usize::from(u16::from_le_bytes(amount))),
+                op => {
+                    zone.generators.insert(op, i16::from_le_bytes(amount));
+                }
+            }
+        }
+        match linked {
+            Some(link) => zones.push(Sf2Zone { link, ..zone }),
+            // Only the first zone can be global
+            None if bag == range.start => global = Some(zone),
+            None => {}
+        }
+    }
+    Ok(zones)
+}
+
+/// Parses the content of an SF2 file
+fn parse(bytes: &[u8]) -> std::result::Result<SoundFont, String> {
+    if bytes.len() < 12 || &bytes[..4] != b"RIFF" || &bytes[8..12] != b"sfbk" {
+        return Err("Not a SoundFont 2 file".to_string());
+    }
+    let mut info = vec![];
+    let mut sdta = vec![];
+    let mut pdta = vec![];
+    for (id, content) in chunks(&bytes[12..])? {
+        if id == b"LIST" && content.len() >= 4 {
+            match &content[..4] {
+                b"INFO" => info = chunks(&content[4..])?,
+                b"sdta" => sdta = chunks(&content[4..])?,
+                b"pdta" => pdta = chunks(&content[4..])?,
+                _ => {}
+            }
+        }
+    }
+
+    let name = info
+        .iter()
+        .find(|(id, _)| *id == b"INAM")
+        .map(|(_, name)| {
+            let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+            String::from_utf8_lossy(&name[..len]).into_owned()
+        })
+        .unwrap_or_default();
+
+    // 16 bit samples, with 8 more bits in the optional sm24 chunk
+    let smpl = sdta
+        .iter()
+        .find(|(id, _)| *id == b"smpl")
+        .map_or(&[][..], |(_, content)| content);
+    let sm24 = sdta
+        .iter()
+        .find(|(id, _)| *id == b"sm24")
+        .map(|(_, content)| content)
+        .filter(|sm24| sm24.len() >= smpl.len() / 2);
+    let data = smpl
+        .chunks_exact(2)
+        .enumerate()
+        .map(|(idx, sample)| {
+            let high = i32::from(i16::from_le_bytes([sample[0], sample[1]]));
+            let low = sm24.map_or(0, |sm24| i32::from(sm24[idx]));
+            ((high << 8) | low) as f32 / 8_388_608.
+        })
+        .collect();
+
+    // Indices of the first generator of every bag
+    let bags = |id: &[u8]| {
+        Ok::<_, String>(
+            records(&pdta, id, 4)?
+                .iter()
+                .map(|bag| usize::from(u16_at(bag, 0)))
+                .collect::<Vec<_>>(),
+        )
+    };
+
+    // Every header ends where the next one starts, so the terminal header isn't returned
+    let preset_bags = bags(b"pbag")?;
+    let preset_generators = records(&pdta, b"pgen", 4)?;
+    let presets = records(&pdta, b"phdr", 38)?
+        .windows(2)
+        .map(|headers| {
+            Ok(Sf2Preset {
+                name: record_name(headers[0]),
+                preset: u16_at(headers[0], 20),
+                bank: u16_at(headers[0], 22),
+                zones: read_zones(
+                    &preset_bags,
+                    &preset_generators,
+                    usize::from(u16_at(headers[0], 24))..usize::from(u16_at(headers[1], 24)),
+                    INSTRUMENT,
+                )?,
+            })
+        })
+        .collect::<std::result::Result<_, String>>()?;
+
+    let instrument_bags = bags(b"ibag")?;
+    let instrument_generators = records(&pdta, b"igen", 4)?;
+    let instruments = records(&pdta, b"inst", 22)?
+        .windows(2)
+        .map(|headers| {
+            Ok(Sf2Instrument {
+                name: record_name(headers[0]),
+                zones: read_zones(
+                    &instrument_bags,
+                    &instrument_generators,
+                    usize::from(u16_at(headers[0], 20))..usize::from(u16_at(headers[1], 20)),
+                    SAMPLE_ID,
+                )?,
+            })
+        })
+        .collect::<std::result::Result<_, String>>()?;
+
+    let sample_headers = records(&pdta, b"shdr", 46)?;
+    let samples = sample_headers[..sample_headers.len() - 1]
+        .iter()
+        .map(|header| {
+            let position = |offset: usize| u32_at(header, offset) as usize;
+            Sf2Sample {
+                name: record_name(header),
+                start: position(20),
+                end: position(24),
+                loop_start: position(28),
+                loop_end: position(32),
+                sample_rate: u32_at(header, 36),
+                original_pitch: header[40],
+                pitch_correction: i8::from_le_bytes([header[41]]),
+            }
+        })
+        .collect();
+
+    Ok(SoundFont {
+        path: String::new(),
+        name,
+        presets,
+        instruments,
+        samples,
+        data,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::song::{test_song, SongConfig};
+    use crate::trigger::IntoTrigger;
+
+    fn chunk(id: &[u8], content: &[u8]) -> Vec<u8> {
+        let mut bytes = id.to_vec();
+        bytes.extend(&(content.len() as u32).to_le_bytes());
+        bytes.extend(content);
+        if content.len() % 2 == 1 {
+            bytes.push(0);
+        }
+        bytes
+    }
+
+    fn list(kind: &[u8], chunks: &[Vec<u8>]) -> Vec<u8> {
+        chunk(b"LIST", &[kind.to_vec(), chunks.concat()].concat())
+    }
+
+    fn name(name: &str) -> Vec<u8> {
+        let mut bytes = name.as_bytes().to_vec();
+        bytes.resize(20, 0);
+        bytes
+    }
+
+    /// Generators with amounts, followed by the terminal generator
+    fn generators(generators: &[(u16, [u8; 2])]) -> Vec<u8> {
+        let mut bytes = vec![];
+        for (op, amount) in generators.iter().chain(&[(0, [0, 0])]) {
+            bytes.extend(&op.to_le_bytes());
+            bytes.extend(amount);
+        }
+        bytes
+    }
+
+    fn bags(first_generators: &[u16]) -> Vec<u8> {
+        first_generators
+            .iter()
+            .flat_map(|generator| [generator.to_le_bytes(), [0, 0]].concat())
+            .collect()
+    }
+
+    fn sample_header(
+        name_: &str,
+        start: u32,
+        end: u32,
+        loop_points: (u32, u32),
+        rate: u32,
+        pitch: u8,
+    ) -> Vec<u8> {
+        let mut bytes = name(name_);
+        for value in &[start, end, loop_points.0, loop_points.1, rate] {
+            bytes.extend(&value.to_le_bytes());
+        }
+        bytes.extend(&[pitch, 0, 0, 0, 1, 0]);
+        bytes
+    }
+
+    /// Piano preset with an instrument that plays a sample of 0.5 with a loop for the low keys,
+    /// and a sample of 0.25 at half the sample rate panned left for the high keys
+    fn write_font(name_: &str) -> String {
+        let mut smpl = vec![];
+        for value in &[16_384_i16, 8_192] {
+            for idx in 0..1_046 {
+                let value = if idx < 1_000 { *value } else { 0 };
+                smpl.extend(&value.to_le_bytes());
+            }
+        }
+
+        let amount = |value: i16| value.to_le_bytes();
+        let pdta = list(
+            b"pdta",
+            &[
+                chunk(
+                    b"phdr",
+                    &[
+                        [name("Piano"), vec![0, 0, 0, 0, 0, 0], vec![0; 12]].concat(),
+                        [name("Drums"), vec![0, 0, 128, 0, 1, 0], vec![0; 12]].concat(),
+                        [name("EOP"), vec![0, 0, 0, 0, 1, 0], vec![0; 12]].concat(),
+                    ]
+                    .concat(),
+                ),
+                chunk(b"pbag", &bags(&[0, 2])),
+                chunk(
+                    b"pgen",
+                    &generators(&[(INITIAL_ATTENUATION, amount(200)), (INSTRUMENT, amount(0))]),
+                ),
+                chunk(
+                    b"inst",
+                    &[name("Keys"), vec![0, 0], name("EOI"), vec![3, 0]].concat(),
+                ),
+                chunk(b"ibag", &bags(&[0, 1, 4, 7])),
+                chunk(
+                    b"igen",
+                    &generators(&[
+                        (RELEASE_VOL_ENV, amount(-7_973)),
+                        (KEY_RANGE, [0, 59]),
+                        (SAMPLE_MODES, amount(1)),
+                        (SAMPLE_ID, amount(0)),
+                        (KEY_RANGE, [60, 127]),
+                        (PAN, amount(-500)),
+                        (SAMPLE_ID, amount(1)),
+                    ]),
+                ),
+                chunk(
+                    b"shdr",
+                    &[
+                        sample_header("Half", 0, 1_000, (100, 200), 44_100, 59),
+                        sample_header("Quarter", 1_046, 2_046, (1_046, 1_046), 22_050, 72),
+                        sample_header("EOS", 0, 0, (0, 0), 0, 0),
+                    ]
+                    .concat(),
+                ),
+            ],
+        );
+        let body = [
+            b"sfbk".to_vec(),
+            list(b"INFO", &[chunk(b"INAM", b"Test font\0")]),
+            list(b"sdta", &[chunk(b"smpl", &smpl)]),
+            pdta,
+        ]
+        .concat();
+
+        let path = std::env::temp_dir().join(name_).with_extension("sf2");
+        fs::write(&path, chunk(b"RIFF", &body)).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn presets_point_to_instruments_and_samples() {
+        let path = write_font("dawremi-sf2-parse");
+        let font = SoundFont::open(&path);
+        assert_eq!("Test font", font.name);
+
+        assert_eq!(2, font.presets.len());
+        let piano = font.preset(0, 0).unwrap();
+        assert_eq!("Piano", piano.name);
+        assert_eq!(1, piano.zones.len());
+        assert_eq!(
+            Some(&200),
+            piano.zones[0].generators.get(&INITIAL_ATTENUATION)
+        );
+        assert!(font.preset(128, 0).unwrap().zones.is_empty());
+
+        // The global zone is merged into the other zones
+        let zones = &font.instruments[0].zones;
+        assert_eq!(2, zones.len());
+        assert_eq!((0, 59), zones[0].key_range);
+        assert_eq!((60, 127), zones[1].key_range);
+        assert_eq!(1, zones[1].link);
+        assert_eq!(Some(&-7_973), zones[1].generators.get(&RELEASE_VOL_ENV));
+
+        assert_eq!(2, font.samples.len());
+        assert_eq!("Quarter", font.samples[1].name);
+        assert_eq!((1_046, 2_046), (font.samples[1].start, font.samples[1].end));
+        assert_eq!(22_050, font.samples[1].sample_rate);
+
+        assert!(matches!(
+            font.try_player(0, 5),
+            Err(Error::SoundFont { .. })
+        ));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn notes_use_the_generators_of_both_zones() {
+        let path = write_font("dawremi-sf2-notes");
+        let font = SoundFont::open(&path);
+        let song = test_song(SongConfig::default());
+        let piano = font.player(0, 0).velocity(127);
+        let release = piano.release(44_100);
+        assert_eq!(441, release);
+
+        // The low sample loops after its end, with the attenuation of the preset
+        let low = piano.note(&song, 246.941_650_628_1, 5_000 + release);
+        assert!((low[3_000].left - 0.05).abs() < 1e-4);
+        assert!((low[3_000].right - 0.05).abs() < 1e-4);
+        assert!(low[5_000 + release - 1].left.abs() < 1e-4);
+
+        // The high one plays at half the speed because of its sample rate, stops at its end and is panned left
+        let high = piano.note(&song, 523.251_130_601_2, 3_000);
+        assert!((high[1_000].left - 0.025).abs() < 1e-4);
+        assert_eq!(0., high[1_000].right);
+        assert_eq!(Frame::default(), high[2_500]);
+
+        // Soft notes are quieter
+        let soft = font.player(0, 0).velocity(64);
+        let ratio = soft.note(&song, 246.941_650_628_1, 2_000)[1_000].left / low[1_000].left;
+        // Velocity 64 of 127, squared
+        assert!((ratio - 4_096. / 16_129.).abs() < 1e-4);
+
+        // Presets without zones are silent
+        let drums = font.player(128, 0);
+        assert!(drums
+            .note(&song, 440., 100)
+            .iter()
+            .all(|frame| *frame == Frame::default()));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn triggers_overlap_during_the_release() {
+        let path = write_font("dawremi-sf2-triggers");
+        let font = SoundFont::open(&path);
+        let piano = font.player(0, 0).velocity(127);
+        let triggers = vec![[246.941_650_628_1].samples(5_000)];
+        let audio = piano.render(&test_song(SongConfig::default()), &triggers);
+        assert_eq!(5_000 + 441 + 1, audio.len());
+        assert!((audio[3_000].left - 0.05).abs() < 1e-4);
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,820 @@
+//! Sampled instruments in the SFZ format
+//!
+//! The regions of an instrument are picked by key, velocity and round robin, and their samples
+//! are loaded with `Song::sound`, so they are resampled and cached like any other sound.
+//! Supported opcodes:
+//!
+//! - `sample`, `default_path` (in `<control>`), `offset`
+//! - `key`, `lokey`, `hikey`, `lovel`, `hivel`
+//! - `pitch_keycenter` (a key or `sample`), `pitch_keytrack`, `transpose`, `tune`
+//! - `loop_mode`, `loop_start`, `loop_end`
+//! - `volume`, `amp_veltrack`, `ampeg_delay`, `ampeg_attack`, `ampeg_hold`, `ampeg_decay`,
+//!   `ampeg_sustain`, `ampeg_release`
+//! - `seq_length`, `seq_position`, `lorand`, `hirand`
+//!
+//! Other opcodes are ignored, and so are regions with `trigger=release`
+
+use super::library::{parse_note_name, read_sample, Sample};
+use crate::error::{Error, Result};
+use crate::frame::Frame;
+use crate::signals::adsr::Adsr;
+use crate::song::Song;
+use crate::trigger::{Frequency, TriggerListExtension};
+use rand::Rng;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// What a region does when it reaches its loop points
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoopMode {
+    /// Plays the sample once, stopping early if the note is released
+    NoLoop,
+    /// Plays the whole sample, even after the note is released, without a release stage
+    OneShot,
+    /// Loops until the end of the note, including the release
+    Continuous,
+    /// Loops while the note is held, and plays the rest of the sample during the release
+    Sustain,
+}
+
+/// Amplitude envelope of a region, with the times in seconds
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SfzEnvelope {
+    pub delay: f64,
+    pub attack: f64,
+    pub hold: f64,
+    pub decay: f64,
+    /// Level after the decay, from 0 to 1
+    pub sustain: f64,
+    pub release: f64,
+}
+
+impl Default for SfzEnvelope {
+    fn default() -> Self {
+        Self {
+            delay: 0.,
+            attack: 0.,
+            hold: 0.,
+            decay: 0.,
+            sustain: 1.,
+            release: 0.,
+        }
+    }
+}
+
+impl SfzEnvelope {
+    /// Level at `time` seconds after the start of a note that's still held
+    fn held_level(&self, time: f64) -> f64 {
+        let mut time = time - self.delay;
+        if time < 0. {
+            return 0.;
+        }
+        if time < self.attack {
+            return time / self.attack;
+        }
+        time -= self.attack + self.hold;
+        if time < 0. {
+            return 1.;
+        }
+        if time < self.decay {
+            return (self.sustain - 1.).mul_add(time / self.decay, 1.);
+        }
+        self.sustain
+    }
+
+    /// Level at `time` seconds, with the note released at `note_off` seconds
+    fn level(&self, time: f64, note_off: Option<f64>) -> f64 {
+        match note_off {
+            Some(note_off) if time >= note_off => {
+                let released = time - note_off;
+                if released >= self.release {
+                    0.
+                } else {
+                    self.held_level(note_off) * (1. - released / self.release)
+                }
+            }
+            _ => self.held_level(time),
+        }
+    }
+}
+
+/// A sample that plays for a range of keys and velocities
+#[derive(Clone, Debug, PartialEq)]
+pub struct SfzRegion {
+    /// Path to the sample, including the folder of the SFZ file
+    pub sample: String,
+    /// Sample rate of the file
+    pub sample_rate: u32,
+    pub lokey: u8,
+    pub hikey: u8,
+    pub lovel: u8,
+    pub hivel: u8,
+    /// Key at which the sample plays at its original pitch
+    pub pitch_keycenter: f64,
+    /// Cents that the pitch changes for every key
+    pub pitch_keytrack: f64,
+    pub transpose: f64,
+    /// Cents added to the pitch
+    pub tune: f64,
+    /// Gain in dB
+    pub volume: f64,
+    /// Percentage of the amplitude that depends on the velocity
+    pub amp_veltrack: f64,
+    /// Where the sample starts, in frames of the file
+    pub offset: usize,
+    pub loop_mode: LoopMode,
+    /// Start and end of the loop in frames of the file, with the end excluded
+    pub loop_points: Option<(usize, usize)>,
+    pub envelope: SfzEnvelope,
+    /// The region plays once every `seq_length` times, on the `seq_position`th time starting at 1
+    pub seq_length: usize,
+    pub seq_position: usize,
+    /// The region plays when a random number from 0 to 1 is in this range
+    pub random: (f64, f64),
+}
+
+impl SfzRegion {
+    fn matches(&self, key: u8, velocity: u8) -> bool {
+        (self.lokey..=self.hikey).contains(&key) && (self.lovel..=self.hivel).contains(&velocity)
+    }
+
+    /// Ratio between the sample rate of the song and the one of the file
+    fn rate(&self, sample_rate: u32) -> f64 {
+        f64::from(sample_rate) / f64::from(self.sample_rate)
+    }
+
+    /// Frames of the sample that are moved on every frame of the note, when playing `key`
+    fn step(&self, key: f64) -> f64 {
+        let cents = (key - self.pitch_keycenter).mul_add(self.pitch_keytrack, self.tune);
+        let semitones = cents / 100. + self.transpose;
+        (semitones / 12.).exp2()
+    }
+
+    /// Frames it takes to play the sample from the offset to its end, when playing `key`
+    fn remaining(&self, audio: &[Frame], sample_rate: u32, key: f64) -> usize {
+        let start = self.offset as f64 * self.rate(sample_rate);
+        ((audio.len() as f64 - start).max(0.) / self.step(key)).ceil() as usize
+    }
+
+    /// Adds the region to `out`, playing `key` with the note released at `note_off`
+    fn render(
+        &self,
+        audio: &[Frame],
+        sample_rate: u32,
+        key: f64,
+        velocity: u8,
+        note_off: usize,
+        out: &mut [Frame],
+    ) {
+        // The audio is already resampled to the sample rate of the song
+        let rate = self.rate(sample_rate);
+        let step = self.step(key);
+
+        let velocity = f64::from(velocity) / 127.;
+        let veltrack = self.amp_veltrack / 100.;
+        let gain = 10_f64.powf(self.volume / 20.) * veltrack.mul_add(velocity * velocity - 1., 1.);
+
+        let loop_points = self
+            .loop_points
+            .map(|(start, end)| (start as f64 * rate, end as f64 * rate))
+            .filter(|(start, end)| end > start);
+        let note_off = match self.loop_mode {
+            LoopMode::OneShot => None,
+            _ => Some(note_off),
+        };
+
+        let mut position = self.offset as f64 * rate;
+        for (idx, frame) in out.iter_mut().enumerate() {
+            let looping = match self.loop_mode {
+                LoopMode::Continuous => true,
+                LoopMode::Sustain => note_off.is_some_and(|note_off| idx < note_off),
+                LoopMode::NoLoop | LoopMode::OneShot => false,
+            };
+            if let Some((start, end)) = loop_points.filter(|_| looping) {
+                if position >= end {
+                    position = start + (position - start) % (end - start);
+                }
+            }
+
+            let sample = position as usize;
+            if sample >= audio.len() {
+                break;
+            }
+            let fraction = position - sample as f64;
+            let next = audio.get(sample + 1).copied().unwrap_or_default();
+            let value = audio[sample] * (1. - fraction) + next * fraction;
+
+            let seconds = |samples: usize| samples as f64 / f64::from(sample_rate);
+            let level = self.envelope.level(seconds(idx), note_off.map(seconds));
+            *frame += value * (gain * level);
+            position += step;
+        }
+    }
+}
+
+/// Instrument made of the regions of an SFZ file
+#[derive(Debug)]
+pub struct SfzInstrument {
+    pub regions: Vec<SfzRegion>,
+    /// Velocity used for every note, from 1 to 127
+    pub velocity: u8,
+    /// Times each region has matched a note, for the round robins
+    counters: Vec<AtomicUsize>,
+}
+
+impl SfzInstrument {
+    /// Loads an SFZ file, reading the sample rate and loop points of its samples
+    ///
+    /// # Panics
+    ///
+    /// Panics if the file can't be loaded. See `try_open` for a version that returns an error
+    pub fn open(path: &str) -> Self {
+        Self::try_open(path).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Loads an SFZ file, reading the sample rate and loop points of its samples
+    pub fn try_open(path: &str) -> Result<Self> {
+        let source = fs::read_to_string(path).map_err(Error::io(path))?;
+        let dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+        let regions = parse(&source, path, dir)?;
+        Ok(Self {
+            counters: regions.iter().map(|_| AtomicUsize::new(0)).collect(),
+            regions,
+            velocity: 100,
+        })
+    }
+
+    /// Sets the velocity used for every note
+    pub fn velocity(mut self, velocity: u8) -> Self {
+        self.velocity = velocity.clamp(1, 127);
+        self
+    }
+
+    /// Longest release of the regions, in samples
+    pub fn release(&self, sample_rate: u32) -> usize {
+        self.regions
+            .iter()
+            .map(|region| (region.envelope.release * f64::from(sample_rate)).ceil() as usize)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Adsr to pass to `TriggerListExtension::generate`, so notes overlap during the release
+    pub fn default_adsr(&self, sample_rate: u32) -> Adsr {
+        Adsr {
+            release: self.release(sample_rate),
+            ..Default::default()
+        }
+    }
+
+    /// Plays `frequency` for `length` samples, with the release at the end
+    ///
+    /// One-shot regions play until the end of their sample, so the note can be longer than `length`
+    ///
+    /// # Panics
+    ///
+    /// Panics if a sample can't be opened. See `try_note` for a version that returns an error
+    pub fn note(&self, song: &Song, frequency: Frequency, length: usize) -> Vec<Frame> {
+        self.try_note(song, frequency, length)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Plays `frequency` for `length` samples, with the release at the end
+    pub fn try_note(&self, song: &Song, frequency: Frequency, length: usize) -> Result<Vec<Frame>> {
+        let sample_rate = song.sample_rate();
+        let key = 12_f64.mul_add((frequency / 440.).log2(), 69.);
+        let rounded = key.round().clamp(0., 127.) as u8;
+        let random: f64 = song.rng().gen();
+        let note_off = length.saturating_sub(self.release(sample_rate));
+
+        let mut out = vec![Frame::default(); length];
+        for (region, counter) in self.regions.iter().zip(&self.counters) {
+            if !region.matches(rounded, self.velocity) {
+                continue;
+            }
+            let hits = counter.fetch_add(1, Ordering::Relaxed);
+            if hits % region.seq_length + 1 != region.seq_position
+                || random < region.random.0
+                || random >= region.random.1
+            {
+                continue;
+            }
+
+            let audio = song.try_sound(region.sample.as_str())?;
+            if region.loop_mode == LoopMode::OneShot {
+                let remaining = region.remaining(&audio, sample_rate, key);
+                if remaining > out.len() {
+                    out.resize(remaining, Frame::default());
+                }
+            }
+            region.render(&audio, sample_rate, key, self.velocity, note_off, &mut out);
+        }
+        Ok(out)
+    }
+
+    /// Renders the triggers, starting the round robins again
+    ///
+    /// Every note ends with its trigger and release, so one-shot samples get cut there.
+    /// Use `note` to get the whole sample
+    pub fn render(&self, song: &Song, triggers: &impl TriggerListExtension) -> Vec<Frame> {
+        for counter in &self.counters {
+            counter.store(0, Ordering::Relaxed);
+        }
+        triggers.generate(
+            song,
+            &mut |frequency, length| {
+                // Longer notes would push the ones after them
+                let mut note = self.note(song, frequency, length);
+                note.truncate(length);
+                note
+            },
+            self.default_adsr(song.sample_rate()),
+        )
+    }
+}
+
+/// Opcodes of one header, in the order they were written
+type Opcodes = Vec<(String, String)>;
+
+/// Parses the regions of an SFZ file, with the samples relative to `dir`
+fn parse(source: &str, path: &str, dir: &Path) -> Result<Vec<SfzRegion>> {
+    let error = |line: usize, message: String| Error::Sfz {
+        path: path.to_string(),
+        line,
+        message,
+    };
+
+    let mut control = Opcodes::new();
+    let mut global = Opcodes::new();
+    let mut master = Opcodes::new();
+    let mut group = Opcodes::new();
+    // Opcodes of the region being parsed, and the line where it started
+    let mut region: Option<(Opcodes, usize)> = None;
+    let mut header = String::new();
+    let mut regions = vec![];
+    let mut samples = HashMap::new();
+
+    let mut finish_region =
+        |region: &mut Option<(Opcodes, usize)>, control: &Opcodes, inherited: Opcodes| {
+            if let Some((opcodes, line)) = region.take() {
+                let mut all = inherited;
+                all.extend(opcodes);
+                if let Some(region) = build_region(&all, control, dir, &mut samples)
+                    .map_err(|message| error(line, message))?
+                {
+                    regions.push(region);
+                }
+            }
+            Ok::<(), Error>(())
+        };
+
+    for (idx, line) in strip_comments(source).lines().enumerate() {
+        let line_number = idx + 1;
+        let mut rest = line.trim();
+        // Preprocessor directives like #include and #define aren't supported
+        if let Some(directive) = rest.strip_prefix('#') {
+            let name = directive.split_whitespace().next().unwrap_or_default();
+            return Err(error(line_number, format!("#{} is not supported", name)));
+        }
+
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix('<') {
+                let end = after
+                    .find('>')
+                    .ok_or_else(|| error(line_number, "Unclosed header".to_string()))?;
+                let inherited = [&global[..], &master[..], &group[..]].concat();
+                finish_region(&mut region, &control, inherited)?;
+
+                header = after[..end].to_string();
+                match header.as_str() {
+                    "global" => {
+                        global.clear();
+                        master.clear();
+                        group.clear();
+                    }
+                    "master" => {
+                        master.clear();
+                        group.clear();
+                    }
+                    "group" => group.clear(),
+                    "region" => region = Some((vec![], line_number)),
+                    _ => {}
+                }
+                rest = after[end + 1..].trim_start();
+                continue;
+            }
+
+            let equals = rest
+                .find('=')
+                .ok_or_else(|| error(line_number, format!("Expected an opcode, found {}", rest)))?;
+            let name = rest[..equals].trim().to_string();
+            let after = &rest[equals + 1..];
+            let end = value_end(after);
+            let value = after[..end].trim().to_string();
+            rest = after[end..].trim_start();
+
+            let opcodes = match header.as_str() {
+                "control" => &mut control,
+                "global" => &mut global,
+                "master" => &mut master,
+                "group" => &mut group,
+                "region" => &mut region.as_mut().unwrap().0,
+                // Opcodes of unsupported headers, like <curve> or <effect>
+                _ => continue,
+            };
+            opcodes.push((name, value));
+        }
+    }
+
+    let inherited = [&global[..], &master[..], &group[..]].concat();
+    finish_region(&mut region, &control, inherited)?;
+    Ok(regions)
+}
+
+/// Replaces the comments with spaces, keeping the lines where they were
+fn strip_comments(source: &str) -> String {
+    let mut result = String::with_capacity(source.len());
+    let mut chars = source.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('/', Some('/')) => {
+                while chars.peek().is_some_and(|&c| c != '\n') {
+                    chars.next();
+                }
+            }
+            ('/', Some('*')) => {
+                chars.next();
+                let mut previous = ' ';
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        result.push('\n');
+                    }
+                    if previous == '*' && c == '/' {
+                        break;
+                    }
+                    previous = c;
+                }
+                result.push(' ');
+            }
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+/// End of the value of an opcode, which can have spaces, so it goes until the next opcode or header
+fn value_end(text: &str) -> usize {
+    let bytes = text.as_bytes();
+    for (idx, &byte) in bytes.iter().enumerate() {
+        if byte == b'<' {
+            return idx;
+        }
+        if byte.is_ascii_whitespace() {
+            let next = &text[idx..].trim_start();
+            let name_len = next
+                .bytes()
+                .take_while(|b| b.is_ascii_alphanumeric() || *b == b'_')
+                .count();
+            if name_len > 0 && next.as_bytes().get(name_len) == Some(&b'=') {
+                return idx;
+            }
+        }
+    }
+    text.len()
+}
+
+/// Key from a number or a note name like "c4", where C4 is 60
+fn parse_key(value: &str) -> std::result::Result<u8, String> {
+    value
+        .parse::<u8>()
+        .ok()
+        .or_else(|| parse_note_name(value))
+        .filter(|key| *key < 128)
+        .ok_or_else(|| format!("{} is not a key", value))
+}
+
+fn parse_number<T: FromStr>(name: &str, value: &str) -> std::result::Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} is not a valid value for {}", value, name))
+}
+
+/// Builds a region from its opcodes and the ones it inherits, or `None` if it isn't played on note on
+fn build_region(
+    opcodes: &[(String, String)],
+    control: &[(String, String)],
+    dir: &Path,
+    samples: &mut HashMap<String, Sample>,
+) -> std::result::Result<Option<SfzRegion>, String> {
+    // Later opcodes override the earlier ones, which are inherited from the headers above
+    let get = |name: &str| {
+        opcodes
+            .iter()
+            .rev()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    };
+    if get("trigger").is_some_and(|trigger| trigger != "attack") {
+        return Ok(None);
+    }
+
+    let default_path = control
+        .iter()
+        .rev()
+        .find(|(n, _)| n == "default_path")
+        .map_or("", |(_, value)| value.as_str());
+    let sample = get("sample").ok_or_else(|| "Region without a sample".to_string())?;
+    let sample = dir
+        .join(format!("{}{}", default_path, sample).replace('\\', "/"))
+        .to_string_lossy()
+        .into_owned();
+
+    if !samples.contains_key(&sample) {
+        let metadata = read_sample(Path::new(&sample), Path::new(""))
+            .map_err(|err| err.to_string())?
+            .ok_or_else(|| format!("Unknown audio format in {}", sample))?;
+        samples.insert(sample.clone(), metadata);
+    }
+    let Sample {
+        sample_rate,
+        loop_points: file_loop,
+        root_key: file_root,
+        ..
+    } = samples[&sample];
+
+    let number = |name: &str, default: f64| {
+        get(name).map_or(Ok(default), |value| parse_number::<f64>(name, value))
+    };
+    let key = |name: &str, default: u8| get(name).map_or(Ok(default), parse_key);
+
+    let center_key = key("key", 60)?;
+    let pitch_keycenter = match get("pitch_keycenter") {
+        Some("sample") => f64::from(file_root.unwrap_or(60)),
+        Some(value) => f64::from(parse_key(value)?),
+        None => f64::from(center_key),
+    };
+
+    let file_loop_mode = if file_loop.is_some() {
+        LoopMode::Continuous
+    } else {
+        LoopMode::NoLoop
+    };
+    let loop_mode = match get("loop_mode").or_else(|| get("loopmode")) {
+        None => file_loop_mode,
+        Some("no_loop") => LoopMode::NoLoop,
+        Some("one_shot") => LoopMode::OneShot,
+        Some("loop_continuous") => LoopMode::Continuous,
+        Some("loop_sustain") => LoopMode::Sustain,
+        Some(other) => return Err(format!("{} is not a loop mode", other)),
+    };
+    let loop_start = get("loop_start").or_else(|| get("loopstart"));
+    let loop_end = get("loop_end").or_else(|| get("loopend"));
+    let loop_points = match (loop_start, loop_end, file_loop) {
+        (None, None, file_loop) => file_loop,
+        (start, end, file_loop) => {
+            let start = start.map_or_else(
+                || Ok(file_loop.map_or(0, |l| l.0)),
+                |value| parse_number::<usize>("loop_start", value),
+            )?;
+            // The end of the loop is inclusive in SFZ files
+            let end = match end {
+                Some(value) => parse_number::<usize>("loop_end", value)? + 1,
+                None => file_loop.map_or(usize::MAX, |l| l.1),
+            };
+            Some((start, end))
+        }
+    };
+
+    let has_key = get("key").is_some();
+    Ok(Some(SfzRegion {
+        sample,
+        sample_rate,
+        lokey: if has_key {
+            center_key
+        } else {
+            key("lokey", 0)?
+        },
+        hikey: if has_key {
+            center_key
+        } else {
+            key("hikey", 127)?
+        },
+        lovel: key("lovel", 1)?,
+        hivel: key("hivel", 127)?,
+        pitch_keycenter,
+        pitch_keytrack: number("pitch_keytrack", 100.)?,
+        transpose: number("transpose", 0.)?,
+        tune: number("tune", 0.)?,
+        volume: number("volume", 0.)?,
+        amp_veltrack: number("amp_veltrack", 100.)?,
+        offset: get("offset").map_or(Ok(0), |value| parse_number("offset", value))?,
+        loop_mode,
+        loop_points,
+        envelope: SfzEnvelope {
+            delay: number("ampeg_delay", 0.)?,
+            attack: number("ampeg_attack", 0.)?,
+            hold: number("ampeg_hold", 0.)?,
+            decay: number("ampeg_decay", 0.)?,
+            sustain: number("ampeg_sustain", 100.)? / 100.,
+            release: number("ampeg_release", 0.)?,
+        },
+        seq_length: get("seq_length")
+            .map_or(Ok(1), |value| parse_number("seq_length", value))?
+            .max(1),
+        seq_position: get("seq_position")
+            .map_or(Ok(1), |value| parse_number("seq_position", value))?
+            .max(1),
+        random: (number("lorand", 0.)?, number("hirand", 1.)?),
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::song::{test_song, SongConfig};
+    use crate::sound_files::io::save_file;
+    use crate::trigger::IntoTrigger;
+
+    /// Folder with an SFZ file and two samples of constant values
+    fn write_instrument(name: &str, sfz: &str) -> String {
+        let dir = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("samples")).unwrap();
+        for (file, value) in &[("one.wav", 0.25), ("two.wav", 0.5)] {
+            let path = dir.join("samples").join(file);
+            save_file(
+                &vec![Frame::mono(*value); 1_000],
+                path.to_str().unwrap(),
+                44_100,
+                24,
+            );
+        }
+        let path = dir.join("instrument.sfz");
+        fs::write(&path, sfz).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    /// Removes the folder made by `write_instrument`
+    fn remove_instrument(path: &str) {
+        fs::remove_dir_all(Path::new(path).parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn regions_inherit_opcodes_from_headers() {
+        let path = write_instrument(
+            "dawremi-sfz-parse",
+            "
+            // Comment with a <region> in it
+            <control> default_path=samples/
+            <global> volume=-6 /* block
+            comment */ ampeg_release=0.5
+            <group> lokey=c4 hikey=72 pitch_keycenter=60
+            <region> sample=one.wav lovel=1 hivel=64
+            <region> sample=two.wav lovel=65 loop_mode=loop_sustain loop_start=10 loop_end=99
+            <group> key=40
+            <region> sample=one.wav tune=-20 seq_length=2 seq_position=2
+            <region> sample=two.wav trigger=release
+            ",
+        );
+        let instrument = SfzInstrument::open(&path);
+
+        assert_eq!(3, instrument.regions.len());
+        let first = &instrument.regions[0];
+        assert!(first.sample.ends_with("samples/one.wav"));
+        assert_eq!(
+            (60, 72, 1, 64),
+            (first.lokey, first.hikey, first.lovel, first.hivel)
+        );
+        assert_eq!(-6., first.volume);
+        assert_eq!(0.5, first.envelope.release);
+        assert_eq!(LoopMode::NoLoop, first.loop_mode);
+
+        let second = &instrument.regions[1];
+        assert_eq!(LoopMode::Sustain, second.loop_mode);
+        assert_eq!(Some((10, 100)), second.loop_points);
+
+        let third = &instrument.regions[2];
+        assert_eq!((40, 40), (third.lokey, third.hikey));
+        assert_eq!(40., third.pitch_keycenter);
+        assert_eq!(-20., third.tune);
+        assert_eq!((2, 2), (third.seq_length, third.seq_position));
+        assert_eq!(22_050, instrument.release(44_100));
+
+        remove_instrument(&path);
+    }
+
+    #[test]
+    fn errors_say_the_line() {
+        let path = write_instrument(
+            "dawremi-sfz-errors",
+            "<region> sample=samples/one.wav\n<region> sample=samples/one.wav lokey=nope\n",
+        );
+        assert!(matches!(
+            SfzInstrument::try_open(&path),
+            Err(Error::Sfz { line: 2, .. })
+        ));
+        remove_instrument(&path);
+
+        let path = write_instrument(
+            "dawremi-sfz-directives",
+            "#define $KEY 60\n<region> sample=samples/one.wav key=$KEY\n",
+        );
+        assert!(matches!(
+            SfzInstrument::try_open(&path),
+            Err(Error::Sfz { line: 1, message, .. }) if message == "#define is not supported"
+        ));
+
+        remove_instrument(&path);
+    }
+
+    #[test]
+    fn notes_pick_regions_by_velocity_and_round_robin() {
+        let path = write_instrument(
+            "dawremi-sfz-notes",
+            "
+            <region> sample=samples/one.wav hikey=59 amp_veltrack=0
+            <region> sample=samples/one.wav lokey=60 hivel=64 amp_veltrack=0 seq_length=2 seq_position=1
+            <region> sample=samples/two.wav lokey=60 hivel=64 amp_veltrack=0 seq_length=2 seq_position=2
+            <region> sample=samples/two.wav lokey=60 lovel=65 amp_veltrack=0 volume=6.0205999
+            ",
+        );
+        let song = test_song(SongConfig::default());
+        let soft = SfzInstrument::open(&path).velocity(50);
+        let loud = SfzInstrument::open(&path).velocity(100);
+        let c4 = 261.625_565_300_6;
+
+        // Round robins alternate between the samples
+        assert!((soft.note(&song, c4, 10)[5].left - 0.25).abs() < 1e-4);
+        assert!((soft.note(&song, c4, 10)[5].left - 0.5).abs() < 1e-4);
+        assert!((soft.note(&song, c4, 10)[5].left - 0.25).abs() < 1e-4);
+
+        // Loud notes use the other layer, with the volume doubled
+        assert!((loud.note(&song, c4, 10)[5].left - 1.).abs() < 1e-4);
+        // Low notes use the first region
+        assert!((loud.note(&song, 110., 10)[5].left - 0.25).abs() < 1e-4);
+
+        // Samples without a loop stop at their end, even if pitched down
+        let note = loud.note(&song, 110., 3_000);
+        assert!(note[1_500].left.abs() > 0.1);
+        assert_eq!(Frame::default(), note[2_900]);
+
+        remove_instrument(&path);
+    }
+
+    #[test]
+    fn loops_and_envelopes_shape_the_notes() {
+        let path = write_instrument(
+            "dawremi-sfz-loops",
+            "
+            <region> sample=samples/one.wav amp_veltrack=0 loop_mode=loop_continuous
+            loop_start=100 loop_end=199 ampeg_attack=0.01 ampeg_release=0.01
+            ",
+        );
+        let song = test_song(SongConfig::default());
+        let instrument = SfzInstrument::open(&path);
+
+        let release = instrument.release(44_100);
+        assert_eq!(441, release);
+
+        // The loop keeps it playing after the end of the sample, with the attack at the start
+        let note = instrument.note(&song, 261.625_565_300_6, 5_000 + release);
+        assert_eq!(0., note[0].left);
+        assert!((note[220].left - 0.125).abs() < 0.01);
+        assert!((note[3_000].left - 0.25).abs() < 1e-4);
+        // And the release at the end
+        assert!(note[5_000 + release / 2].left < 0.2);
+        assert!(note[5_000 + release - 1].left.abs() < 0.01);
+
+        // Triggers overlap during the release
+        let triggers = vec![[261.625_565_300_6].samples(5_000)];
+        let audio = instrument.render(&song, &triggers);
+        assert_eq!(5_000 + release + 1, audio.len());
+        assert!((audio[3_000].left - 0.25).abs() < 1e-4);
+
+        remove_instrument(&path);
+    }
+
+    #[test]
+    fn one_shots_play_the_whole_sample() {
+        let path = write_instrument(
+            "dawremi-sfz-one-shot",
+            "<region> sample=samples/one.wav amp_veltrack=0 loop_mode=one_shot",
+        );
+        let song = test_song(SongConfig::default());
+        let instrument = SfzInstrument::open(&path);
+        let c4 = 261.625_565_300_6;
+
+        let note = instrument.note(&song, c4, 10);
+        assert_eq!(1_000, note.len());
+        assert!((note[900].left - 0.25).abs() < 1e-4);
+
+        // Rendered notes end with their trigger, so the following ones still start on time
+        let triggers = vec![[c4].samples(10), [c4].samples(10)];
+        assert_eq!(21, instrument.render(&song, &triggers).len());
+
+        remove_instrument(&path);
+    }
+}
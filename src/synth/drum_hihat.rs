@@ -27,6 +27,16 @@ impl Instrument for DrumHiHat {
     }
 
     fn generate(&self, length: usize, frequency: Frequency, sample_rate: u32) -> Vec<Frame> {
+        with_rng(|rng| self.generate_with_rng(length, frequency, sample_rate, rng))
+    }
+
+    fn generate_with_rng(
+        &self,
+        length: usize,
+        frequency: Frequency,
+        sample_rate: u32,
+        rng: &mut dyn RngCore,
+    ) -> Vec<Frame> {
         (0..length)
             .enumerate()
             .map(|(idx, sample)| {
@@ -45,7 +55,7 @@ impl Instrument for DrumHiHat {
                     -1.
                 };
 
-                let result = 0.1_f64.mul_add(square, 0.9 * rand::thread_rng().gen_range(-1., 1.));
+                let result = 0.1_f64.mul_add(square, 0.9 * rng.gen_range(-1., 1.));
 
                 Frame::mono(result)
             })
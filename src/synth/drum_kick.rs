@@ -27,6 +27,16 @@ impl Instrument for DrumKick {
     }
 
     fn generate(&self, length: usize, frequency: Frequency, sample_rate: u32) -> Vec<Frame> {
+        with_rng(|rng| self.generate_with_rng(length, frequency, sample_rate, rng))
+    }
+
+    fn generate_with_rng(
+        &self,
+        length: usize,
+        frequency: Frequency,
+        sample_rate: u32,
+        rng: &mut dyn RngCore,
+    ) -> Vec<Frame> {
         (0..length)
             .enumerate()
             .map(|(idx, sample)| {
@@ -39,7 +49,7 @@ impl Instrument for DrumKick {
                     frequency
                         .mul_add(time, a_lfo * frequency * (f_lfo * time).sin())
                         .sin(),
-                    0.01 * rand::thread_rng().gen_range(-1., 1.),
+                    0.01 * rng.gen_range(-1., 1.),
                 );
 
                 Frame::mono(result)
@@ -27,6 +27,16 @@ impl Instrument for Harmonica {
     }
 
     fn generate(&self, length: usize, frequency: Frequency, sample_rate: u32) -> Vec<Frame> {
+        with_rng(|rng| self.generate_with_rng(length, frequency, sample_rate, rng))
+    }
+
+    fn generate_with_rng(
+        &self,
+        length: usize,
+        frequency: Frequency,
+        sample_rate: u32,
+        rng: &mut dyn RngCore,
+    ) -> Vec<Frame> {
         (0..length)
             .enumerate()
             .map(|(idx, sample)| {
@@ -57,7 +67,7 @@ impl Instrument for Harmonica {
                 let result = 0.02_f64.mul_add(
                     square_1,
                     0.01_f64.mul_add(
-                        rand::thread_rng().gen_range(-1., 1.),
+                        rng.gen_range(-1., 1.),
                         0.5_f64.mul_add(square_2, 0.15 * square_3),
                     ),
                 );
@@ -1,15 +1,53 @@
 //! Contains some default instruments to generate interesting sounds
 
-use crate::{effects::*, frame::*, signals::adsr::*, trigger::*};
+use crate::{
+    effects::*,
+    frame::*,
+    random::{default_rng, track_rng},
+    signals::adsr::*,
+    song::Song,
+    trigger::*,
+};
 
 use core::f64::consts::TAU;
-use rand::Rng;
+use rand::{Rng, RngCore};
 
 pub trait Instrument {
     fn default_adsr(_sample_rate: u32) -> Adsr {
         Adsr::default()
     }
+    /// Generates a note
+    ///
+    /// Instruments that use random numbers take them from the track being generated, see `Song::rng`
     fn generate(&self, length: usize, frequency: Frequency, sample_rate: u32) -> Vec<Frame>;
+
+    /// Same as `generate`, but taking any random numbers from `rng`
+    ///
+    /// Instruments that use random numbers should implement it, and call it from `generate` with `with_rng`
+    fn generate_with_rng(
+        &self,
+        length: usize,
+        frequency: Frequency,
+        sample_rate: u32,
+        _rng: &mut dyn RngCore,
+    ) -> Vec<Frame> {
+        self.generate(length, frequency, sample_rate)
+    }
+
+    /// Generates a note with the random numbers of the current track, see `Song::rng`
+    fn generate_for(&self, song: &Song, length: usize, frequency: Frequency) -> Vec<Frame> {
+        self.generate_with_rng(length, frequency, song.sample_rate(), &mut song.rng())
+    }
+}
+
+/// Calls `f` with the random numbers of the track being generated, see `Song::rng`
+///
+/// Outside of a song, the numbers come from `default_rng`, so instruments sound the same every time
+pub fn with_rng<T>(f: impl FnOnce(&mut dyn RngCore) -> T) -> T {
+    match track_rng() {
+        Some(mut rng) => f(&mut rng),
+        None => f(&mut default_rng()),
+    }
 }
 
 mod harmonica;
@@ -22,8 +22,33 @@ impl Instrument for Plucked {
     }
 
     fn generate(&self, length: usize, frequency: Frequency, sample_rate: u32) -> Vec<Frame> {
+        track_rng().map_or_else(
+            || self.pluck(length, frequency, sample_rate, 3333),
+            |mut rng| self.generate_with_rng(length, frequency, sample_rate, &mut rng),
+        )
+    }
+
+    fn generate_with_rng(
+        &self,
+        length: usize,
+        frequency: Frequency,
+        sample_rate: u32,
+        rng: &mut dyn RngCore,
+    ) -> Vec<Frame> {
+        self.pluck(length, frequency, sample_rate, rng.gen())
+    }
+}
+impl Plucked {
+    /// Generates a note, with `seed` used for the noise of `InitialBurstType::Random`
+    fn pluck(
+        &self,
+        length: usize,
+        frequency: Frequency,
+        sample_rate: u32,
+        seed: u64,
+    ) -> Vec<Frame> {
         let noise_length = (sample_rate as f64 / frequency) as usize;
-        let mut noise = self.0.noise(noise_length);
+        let mut noise = self.0.noise(noise_length, seed);
 
         (0..length)
             .map(|sample| {
@@ -49,9 +74,9 @@ pub enum InitialBurstType {
     Hill,
 }
 impl InitialBurstType {
-    fn noise(&self, length: usize) -> Vec<f64> {
+    fn noise(&self, length: usize, seed: u64) -> Vec<f64> {
         match self {
-            InitialBurstType::Random => noise(length, 3333),
+            InitialBurstType::Random => noise(length, seed),
             InitialBurstType::DoubleTriangle => {
                 interpolate(vec![(length / 4, 1.), (length * 3 / 4, -1.), (length, 0.)])
             }
@@ -81,4 +106,11 @@ mod test {
         let vec = Plucked(InitialBurstType::DoubleTriangle).generate(1000, 100., sample_rate);
         assert_eq!(1000, vec.len());
     }
+
+    #[test]
+    fn random_burst_keeps_its_seed_outside_of_a_song() {
+        let plucked = Plucked(InitialBurstType::Random);
+        let vec = plucked.generate(1000, 100., 44_100);
+        assert_eq!(vec, plucked.pluck(1000, 100., 44_100, 3333));
+    }
 }
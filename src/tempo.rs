@@ -0,0 +1,216 @@
+//! Tempo changes during a song
+//!
+//! The tempo is described with breakpoints in beats. At each one the tempo can either jump
+//! to a new value, or ramp linearly from the previous breakpoint (for ritardandos and accelerandos)
+
+/// Change of tempo at a beat of the song
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TempoChange {
+    pub beat: f64,
+    pub bpm: f64,
+    /// If true, the tempo goes linearly from the previous breakpoint to `bpm`,
+    /// instead of jumping to it at `beat`
+    pub ramp: bool,
+}
+impl TempoChange {
+    /// Tempo becomes `bpm` at `beat`
+    pub const fn jump(beat: f64, bpm: f64) -> Self {
+        Self {
+            beat,
+            bpm,
+            ramp: false,
+        }
+    }
+
+    /// Tempo changes gradually from the previous breakpoint, and reaches `bpm` at `beat`
+    pub const fn ramp(beat: f64, bpm: f64) -> Self {
+        Self {
+            beat,
+            bpm,
+            ramp: true,
+        }
+    }
+}
+
+/// Part of the song where the tempo is either constant or changes linearly
+#[derive(Clone, Debug)]
+struct Segment {
+    beat: f64,
+    end_beat: f64,
+    /// Time in seconds at which the segment starts
+    seconds: f64,
+    bpm: f64,
+    end_bpm: f64,
+}
+impl Segment {
+    fn is_constant(&self) -> bool {
+        self.bpm == self.end_bpm
+    }
+
+    /// Seconds from the start of the segment to `beat`
+    fn seconds_to(&self, beat: f64) -> f64 {
+        let beats = beat - self.beat;
+        if self.is_constant() {
+            beats * 60. / self.bpm
+        } else {
+            // Integrate 60 / bpm over the beats, with bpm changing linearly
+            let slope = (self.end_beat - self.beat) / (self.end_bpm - self.bpm);
+            60. * slope * (self.bpm_at(beat) / self.bpm).ln()
+        }
+    }
+
+    /// Inverse of `seconds_to`
+    fn beats_in(&self, seconds: f64) -> f64 {
+        if self.is_constant() {
+            self.beat + seconds * self.bpm / 60.
+        } else {
+            let slope = (self.end_beat - self.beat) / (self.end_bpm - self.bpm);
+            let bpm = self.bpm * (seconds / (60. * slope)).exp();
+            slope.mul_add(bpm - self.bpm, self.beat)
+        }
+    }
+
+    fn bpm_at(&self, beat: f64) -> f64 {
+        if self.is_constant() {
+            self.bpm
+        } else {
+            let progress = (beat - self.beat) / (self.end_beat - self.beat);
+            (self.end_bpm - self.bpm).mul_add(progress, self.bpm)
+        }
+    }
+}
+
+/// Converts between beats and seconds, taking tempo changes into account
+#[derive(Clone, Debug)]
+pub struct TempoMap {
+    segments: Vec<Segment>,
+}
+
+impl TempoMap {
+    /// Makes a tempo map that starts at `bpm` and follows `changes`
+    ///
+    /// Changes can be in any order
+    pub fn new(bpm: f64, changes: &[TempoChange]) -> Self {
+        let mut changes = changes.to_vec();
+        changes.sort_by(|a, b| a.beat.partial_cmp(&b.beat).unwrap());
+
+        let mut segments = vec![];
+        let mut beat = 0.;
+        let mut seconds = 0.;
+        let mut current_bpm = bpm;
+
+        for change in changes {
+            let segment = Segment {
+                beat,
+                end_beat: change.beat,
+                seconds,
+                bpm: current_bpm,
+                end_bpm: if change.ramp { change.bpm } else { current_bpm },
+            };
+
+            if change.beat > beat {
+                seconds += segment.seconds_to(change.beat);
+                segments.push(segment);
+            }
+            beat = change.beat.max(beat);
+            current_bpm = change.bpm;
+        }
+
+        segments.push(Segment {
+            beat,
+            end_beat: f64::INFINITY,
+            seconds,
+            bpm: current_bpm,
+            end_bpm: current_bpm,
+        });
+
+        Self { segments }
+    }
+
+    /// Tempo map with no changes
+    pub fn constant(bpm: f64) -> Self {
+        Self::new(bpm, &[])
+    }
+
+    /// Returns the time in seconds at which `beat` happens
+    pub fn seconds_at(&self, beat: f64) -> f64 {
+        let segment = self
+            .segments
+            .iter()
+            .rev()
+            .find(|segment| segment.beat <= beat)
+            .unwrap_or(&self.segments[0]);
+        segment.seconds + segment.seconds_to(beat)
+    }
+
+    /// Returns the beat that happens at `seconds`
+    pub fn beat_at(&self, seconds: f64) -> f64 {
+        let segment = self
+            .segments
+            .iter()
+            .rev()
+            .find(|segment| segment.seconds <= seconds)
+            .unwrap_or(&self.segments[0]);
+        segment.beats_in(seconds - segment.seconds)
+    }
+
+    /// Returns the tempo at `beat`
+    pub fn bpm_at(&self, beat: f64) -> f64 {
+        self.segments
+            .iter()
+            .rev()
+            .find(|segment| segment.beat <= beat)
+            .unwrap_or(&self.segments[0])
+            .bpm_at(beat)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} is not {}", a, b);
+    }
+
+    #[test]
+    fn constant_tempo() {
+        let map = TempoMap::constant(120.);
+
+        assert_close(0., map.seconds_at(0.));
+        assert_close(2., map.seconds_at(4.));
+        assert_close(4., map.beat_at(2.));
+        assert_close(120., map.bpm_at(100.));
+    }
+
+    #[test]
+    fn tempo_jumps() {
+        let map = TempoMap::new(120., &[TempoChange::jump(4., 60.)]);
+
+        // 4 beats at 120, then 2 beats at 60
+        assert_close(4., map.seconds_at(6.));
+        assert_close(6., map.beat_at(4.));
+        assert_close(120., map.bpm_at(3.9));
+        assert_close(60., map.bpm_at(4.));
+    }
+
+    #[test]
+    fn tempo_ramps() {
+        let map = TempoMap::new(
+            120.,
+            &[TempoChange::ramp(4., 60.), TempoChange::jump(8., 90.)],
+        );
+
+        assert_close(90., map.bpm_at(2.));
+        assert_close(90., map.bpm_at(8.));
+
+        // Integral of 60 / (120 - 15 * beat) from 0 to 4
+        let ramp = 4. * 2_f64.ln();
+        assert_close(ramp, map.seconds_at(4.));
+        assert_close(ramp + 4., map.seconds_at(8.));
+
+        for &beat in &[0.5, 3., 4., 7.5, 12.] {
+            assert_close(beat, map.beat_at(map.seconds_at(beat)));
+        }
+    }
+}
@@ -0,0 +1,257 @@
+//! Time signatures, bars and musical positions
+//!
+//! Beats of the song are quarter notes, so a bar of 6/8 lasts 3 beats.
+//! Time signatures can change at the start of any bar
+
+/// Number of ticks in one beat of a time signature
+pub const TICKS_PER_BEAT: usize = 960;
+
+/// Number of beats in a bar, and the note value of each beat
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeSignature {
+    pub numerator: usize,
+    pub denominator: usize,
+}
+impl TimeSignature {
+    pub const fn new(numerator: usize, denominator: usize) -> Self {
+        Self {
+            numerator,
+            denominator,
+        }
+    }
+
+    /// Length of one beat of the time signature, in beats of the song
+    pub fn beat_length(&self) -> f64 {
+        4. / self.denominator as f64
+    }
+
+    /// Length of one bar, in beats of the song
+    pub fn bar_length(&self) -> f64 {
+        self.numerator as f64 * self.beat_length()
+    }
+}
+impl Default for TimeSignature {
+    fn default() -> Self {
+        Self::new(4, 4)
+    }
+}
+
+/// Change of time signature at the start of a bar
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeSignatureChange {
+    /// Bar where the new time signature starts, counting from 1
+    pub bar: usize,
+    pub time_signature: TimeSignature,
+}
+impl TimeSignatureChange {
+    pub const fn new(bar: usize, time_signature: TimeSignature) -> Self {
+        Self {
+            bar,
+            time_signature,
+        }
+    }
+}
+
+/// Position in the song, like the ones shown by a DAW
+///
+/// Bars and beats are counted from 1, and beats use the note value of the time signature
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Position {
+    pub bar: usize,
+    pub beat: usize,
+    /// Subdivision of the beat, from 0 to `TICKS_PER_BEAT`
+    pub tick: usize,
+}
+impl Position {
+    pub const fn new(bar: usize, beat: usize, tick: usize) -> Self {
+        Self { bar, beat, tick }
+    }
+
+    /// Start of the bar
+    pub const fn bar(bar: usize) -> Self {
+        Self::new(bar, 1, 0)
+    }
+
+    pub const fn is_downbeat(&self) -> bool {
+        self.beat == 1 && self.tick == 0
+    }
+}
+impl Default for Position {
+    fn default() -> Self {
+        Self::bar(1)
+    }
+}
+
+/// Group of bars that share a time signature
+#[derive(Clone, Debug)]
+struct Segment {
+    /// First bar of the segment, counting from 1
+    bar: usize,
+    /// Beat of the song at which the segment starts
+    beat: f64,
+    time_signature: TimeSignature,
+}
+
+/// Converts between bars and beats, taking time signature changes into account
+#[derive(Clone, Debug)]
+pub struct TimeSignatureMap {
+    segments: Vec<Segment>,
+}
+
+impl TimeSignatureMap {
+    /// Makes a map that starts with `time_signature` and follows `changes`
+    ///
+    /// Changes can be in any order
+    pub fn new(time_signature: TimeSignature, changes: &[TimeSignatureChange]) -> Self {
+        let mut changes = changes.to_vec();
+        changes.sort_by_key(|change| change.bar);
+
+        let mut segments = vec![Segment {
+            bar: 1,
+            beat: 0.,
+            time_signature,
+        }];
+
+        for change in changes {
+            let last = segments.last_mut().unwrap();
+            let bar = change.bar.max(1);
+            if bar == last.bar {
+                last.time_signature = change.time_signature;
+            } else {
+                let beat =
+                    ((bar - last.bar) as f64).mul_add(last.time_signature.bar_length(), last.beat);
+                segments.push(Segment {
+                    bar,
+                    beat,
+                    time_signature: change.time_signature,
+                });
+            }
+        }
+
+        Self { segments }
+    }
+
+    /// Map with the same time signature for the whole song
+    pub fn constant(time_signature: TimeSignature) -> Self {
+        Self::new(time_signature, &[])
+    }
+
+    fn segment_for_bar(&self, bar: usize) -> &Segment {
+        self.segments
+            .iter()
+            .rev()
+            .find(|segment| segment.bar <= bar)
+            .unwrap_or(&self.segments[0])
+    }
+
+    fn segment_for_beat(&self, beat: f64) -> &Segment {
+        self.segments
+            .iter()
+            .rev()
+            .find(|segment| segment.beat <= beat)
+            .unwrap_or(&self.segments[0])
+    }
+
+    /// Returns the time signature of `bar`
+    pub fn time_signature_at(&self, bar: usize) -> TimeSignature {
+        self.segment_for_bar(bar).time_signature
+    }
+
+    /// Returns the beat of the song at which `position` happens
+    pub fn beat_of(&self, position: Position) -> f64 {
+        let segment = self.segment_for_bar(position.bar);
+        let signature = segment.time_signature;
+        let bars = position.bar.saturating_sub(segment.bar) as f64;
+        let beats =
+            position.beat.saturating_sub(1) as f64 + position.tick as f64 / TICKS_PER_BEAT as f64;
+
+        bars.mul_add(
+            signature.bar_length(),
+            beats.mul_add(signature.beat_length(), segment.beat),
+        )
+    }
+
+    /// Returns the beat of the song after `bars` bars, counting from the start
+    pub fn bars_to_beats(&self, bars: f64) -> f64 {
+        let bar = bars.floor().max(0.) as usize + 1;
+        let start = self.beat_of(Position::bar(bar));
+        (bars - bars.floor()).mul_add(self.time_signature_at(bar).bar_length(), start)
+    }
+
+    /// Returns the number of bars from the start of the song to `beat`
+    pub fn beats_to_bars(&self, beat: f64) -> f64 {
+        let segment = self.segment_for_beat(beat);
+        let bars = (beat - segment.beat) / segment.time_signature.bar_length();
+        (segment.bar - 1) as f64 + bars
+    }
+
+    /// Returns the position of `beat`, rounding down to the closest tick
+    pub fn position_at(&self, beat: f64) -> Position {
+        let segment = self.segment_for_beat(beat);
+        let signature = segment.time_signature;
+
+        // Count in ticks, to avoid rounding errors at the boundaries of beats and bars
+        let beats = (beat - segment.beat).max(0.) / signature.beat_length();
+        let ticks = beats.mul_add(TICKS_PER_BEAT as f64, 1e-6).floor() as usize;
+        let ticks_per_bar = signature.numerator * TICKS_PER_BEAT;
+
+        Position {
+            bar: segment.bar + ticks / ticks_per_bar,
+            beat: ticks % ticks_per_bar / TICKS_PER_BEAT + 1,
+            tick: ticks % TICKS_PER_BEAT,
+        }
+    }
+
+    /// Returns the position of the beat that comes after `position`
+    pub fn next_beat(&self, position: Position) -> Position {
+        if position.beat >= self.time_signature_at(position.bar).numerator {
+            Position::bar(position.bar + 1)
+        } else {
+            Position::new(position.bar, position.beat + 1, 0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} is not {}", a, b);
+    }
+
+    #[test]
+    fn bars_in_four_four() {
+        let map = TimeSignatureMap::constant(TimeSignature::default());
+
+        assert_close(28., map.beat_of(Position::bar(8)));
+        assert_close(29.5, map.beat_of(Position::new(8, 2, 480)));
+        assert_close(28., map.bars_to_beats(7.));
+        assert_close(7.5, map.beats_to_bars(30.));
+        assert_eq!(Position::new(8, 2, 480), map.position_at(29.5));
+    }
+
+    #[test]
+    fn time_signature_changes() {
+        let map = TimeSignatureMap::new(
+            TimeSignature::default(),
+            &[
+                TimeSignatureChange::new(5, TimeSignature::new(3, 4)),
+                TimeSignatureChange::new(3, TimeSignature::new(6, 8)),
+            ],
+        );
+
+        // 2 bars of 4/4, 2 bars of 6/8 and then 3/4
+        assert_eq!(TimeSignature::new(6, 8), map.time_signature_at(4));
+        assert_close(8., map.beat_of(Position::bar(3)));
+        assert_close(14., map.beat_of(Position::bar(5)));
+        assert_close(9., map.beat_of(Position::new(3, 3, 0)));
+        assert_close(17., map.bars_to_beats(5.));
+        assert_close(3.5, map.beats_to_bars(12.5));
+
+        assert_eq!(Position::new(4, 6, 0), map.position_at(13.5));
+        assert_eq!(Position::new(5, 1, 0), map.position_at(14.));
+        assert_eq!(Position::bar(5), map.next_beat(Position::new(4, 6, 0)));
+        assert_eq!(Position::new(5, 2, 0), map.next_beat(Position::bar(5)));
+    }
+}
@@ -0,0 +1,350 @@
+//! Timelines, for arranging clips of audio in a track
+//!
+//! Each clip is placed at a position of the song, and can be trimmed and faded.
+//! Clips that overlap get added together
+
+use crate::effects::Automation;
+use crate::error::Result;
+use crate::frame::Frame;
+use crate::signals::adsr::Adsr;
+use crate::song::{Song, TrackGenerator};
+use crate::sound_files::pattern::add_vecs_starting_from;
+use crate::sound_files::Sound;
+use crate::time_signature::Position;
+use crate::trigger::{generate_triggers, Frequency, Length, Trigger};
+
+/// Where a clip starts
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClipStart {
+    Samples(usize),
+    Seconds(f64),
+    Beats(f64),
+    Position(Position),
+}
+impl ClipStart {
+    fn sample(&self, song: &Song) -> usize {
+        match *self {
+            Self::Samples(samples) => samples,
+            Self::Seconds(seconds) => song.seconds(seconds),
+            Self::Beats(beats) => song.beats(beats),
+            Self::Position(position) => song.position(position),
+        }
+    }
+}
+impl From<Position> for ClipStart {
+    fn from(position: Position) -> Self {
+        Self::Position(position)
+    }
+}
+
+type Instrument = dyn FnMut(Frequency, usize) -> Vec<Frame> + Send;
+
+/// Audio that a clip plays
+pub enum ClipSource {
+    Audio(Vec<Frame>),
+    Sound(Sound),
+    /// Triggers played with an instrument, following the tempo from where the clip starts
+    Triggers {
+        triggers: Vec<Trigger>,
+        instrument: Box<Instrument>,
+        adsr: Adsr,
+    },
+    Song(Box<Song>),
+}
+impl ClipSource {
+    pub fn triggers(
+        triggers: Vec<Trigger>,
+        instrument: impl FnMut(Frequency, usize) -> Vec<Frame> + Send + 'static,
+        adsr: Adsr,
+    ) -> Self {
+        Self::Triggers {
+            triggers,
+            instrument: Box::new(instrument),
+            adsr,
+        }
+    }
+
+    fn generate(&mut self, song: &Song, start: f64) -> Result<Vec<Frame>> {
+        match self {
+            Self::Audio(audio) => Ok(audio.clone()),
+            Self::Sound(sound) => song.try_sound(sound.clone()),
+            Self::Triggers {
+                triggers,
+                instrument,
+                adsr,
+            } => Ok(generate_triggers(
+                triggers,
+                song,
+                start,
+                instrument.as_mut(),
+                *adsr,
+            )),
+            Self::Song(other) => {
+                other.try_generate(song.sample_rate())?;
+                Ok(other.generated().unwrap().to_vec())
+            }
+        }
+    }
+}
+impl From<Vec<Frame>> for ClipSource {
+    fn from(audio: Vec<Frame>) -> Self {
+        Self::Audio(audio)
+    }
+}
+impl From<Sound> for ClipSource {
+    fn from(sound: Sound) -> Self {
+        Self::Sound(sound)
+    }
+}
+impl From<Song> for ClipSource {
+    fn from(song: Song) -> Self {
+        Self::Song(Box::new(song))
+    }
+}
+
+/// Audio placed at a position of a timeline
+pub struct Clip {
+    source: ClipSource,
+    start: ClipStart,
+    /// Part of the source that gets skipped
+    offset: Length,
+    /// None plays the source until its end
+    length: Option<Length>,
+    fade_in: Length,
+    fade_out: Length,
+    gain: Automation<f64>,
+}
+
+impl Clip {
+    pub fn new(source: impl Into<ClipSource>, start: impl Into<ClipStart>) -> Self {
+        Self {
+            source: source.into(),
+            start: start.into(),
+            offset: Length::Samples(0),
+            length: None,
+            fade_in: Length::Samples(0),
+            fade_out: Length::Samples(0),
+            gain: Automation::Const(1.),
+        }
+    }
+
+    /// Skips the start of the source
+    pub const fn offset(mut self, offset: Length) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Cuts the clip after `length`
+    pub const fn length(mut self, length: Length) -> Self {
+        self.length = Some(length);
+        self
+    }
+
+    pub const fn fade_in(mut self, fade_in: Length) -> Self {
+        self.fade_in = fade_in;
+        self
+    }
+
+    pub const fn fade_out(mut self, fade_out: Length) -> Self {
+        self.fade_out = fade_out;
+        self
+    }
+
+    /// Multiplier applied to the clip, with the index counting from the start of the clip
+    pub fn gain(mut self, gain: Automation<f64>) -> Self {
+        self.gain = gain;
+        self
+    }
+
+    /// Generates the clip, returning the sample where it starts and its audio
+    fn generate(&mut self, song: &Song) -> Result<(usize, Vec<Frame>)> {
+        let start = self.start.sample(song);
+        let beat = match self.start {
+            ClipStart::Beats(beat) => beat,
+            _ => song.beat_at(start),
+        };
+
+        let audio = self.source.generate(song, beat)?;
+        let offset = self.offset.get(song, beat).min(audio.len());
+        let length = self
+            .length
+            .map_or(audio.len(), |length| length.get(song, beat))
+            .min(audio.len() - offset);
+        let fade_in = self.fade_in.get(song, beat).min(length);
+        let fade_out = self.fade_out.get(song, beat).min(length);
+
+        let audio = audio[offset..offset + length]
+            .iter()
+            .enumerate()
+            .map(|(idx, &frame)| {
+                let mut mult = self.gain.value(idx);
+                if idx < fade_in {
+                    mult *= idx as f64 / fade_in as f64;
+                }
+                if length - idx <= fade_out {
+                    mult *= (length - idx - 1) as f64 / fade_out as f64;
+                }
+                frame * mult
+            })
+            .collect();
+
+        Ok((start, audio))
+    }
+}
+
+/// Track made of clips placed at positions of the song
+///
+/// Can be converted into a `TrackGenerator`
+#[derive(Default)]
+pub struct Timeline {
+    clips: Vec<Clip>,
+}
+
+impl Timeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a clip to the timeline
+    pub fn clip(mut self, clip: Clip) -> Self {
+        self.clips.push(clip);
+        self
+    }
+
+    /// Adds a clip to the timeline
+    pub fn add(&mut self, clip: Clip) {
+        self.clips.push(clip);
+    }
+
+    /// Renders all of the clips
+    ///
+    /// # Panics
+    ///
+    /// Panics if a clip can't be generated. See `try_generate` for a version that returns an error
+    pub fn generate(&mut self, song: &Song) -> Vec<Frame> {
+        self.try_generate(song)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Renders all of the clips, returning an error if a sound or nested song fails
+    pub fn try_generate(&mut self, song: &Song) -> Result<Vec<Frame>> {
+        let mut out = vec![];
+        for clip in &mut self.clips {
+            let (start, audio) = clip.generate(song)?;
+            out = add_vecs_starting_from(out, start, &audio, 1.);
+        }
+        Ok(out)
+    }
+}
+
+impl From<Timeline> for TrackGenerator {
+    fn from(mut timeline: Timeline) -> Self {
+        Self::fallible(move |song| timeline.try_generate(song))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::effects::EffectBundle;
+    use crate::song::SongConfig;
+    use crate::track::Track;
+    use crate::trigger::IntoTrigger;
+
+    fn song() -> Song {
+        let config = SongConfig {
+            bpm: 120.,
+            ..Default::default()
+        };
+        let mut song = Song::new(vec![Track::new(|_song: &Song| vec![])], config);
+        song.generate(100);
+        song
+    }
+
+    #[test]
+    fn clips_are_placed_at_their_positions() {
+        let song = song();
+        let audio = Timeline::new()
+            .clip(Clip::new(vec![Frame::mono(1.); 10], ClipStart::Samples(5)))
+            .clip(Clip::new(vec![Frame::mono(0.5); 10], ClipStart::Beats(0.2)))
+            .clip(Clip::new(
+                vec![Frame::mono(0.25); 10],
+                Position::new(1, 2, 0),
+            ))
+            .generate(&song);
+
+        // One beat at 120 bpm is 50 samples
+        assert_eq!(60, audio.len());
+        assert_eq!(Frame::default(), audio[4]);
+        assert_eq!(Frame::mono(1.), audio[5]);
+        assert_eq!(Frame::mono(1.5), audio[10]);
+        assert_eq!(Frame::mono(0.5), audio[15]);
+        assert_eq!(Frame::mono(0.25), audio[50]);
+    }
+
+    #[test]
+    fn clips_can_be_trimmed_and_faded() {
+        let song = song();
+        let source: Vec<Frame> = (0..20).map(|i| Frame::mono(i as f64)).collect();
+        let audio = Timeline::new()
+            .clip(
+                Clip::new(source, ClipStart::Samples(0))
+                    .offset(Length::Samples(5))
+                    .length(Length::Samples(10))
+                    .fade_in(Length::Samples(2))
+                    .fade_out(Length::Samples(2)),
+            )
+            .generate(&song);
+
+        let expected: Vec<Frame> = [0., 3., 7., 8., 9., 10., 11., 12., 6.5, 0.]
+            .iter()
+            .map(|&value| Frame::mono(value))
+            .collect();
+        assert_eq!(expected, audio);
+    }
+
+    #[test]
+    fn triggers_and_songs_can_be_clips() {
+        let song = song();
+        let nested = Song::new(
+            vec![Track::new(|song: &Song| {
+                vec![Frame::mono(1.); song.beats(1.)]
+            })],
+            SongConfig {
+                master: EffectBundle(vec![]),
+                ..Default::default()
+            },
+        );
+        let audio = Timeline::new()
+            .clip(Clip::new(nested, ClipStart::Beats(1.)))
+            .clip(Clip::new(
+                ClipSource::triggers(
+                    vec![440_f64.beats(1.)],
+                    |_freq, length| vec![Frame::mono(0.5); length],
+                    Adsr::default(),
+                ),
+                ClipStart::Beats(0.),
+            ))
+            .generate(&song);
+
+        // The nested song is at 100 bpm, so its beat lasts 60 samples
+        assert_eq!(110, audio.len());
+        assert_eq!(Frame::mono(0.5), audio[10]);
+        assert_eq!(Frame::mono(1.), audio[60]);
+        assert_eq!(Frame::mono(1.), audio[109]);
+    }
+
+    #[test]
+    fn timelines_are_tracks() {
+        let mut song = Song::new(
+            vec![Track::new(Timeline::new().clip(Clip::new(
+                vec![Frame::mono(1.); 10],
+                ClipStart::Samples(5),
+            )))],
+            SongConfig::default(),
+        );
+        song.generate(100);
+
+        assert_eq!(15, song.generated().unwrap().len());
+    }
+}
@@ -0,0 +1,324 @@
+//! Tracks are the channels of the mixer
+//!
+//! Each one wraps a `TrackGenerator`, and has its own gain, pan and insert effects
+
+use crate::effects::{Automation, EffectBundle};
+use crate::frame::Frame;
+use crate::helpers::hashing::stable_hash;
+use crate::song::TrackGenerator;
+use std::hash::Hash;
+
+pub struct Track {
+    pub(crate) name: String,
+    pub(crate) generator: TrackGenerator,
+
+    /// Multiplier applied to the track
+    pub(crate) gain: Automation<f64>,
+    /// Value between -1 and 1, same as in `Balance`
+    pub(crate) pan: Automation<f64>,
+    pub(crate) mute: bool,
+    pub(crate) solo: bool,
+
+    /// Effects applied to the track before gain and pan
+    pub(crate) effects: Option<EffectBundle>,
+    /// Sends to aux buses
+    pub(crate) sends: Vec<AuxSend>,
+
+    /// Hash of the version given to `cache`
+    pub(crate) cache: Option<String>,
+}
+
+impl Track {
+    pub fn new(generator: impl Into<TrackGenerator>) -> Self {
+        Self {
+            name: String::new(),
+            generator: generator.into(),
+
+            gain: Automation::Const(1.),
+            pan: Automation::Const(0.),
+            mute: false,
+            solo: false,
+
+            effects: None,
+            sends: vec![],
+
+            cache: None,
+        }
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    pub fn gain(mut self, gain: Automation<f64>) -> Self {
+        self.gain = gain;
+        self
+    }
+
+    pub fn pan(mut self, pan: Automation<f64>) -> Self {
+        self.pan = pan;
+        self
+    }
+
+    /// Muted tracks don't get generated
+    pub const fn mute(mut self, mute: bool) -> Self {
+        self.mute = mute;
+        self
+    }
+
+    /// If any track is soloed, only soloed tracks will be heard
+    pub const fn solo(mut self, solo: bool) -> Self {
+        self.solo = solo;
+        self
+    }
+
+    /// Sets the insert effects of the track
+    pub fn effects(mut self, effects: EffectBundle) -> Self {
+        self.effects = Some(effects);
+        self
+    }
+
+    /// Sends the track to the bus with the given name, after gain and pan are applied
+    pub fn send(mut self, bus: impl Into<String>, amount: Automation<f64>) -> Self {
+        self.sends.push(AuxSend {
+            bus: bus.into(),
+            amount,
+            pre_fader: false,
+        });
+        self
+    }
+
+    /// Sends the track to the bus with the given name, before gain and pan are applied
+    pub fn pre_fader_send(mut self, bus: impl Into<String>, amount: Automation<f64>) -> Self {
+        self.sends.push(AuxSend {
+            bus: bus.into(),
+            amount,
+            pre_fader: true,
+        });
+        self
+    }
+
+    /// Keeps the generated audio of the track, in memory and on disk, and reuses it
+    /// as long as `version`, the sample rate, the tempo, the time signature and the seed
+    /// of the song don't change
+    ///
+    /// The version can be anything that can be hashed, like a number or a hash of the
+    /// generator's inputs. It should change every time the generator does.
+    /// The track is found in the cache by its name, so it needs one
+    pub fn cache(mut self, version: impl Hash) -> Self {
+        self.cache = Some(stable_hash(&version));
+        self
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Applies gain and pan to the frame at `idx`
+    pub(crate) fn fader(&self, idx: usize, frame: Frame) -> Frame {
+        (frame * self.gain.value(idx)).balance(self.pan.value(idx))
+    }
+
+    /// Returns true if the track should be heard, given whether any track in the song is soloed
+    pub(crate) const fn is_audible(&self, any_solo: bool) -> bool {
+        !self.mute && (self.solo || !any_solo)
+    }
+}
+
+/// Part of a track that gets sent to an aux bus
+pub struct AuxSend {
+    pub(crate) bus: String,
+    pub(crate) amount: Automation<f64>,
+    /// Pre-fader sends are taken before the gain and pan of the track
+    pub(crate) pre_fader: bool,
+}
+impl AuxSend {
+    /// Returns what gets sent at `idx`, given the frame before and after the fader
+    pub(crate) fn value(&self, idx: usize, pre_fader: Frame, post_fader: Frame) -> Frame {
+        let frame = if self.pre_fader {
+            pre_fader
+        } else {
+            post_fader
+        };
+        frame * self.amount.value(idx)
+    }
+}
+
+impl<T: Into<TrackGenerator>> From<T> for Track {
+    fn from(generator: T) -> Self {
+        Self::new(generator)
+    }
+}
+
+/// Generator of a hundredth of a second at 0.5, for tests
+#[cfg(test)]
+pub(crate) fn constant(song: &crate::song::Song) -> Vec<Frame> {
+    vec![Frame::mono(0.5); song.seconds(0.01)]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::effects::*;
+    use crate::error::Error;
+    use crate::frame::Frame;
+    use crate::song::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn gain_and_pan_are_applied() {
+        let mut song = Song::new(
+            vec![Track::new(constant)
+                .gain(Automation::Const(0.5))
+                .pan(Automation::Const(1.))],
+            SongConfig::default(),
+        );
+        song.generate(44_100);
+
+        assert_eq!(
+            Frame {
+                left: 0.,
+                right: 0.25
+            },
+            song.generated().unwrap()[0]
+        );
+    }
+
+    #[test]
+    fn muted_tracks_are_not_heard() {
+        let mut song = Song::new(
+            vec![Track::new(constant), Track::new(constant).mute(true)],
+            SongConfig::default(),
+        );
+        song.generate(44_100);
+
+        assert_eq!(Frame::mono(0.5), song.generated().unwrap()[0]);
+    }
+
+    #[test]
+    fn only_soloed_tracks_are_heard() {
+        let mut song = Song::new(
+            vec![
+                Track::new(constant).solo(true),
+                Track::new(constant),
+                Track::new(constant).solo(true).gain(Automation::Const(0.2)),
+            ],
+            SongConfig::default(),
+        );
+        song.generate(44_100);
+
+        assert_eq!(Frame::mono(0.6), song.generated().unwrap()[0]);
+    }
+
+    #[test]
+    fn insert_effects_are_applied_before_gain() {
+        let effects = EffectBundle(vec![Box::new(Volume {
+            mult: Automation::Const(0.5),
+        })]);
+        let mut song = Song::new(
+            vec![Track::new(constant)
+                .effects(effects)
+                .gain(Automation::Const(0.5))],
+            SongConfig::default(),
+        );
+        song.generate(44_100);
+
+        assert_eq!(Frame::mono(0.125), song.generated().unwrap()[0]);
+    }
+
+    fn counted_track(calls: &Arc<AtomicUsize>, version: usize) -> Track {
+        let calls = Arc::clone(calls);
+        Track::new(move |song: &Song| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            constant(song)
+        })
+        .name("counted")
+        .cache(version)
+    }
+
+    #[test]
+    fn cached_tracks_are_kept_in_memory() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let config = SongConfig {
+            track_cache_dir: None,
+            ..Default::default()
+        };
+        let mut song = Song::new(vec![counted_track(&calls, 1)], config);
+
+        song.generate(44_100);
+        song.generate(48_000);
+        song.generate(44_100);
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+        assert_eq!(constant(&song), song.generated().unwrap());
+
+        song.clear_track_cache();
+        song.generate(48_000);
+        assert_eq!(3, calls.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn cached_tracks_are_saved_to_disk() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let folder = std::env::temp_dir().join(format!("dawremi-cache-{}", std::process::id()));
+        let make_song = |version| {
+            let config = SongConfig {
+                name: "cached".to_string(),
+                track_cache_dir: Some(folder.to_string_lossy().into_owned()),
+                ..Default::default()
+            };
+            Song::new(vec![counted_track(&calls, version)], config)
+        };
+
+        make_song(1).generate(44_100);
+        let mut song = make_song(1);
+        song.generate(44_100);
+        assert_eq!(1, calls.load(Ordering::SeqCst));
+        assert_eq!(constant(&song), song.generated().unwrap());
+
+        // Changing the version makes it generate again
+        make_song(2).generate(44_100);
+        assert_eq!(2, calls.load(Ordering::SeqCst));
+
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn cached_tracks_follow_the_song() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let folder =
+            std::env::temp_dir().join(format!("dawremi-song-cache-{}", std::process::id()));
+        let generate = |bpm, seed| {
+            let config = SongConfig {
+                name: "cached".to_string(),
+                track_cache_dir: Some(folder.to_string_lossy().into_owned()),
+                bpm,
+                seed,
+                ..Default::default()
+            };
+            Song::new(vec![counted_track(&calls, 1)], config).generate(44_100);
+        };
+
+        generate(120., 0);
+        generate(120., 0);
+        assert_eq!(1, calls.load(Ordering::SeqCst));
+        generate(90., 0);
+        generate(90., 1);
+        assert_eq!(3, calls.load(Ordering::SeqCst));
+
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+
+    #[test]
+    fn cached_tracks_need_a_name() {
+        let mut song = Song::new(vec![Track::new(constant).cache(1)], SongConfig::default());
+        match song.try_generate(44_100) {
+            Err(Error::Track { name, source }) => {
+                assert_eq!("Track 1", name);
+                assert!(matches!(*source, Error::UnnamedCachedTrack));
+            }
+            _ => panic!("Unnamed tracks shouldn't be cached"),
+        }
+    }
+}
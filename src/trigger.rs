@@ -7,18 +7,23 @@ use crate::song::Song;
 
 pub type Frequency = f64;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Length {
     Samples(usize),
     Beats(f64),
     Seconds(f64),
+    Bars(f64),
 }
 impl Length {
-    /// Returns the length in samples
-    fn get(&self, song: &Song) -> usize {
+    /// Returns the length in samples, if it starts at beat `start`
+    ///
+    /// Beats are counted from `start`, so they follow the tempo changes of the song
+    pub(crate) fn get(&self, song: &Song, start: f64) -> usize {
         match *self {
             Length::Samples(length) => length,
-            Length::Beats(length) => song.beats(length),
+            Length::Beats(length) => song.beats_from(start, length),
             Length::Seconds(length) => song.seconds(length),
+            Self::Bars(length) => song.bars_from(start, length),
         }
     }
 }
@@ -36,9 +41,9 @@ impl Trigger {
         self.freqs.is_empty()
     }
 
-    /// Returns the length in samples
-    fn length(&self, song: &Song) -> usize {
-        self.length.get(song)
+    /// Returns the length in samples, if the trigger starts at beat `start`
+    fn length(&self, song: &Song, start: f64) -> usize {
+        self.length.get(song, start)
     }
 
     pub fn adsr(mut self, adsr: Adsr) -> Self {
@@ -61,6 +66,8 @@ pub trait IntoTrigger {
     fn beats(self, length: f64) -> Trigger;
     fn seconds(self, length: f64) -> Trigger;
     fn samples(self, length: usize) -> Trigger;
+    /// Same as `beats`, but the length is in bars, following the time signature of the song
+    fn bars(self, length: f64) -> Trigger;
 }
 impl IntoTrigger for Vec<Frequency> {
     fn beats(self, length: f64) -> Trigger {
@@ -86,6 +93,14 @@ impl IntoTrigger for Vec<Frequency> {
             adsr: None,
         }
     }
+
+    fn bars(self, length: f64) -> Trigger {
+        Trigger {
+            freqs: self,
+            length: Length::Bars(length),
+            adsr: None,
+        }
+    }
 }
 impl<const N: usize> IntoTrigger for [Frequency; N] {
     fn beats(self, length: f64) -> Trigger {
@@ -111,6 +126,14 @@ impl<const N: usize> IntoTrigger for [Frequency; N] {
             adsr: None,
         }
     }
+
+    fn bars(self, length: f64) -> Trigger {
+        Trigger {
+            freqs: self.into(),
+            length: Length::Bars(length),
+            adsr: None,
+        }
+    }
 }
 impl IntoTrigger for Frequency {
     fn beats(self, length: f64) -> Trigger {
@@ -136,6 +159,14 @@ impl IntoTrigger for Frequency {
             adsr: None,
         }
     }
+
+    fn bars(self, length: f64) -> Trigger {
+        Trigger {
+            freqs: vec![self],
+            length: Length::Bars(length),
+            adsr: None,
+        }
+    }
 }
 impl<N: Into<Note>> IntoTrigger for N {
     fn beats(self, length: f64) -> Trigger {
@@ -155,6 +186,12 @@ impl<N: Into<Note>> IntoTrigger for N {
         let n: Frequency = n.into();
         n.samples(length)
     }
+
+    fn bars(self, length: f64) -> Trigger {
+        let n: Note = self.into();
+        let n: Frequency = n.into();
+        n.bars(length)
+    }
 }
 impl<NOTE: Into<Note> + Clone, const N: usize> IntoTrigger for [NOTE; N] {
     fn beats(self, length: f64) -> Trigger {
@@ -186,6 +223,16 @@ impl<NOTE: Into<Note> + Clone, const N: usize> IntoTrigger for [NOTE; N] {
         }
         array.samples(length)
     }
+
+    fn bars(self, length: f64) -> Trigger {
+        let mut array = [0.; N];
+        for idx in 0..N {
+            let n: Note = self[idx].clone().into();
+            let n: Frequency = n.into();
+            array[idx] = n;
+        }
+        array.bars(length)
+    }
 }
 impl<NOTE: Into<Note>> IntoTrigger for Vec<NOTE> {
     fn beats(self, length: f64) -> Trigger {
@@ -217,6 +264,16 @@ impl<NOTE: Into<Note>> IntoTrigger for Vec<NOTE> {
         }
         array.samples(length)
     }
+
+    fn bars(self, length: f64) -> Trigger {
+        let mut array = Vec::<Frequency>::with_capacity(self.len());
+        for note in self {
+            let n: Note = note.into();
+            let n: Frequency = n.into();
+            array.push(n)
+        }
+        array.bars(length)
+    }
 }
 impl<const M: u8> IntoTrigger for n_tet::NTet<M> {
     fn beats(self, length: f64) -> Trigger {
@@ -233,6 +290,11 @@ impl<const M: u8> IntoTrigger for n_tet::NTet<M> {
         let freq: Frequency = self.into();
         freq.samples(length)
     }
+
+    fn bars(self, length: f64) -> Trigger {
+        let freq: Frequency = self.into();
+        freq.bars(length)
+    }
 }
 impl<const N: usize, const M: u8> IntoTrigger for [n_tet::NTet<M>; N] {
     fn beats(self, length: f64) -> Trigger {
@@ -261,6 +323,15 @@ impl<const N: usize, const M: u8> IntoTrigger for [n_tet::NTet<M>; N] {
         }
         array.samples(length)
     }
+
+    fn bars(self, length: f64) -> Trigger {
+        let mut array = [0.; N];
+        for idx in 0..N {
+            let n: Frequency = self[idx].into();
+            array[idx] = n;
+        }
+        array.bars(length)
+    }
 }
 impl<const M: u8> IntoTrigger for Vec<n_tet::NTet<M>> {
     fn beats(self, length: f64) -> Trigger {
@@ -289,6 +360,15 @@ impl<const M: u8> IntoTrigger for Vec<n_tet::NTet<M>> {
         }
         array.samples(length)
     }
+
+    fn bars(self, length: f64) -> Trigger {
+        let mut array = Vec::<Frequency>::with_capacity(self.len());
+        for note in self {
+            let n: Frequency = note.into();
+            array.push(n)
+        }
+        array.bars(length)
+    }
 }
 
 /// Makes a FrequencyLength with no frequencies
@@ -317,54 +397,95 @@ impl IntoTrigger for Silence {
             adsr: None,
         }
     }
+
+    fn bars(self, length: f64) -> Trigger {
+        Trigger {
+            freqs: vec![],
+            length: Length::Bars(length),
+            adsr: None,
+        }
+    }
+}
+
+/// Converts the triggers into audio, one after the other, with the first one starting at beat `start`
+pub(crate) fn generate_triggers(
+    triggers: &[Trigger],
+    song: &Song,
+    start: f64,
+    fun: &mut dyn FnMut(Frequency, usize) -> Vec<Frame>,
+    default_adsr: Adsr,
+) -> Vec<Frame> {
+    if triggers.is_empty() {
+        return Vec::new();
+    }
+
+    // Get first adsr
+    let adsr = triggers
+        .first()
+        .map(|t| t.adsr.unwrap_or(default_adsr))
+        .unwrap_or(default_adsr);
+
+    // Keep track of the beat where the next trigger starts, so lengths in beats follow the tempo
+    let mut beat = start;
+    let mut vec: Vec<Frame> = vec![Frame::default(); adsr.release + 1];
+    for trig in triggers {
+        let adsr = trig.adsr.unwrap_or(default_adsr);
+
+        let trigger_length = trig.length(song, beat);
+        beat = match trig.length {
+            Length::Beats(length) => beat + length,
+            _ => song.beat_at(song.beats(beat) + trigger_length),
+        };
+        let length = trigger_length + adsr.release;
+
+        if trig.is_empty() {
+            vec = vec.overlap(silence().take_samples(length), adsr.release);
+        } else {
+            vec = vec.overlap(
+                join_tracks(trig.freqs.iter().map(|note| fun(*note, length)).collect()),
+                adsr.release,
+            );
+        }
+    }
+    vec
 }
 
 pub trait TriggerListExtension {
     /// Converts a list of Triggers into audio
     /// Uses the given Adsr unless a trigger has a custom one, in which case it'll use that one
+    ///
+    /// The first trigger is assumed to start at the beginning of the song, use `generate_from`
+    /// if it's placed later, so lengths in beats and bars follow the tempo at that point
     fn generate(
         &self,
         song: &Song,
         fun: &mut dyn FnMut(Frequency, usize) -> Vec<Frame>,
         adsr: Adsr,
+    ) -> Vec<Frame> {
+        self.generate_from(song, 0., fun, adsr)
+    }
+
+    /// Same as `generate`, with the first trigger starting at beat `start` of the song
+    fn generate_from(
+        &self,
+        song: &Song,
+        start: f64,
+        fun: &mut dyn FnMut(Frequency, usize) -> Vec<Frame>,
+        adsr: Adsr,
     ) -> Vec<Frame>;
     fn map_frequencies<F>(self, fun: F) -> Self
     where
         F: Clone + FnMut(&Frequency) -> Frequency;
 }
 impl<const N: usize> TriggerListExtension for [Trigger; N] {
-    fn generate(
+    fn generate_from(
         &self,
         song: &Song,
+        start: f64,
         fun: &mut dyn FnMut(Frequency, usize) -> Vec<Frame>,
         default_adsr: Adsr,
     ) -> Vec<Frame> {
-        if self.is_empty() {
-            return Vec::new();
-        }
-
-        // Get first adsr
-        let adsr = self
-            .get(0)
-            .map(|t| t.adsr.unwrap_or(default_adsr))
-            .unwrap_or(default_adsr);
-
-        let mut vec: Vec<Frame> = vec![Frame::default(); adsr.release + 1];
-        for trig in self {
-            let adsr = trig.adsr.unwrap_or(default_adsr);
-
-            let length = trig.length(song) + adsr.release;
-
-            if trig.is_empty() {
-                vec = vec.overlap(silence().take_samples(length), adsr.release);
-            } else {
-                vec = vec.overlap(
-                    join_tracks(trig.freqs.iter().map(|note| fun(*note, length)).collect()),
-                    adsr.release,
-                );
-            }
-        }
-        vec
+        generate_triggers(self, song, start, fun, default_adsr)
     }
 
     fn map_frequencies<F>(mut self, fun: F) -> Self
@@ -378,38 +499,14 @@ impl<const N: usize> TriggerListExtension for [Trigger; N] {
     }
 }
 impl TriggerListExtension for Vec<Trigger> {
-    fn generate(
+    fn generate_from(
         &self,
         song: &Song,
+        start: f64,
         fun: &mut dyn FnMut(Frequency, usize) -> Vec<Frame>,
         default_adsr: Adsr,
     ) -> Vec<Frame> {
-        if self.is_empty() {
-            return Vec::new();
-        }
-
-        // Get first adsr
-        let adsr = self
-            .get(0)
-            .map(|t| t.adsr.unwrap_or(default_adsr))
-            .unwrap_or(default_adsr);
-
-        let mut vec: Vec<Frame> = vec![Frame::default(); adsr.release + 1];
-        for trig in self {
-            let adsr = trig.adsr.unwrap_or(default_adsr);
-
-            let length = trig.length(song) + adsr.release;
-
-            if trig.is_empty() {
-                vec = vec.overlap(silence().take_samples(length), adsr.release);
-            } else {
-                vec = vec.overlap(
-                    join_tracks(trig.freqs.iter().map(|note| fun(*note, length)).collect()),
-                    adsr.release,
-                );
-            }
-        }
-        vec
+        generate_triggers(self, song, start, fun, default_adsr)
     }
 
     fn map_frequencies<F>(mut self, fun: F) -> Self
@@ -1,6 +1,7 @@
 //! Methods to interact with VST2 plugins
 
 use crate::effects::*;
+use crate::error::{Error, Result};
 use crate::frame::*;
 use crate::helpers::*;
 use std::path::Path;
@@ -20,8 +21,17 @@ pub fn load_plugin<P: AsRef<Path>>(
     path: P,
     host: &Arc<Mutex<SimpleHost>>,
 ) -> PluginLoader<SimpleHost> {
-    PluginLoader::load(path.as_ref(), Arc::clone(host))
-        .unwrap_or_else(|e| panic!("Failed to load plugin: {}", e))
+    try_load_plugin(path, host).unwrap_or_else(|e| panic!("{}", e))
+}
+
+pub fn try_load_plugin<P: AsRef<Path>>(
+    path: P,
+    host: &Arc<Mutex<SimpleHost>>,
+) -> Result<PluginLoader<SimpleHost>> {
+    PluginLoader::load(path.as_ref(), Arc::clone(host)).map_err(|source| Error::Plugin {
+        path: path.as_ref().to_path_buf(),
+        source,
+    })
 }
 
 pub struct VstPlugin {
@@ -38,6 +48,24 @@ impl VstPlugin {
         }
     }
 
+    /// Same as `new`, but returns an error instead of panicking if the instance can't be created
+    pub fn try_new(
+        loader: &mut PluginLoader<SimpleHost>,
+        path: &Path,
+        sample_rate: u32,
+    ) -> Result<Self> {
+        let mut instance = loader.instance().map_err(|source| Error::Plugin {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        instance.init();
+        instance.set_sample_rate(sample_rate as f32);
+
+        Ok(Self {
+            plugin: Mutex::from(instance),
+        })
+    }
+
     /// Get `input.len()` samples out of `instance`
     /// Uses the default number of channels provided by the plugin
     ///
@@ -155,6 +183,21 @@ impl Effect for VstPlugin {
     fn run(&self, input: Vec<Frame>) -> Vec<Frame> {
         self.process_samples(input)
     }
+
+    /// Plugins keep their own state, so we can just pass them each block
+    fn processor(&self) -> Option<Box<dyn BlockProcessor + '_>> {
+        Some(Box::new(VstProcessor(self)))
+    }
+}
+
+struct VstProcessor<'a>(&'a VstPlugin);
+impl BlockProcessor for VstProcessor<'_> {
+    fn process(&mut self, block: &mut [Frame]) {
+        let output = self.0.process_samples(block.to_vec());
+        for (frame, processed) in block.iter_mut().zip(output) {
+            *frame = processed;
+        }
+    }
 }
 
 #[cfg(test)]